@@ -0,0 +1,154 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+
+use super::calendar::AcademicCalendar;
+use super::structures::*;
+
+/// Days looked ahead for the next class, long enough to cross any holiday.
+const NEXT_CLASS_HORIZON_DAYS: i64 = 30;
+
+/// The classes of `weekday` in `week`, or in any week if not given, ordered
+/// by their start. The cells of one class over consecutive periods are
+/// merged into a single class.
+pub fn weekday_classes(
+    table: &[CourseTableItem],
+    weekday: u32,
+    week: Option<u32>,
+    bell_schedule: &[(NaiveTime, NaiveTime)],
+) -> Vec<ScheduledClass> {
+    let mut cells: Vec<(u32, &CourseTableEntry)> = table.iter()
+                                                    .filter(|item| item.day == weekday)
+                                                    .flat_map(|item| item.entries.iter().map(move |entry| (item.time, entry)))
+                                                    .filter(|(_, entry)| match week {
+                                                        Some(week) => entry.weeks.is_empty() || entry.weeks.contains(week),
+                                                        None => true
+                                                    })
+                                                    .collect();
+    cells.sort_by(|(a_period, a), (b_period, b)| {
+        (&a.course_name, &a.course_class, &a.room, a_period).cmp(&(&b.course_name, &b.course_class, &b.room, b_period))
+    });
+
+    let mut classes = Vec::<ScheduledClass>::new();
+    for (period, entry) in cells {
+        if let Some(last) = classes.last_mut() {
+            let same_class = last.course_name == entry.course_name
+                                && last.course_class == entry.course_class
+                                && last.room == entry.room;
+            if same_class && last.end_period + 1 >= period {
+                last.end_period = last.end_period.max(period);
+                continue;
+            }
+        }
+        classes.push(ScheduledClass {
+            date: None,
+            course_name: entry.course_name.clone(),
+            course_class: entry.course_class.clone(),
+            teachers: entry.teachers.clone(),
+            building: entry.building.clone(),
+            room: entry.room.clone(),
+            start_period: period,
+            end_period: period,
+            start_time: None,
+            end_time: None,
+        });
+    }
+
+    let time = |period: u32| (period as usize).checked_sub(1).and_then(|i| bell_schedule.get(i));
+    for class in classes.iter_mut() {
+        class.start_time = time(class.start_period).map(|(start, _)| start.format("%H:%M").to_string());
+        class.end_time = time(class.end_period).map(|(_, end)| end.format("%H:%M").to_string());
+    }
+    classes.sort_by_key(|class| class.start_period);
+    classes
+}
+
+/// The classes held on `date`, following its academic calendar.
+pub fn classes_on(
+    table: &[CourseTableItem],
+    calendar: &AcademicCalendar,
+    date: NaiveDate,
+    bell_schedule: &[(NaiveTime, NaiveTime)],
+) -> Vec<ScheduledClass> {
+    let schedule = match calendar.day(date).schedule {
+        Some(schedule) => schedule,
+        None => return Vec::new()
+    };
+    let mut classes = weekday_classes(table, schedule.weekday, Some(schedule.week), bell_schedule);
+    for class in classes.iter_mut() {
+        class.date = Some(date.to_string());
+    }
+    classes
+}
+
+/// The first class starting after `now`, with the seconds until it starts.
+pub fn next_class(
+    table: &[CourseTableItem],
+    calendar: &AcademicCalendar,
+    now: NaiveDateTime,
+    bell_schedule: &[(NaiveTime, NaiveTime)],
+) -> Option<NextClass> {
+    for days in 0..NEXT_CLASS_HORIZON_DAYS {
+        let date = now.date() + Duration::days(days);
+        for class in classes_on(table, calendar, date, bell_schedule) {
+            let start = class.start_time
+                            .as_ref()
+                            .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok())
+                            .map(|time| date.and_time(time));
+            if let Some(start) = start.filter(|start| *start > now) {
+                return Some(NextClass {
+                    starts_in: (start - now).num_seconds(),
+                    class,
+                });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::super::calendar::{AcademicCalendar, Holiday};
+    use super::super::ical::TimetableConfig;
+    use super::super::structures::CourseTableItem;
+    use super::super::test_util::table_item;
+    use super::{classes_on, next_class};
+
+    fn table() -> Vec<CourseTableItem> {
+        vec![
+            table_item(1, 4, "线性代数[02班]\n1-16周\n一教201"),
+            table_item(1, 1, "高等数学(上)[01班]\n张三\n1-16周\n一教101"),
+            table_item(1, 2, "高等数学(上)[01班]\n张三\n1-16周\n一教101"),
+            table_item(1, 3, "大学物理实验[3班]\n2-16周(双)\n实验楼305"),
+            table_item(3, 5, "体育[5班]\n1-16周\n体育馆"),
+        ]
+    }
+
+    #[test]
+    fn test_classes_on() {
+        let bell_schedule = TimetableConfig::default().bell_schedule().unwrap();
+        let date = |month, day| NaiveDate::from_ymd_opt(2021, month, day).unwrap();
+        let mut calendar = AcademicCalendar::plain("2021-2022", "1", date(9, 6));
+        let classes = classes_on(&table(), &calendar, date(9, 6), &bell_schedule);
+        let names: Vec<&str> = classes.iter().map(|c| c.course_name.as_str()).collect();
+        assert_eq!(names, vec!["高等数学(上)", "线性代数"]);
+        assert_eq!((classes[0].start_period, classes[0].end_period), (1, 2));
+        assert_eq!((classes[0].start_time.as_deref(), classes[0].end_time.as_deref()), (Some("08:00"), Some("09:50")));
+        assert_eq!(classes_on(&table(), &calendar, date(9, 13), &bell_schedule).len(), 3);
+
+        calendar.holidays.push(Holiday { name: "中秋节".to_owned(), start: date(9, 20), end: None });
+        assert!(classes_on(&table(), &calendar, date(9, 20), &bell_schedule).is_empty());
+    }
+
+    #[test]
+    fn test_next_class() {
+        let bell_schedule = TimetableConfig::default().bell_schedule().unwrap();
+        let date = |month, day| NaiveDate::from_ymd_opt(2021, month, day).unwrap();
+        let calendar = AcademicCalendar::plain("2021-2022", "1", date(9, 6));
+        let next = next_class(&table(), &calendar, date(9, 6).and_hms_opt(9, 0, 0).unwrap(), &bell_schedule).unwrap();
+        assert_eq!(next.class.course_name, "线性代数");
+        assert_eq!(next.starts_in, 2 * 3600 + 20 * 60);
+        let next = next_class(&table(), &calendar, date(9, 6).and_hms_opt(12, 0, 0).unwrap(), &bell_schedule).unwrap();
+        assert_eq!((next.class.course_name.as_str(), next.class.date.as_deref()), ("体育", Some("2021-09-08")));
+    }
+}
@@ -1,13 +1,39 @@
 use std::collections::HashMap;
 use std::path::Path;
 
+use chrono::{NaiveDate, NaiveTime};
 use futures::lock::Mutex;
 // use log::info;
 use rocket::fs::NamedFile;
-use rocket::{State, response::status::Unauthorized, serde::json};
+use rocket::http::ContentType;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Shutdown, State, response::status::Unauthorized, serde::json};
 use super::structures::*;
 use super::urls::*;
 use super::login::*;
+use super::selection::*;
+use super::courses::*;
+use super::watcher::Watcher;
+use super::waitlist::Waitlists;
+use super::scheduler::{job_session, Scheduler};
+use super::bidding::Snapshots;
+use super::calendar::{local_now, AcademicCalendar, Calendars};
+use super::agenda;
+use super::webcal::{IfNoneMatch, Subscriptions, WebCalendar};
+use super::ical::{timetable_calendar, TimetableConfig};
+use super::render::{render_timetable, RenderConfig, Theme};
+use super::planner::{self, PlanConstraints, PlannerConfig};
+use super::catalogue::{Catalogue, CatalogueQuery};
+use super::outlines::Outlines;
+use super::markup::MarkupFormat;
+use super::parser::parse_course_outline;
+use super::prerequisites::{passed_courses, PrerequisiteGraph};
+use super::training::{audit, TrainingPlans};
+use super::conversion::{self, ConversionConfig, ScaleTable};
+use super::grades::{self, mark_attempts, GradesConfig, RetakePolicy};
+use super::transcript::{build_transcript, transcript_csv, TranscriptConfig};
+use super::roadmap::{choose_alternative, next_term, parse_term, plan_semesters, terms_from, RemainingCourse, RoadmapConfig, Term};
 
 #[rocket::get("/")]
 pub async fn index() -> NamedFile {
@@ -44,23 +70,7 @@ pub async fn basic_info(
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
     
-    let v = client.post(BASIC_INFO_URL)
-                        .send()
-                        .await
-                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                        .json::<serde_json::Value>()
-                        .await
-                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
-    
-    let basic_info = BasicInfo {
-        id: v["ID"].as_str().unwrap_or_default().to_owned(),
-        sid: v["XH"].as_str().unwrap_or_default().to_owned(),
-        name: v["XM"].as_str().unwrap_or_default().to_owned(),
-        email: v["DZYX"].as_str().unwrap_or_default().to_owned(),
-        year: v["NJMC"].as_str().unwrap_or_default().to_owned(),
-        department: v["YXMC"].as_str().unwrap_or_default().to_owned(),
-        major: v["ZYMC"].as_str().unwrap_or_default().to_owned()
-    };
+    let basic_info = fetch_basic_info(client).await?;
 
     Ok(json::Json(basic_info))
 }
@@ -79,32 +89,8 @@ pub async fn semester_gpa(
 
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
-    
-    let v = client.post(SEMESTER_GPA_URL)
-                        .send()
-                        .await
-                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                        .json::<serde_json::Value>()
-                        .await
-                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
-    
-    let gpa_value_array = v["xnanxqxfj"].as_array().unwrap();
-    let mut gpa_vec = Vec::<SemesterGPA>::new();
-    for gpa in gpa_value_array {
-        let semester_gpa = SemesterGPA {
-            semester_full_name: gpa["XNXQ"].as_str().unwrap_or_default().to_owned(),
-            semester_year: gpa["XN"].as_str().unwrap_or_default().to_owned(),
-            semester_number: gpa["XQ"].as_str().unwrap_or_default().to_owned(),
-            gpa: gpa["XQXFJ"].as_f64()
-        };
-        gpa_vec.push(semester_gpa);
-    }
 
-    let student_gpa = StudentGPA {
-        all_gpa: gpa_vec,
-        average_gpa: v["xfjandpm"]["PJXFJ"].as_f64().unwrap_or_default(),
-        rank: v["xfjandpm"]["PM"].as_str().unwrap_or_default().to_owned()
-    };
+    let student_gpa = fetch_student_gpa(client).await?;
     Ok(json::Json(student_gpa))
 }
 
@@ -112,7 +98,8 @@ pub async fn semester_gpa(
 pub async fn courses_grades(
     username: &str, 
     password: &str,
-    client_storage: &State<Mutex<HashMap<String, Account>>>
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    config: &State<GradesConfig>,
 ) -> Result<json::Json<Vec<CourseGrade>>, Unauthorized<String>> {
 
     // info!("courses_grades {}", username);
@@ -123,35 +110,8 @@ pub async fn courses_grades(
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
 
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert("Content-Type", reqwest::header::HeaderValue::from_static("application/json"));
-    let body = r#"{"xn":null,"xq":null,"kcmc":null,"cxbj":"-1","pylx":"1","current":1,"pageSize":100}"#;
-    let v: serde_json::Value = client.post(COURSE_GRADES_URL)
-                                    .headers(headers)
-                                    .body(body)
-                                    .send()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                                    .json::<serde_json::Value>()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
-    
-    let course_grades_value_vec = v["content"]["list"].as_array().unwrap();
-    let mut course_grades_vec = Vec::<CourseGrade>::new();
-    for course_grade_value in course_grades_value_vec {
-        let course_grade = CourseGrade {
-            code: course_grade_value["kcdm"].as_str().unwrap_or_default().to_owned(),
-            name: course_grade_value["kcmc"].as_str().unwrap_or_default().to_owned(),
-            class_hour: course_grade_value["xs"].as_str().unwrap_or_default().to_owned(),
-            credit: course_grade_value["xf"].as_u64().unwrap_or_default().to_owned(),
-            semester: course_grade_value["xnxqmc"].as_str().unwrap_or_default().to_owned(),
-            final_grade: course_grade_value["zzcj"].as_str().unwrap_or_default().to_owned(),
-            final_level: course_grade_value["xscj"].as_str().unwrap_or_default().to_owned(),
-            department: course_grade_value["yxmc"].as_str().unwrap_or_default().to_owned(),
-            course_type: course_grade_value["kclb"].as_str().unwrap_or_default().to_owned(),
-        };
-        course_grades_vec.push(course_grade);
-    }
+    let mut course_grades_vec = fetch_course_grades(client).await?;
+    mark_attempts(&mut course_grades_vec, config.retake_policy);
 
     #[cfg(debug_assertions)]
     println!("Total {} course grades item", course_grades_vec.len());
@@ -160,118 +120,37 @@ pub async fn courses_grades(
 
 #[rocket::get("/courses")]
 pub async fn get_courses(
-
+    catalogue: &State<Catalogue>,
 ) -> Result<json::Json<Vec<Course>>, Unauthorized<String>> {    
-    let courses_html = reqwest::get(COURSES_URL)
-                                        .await
-                                        .map_err(|_| Unauthorized(Some("Unable to get courses from the web".to_owned())))?
-                                        .text()
-                                        .await
-                                        .map_err(|_| Unauthorized(Some("Unable to get courses from the web".to_owned())))?;
-
-    let cas_fragment = scraper::Html::parse_fragment(&courses_html[..]);
-    let table_selector = scraper::Selector::parse("table")
-                                                    .map_err(|_| Unauthorized(Some(String::from("Unable to parse HTML to fragment"))))?;
-                    
-    let mut table_iter = cas_fragment.select(&table_selector);
-    let _head_table = table_iter.next().unwrap();
-
-    let tr_selector = scraper::Selector::parse("tr")
-                                                .map_err(|_| Unauthorized(Some("Unable to parse the option selector".to_owned())))?;
-    let td_selector = scraper::Selector::parse("td")
-                                                .map_err(|_| Unauthorized(Some("Unable to parse the option selector".to_owned())))?;
-    let a_selector = scraper::Selector::parse("a")
-                                                .map_err(|_| Unauthorized(Some("Unable to parse the option selector".to_owned())))?;
-    let mut courses_vec = Vec::<Course>::new();
-    for table in table_iter {
-        let mut tr_iter = table.select(&tr_selector);
-        tr_iter.next();
-        for tr in tr_iter {
-            let mut td_iter = tr.select(&td_selector);
-            let course = Course {
-                course_id: td_iter.next().unwrap().select(&a_selector).next().unwrap().inner_html(),
-                course_name: td_iter.next().unwrap().select(&a_selector).next().unwrap().inner_html(),
-                credits: td_iter.next().unwrap().inner_html().parse::<f32>().unwrap(),
-                department: td_iter.last().unwrap().inner_html()
-            };
-            courses_vec.push(course);
-        }
-    }
-    Ok(json::Json(courses_vec))
+    Ok(json::Json(catalogue.courses().await?))
 }
 
-async fn parse_course_info(
-    course_info_html: &str
-) -> (Vec<String>, Vec<String>, Option<Vec<String>>, Option<Vec<String>>) {
-    let mut major_teacher = Vec::<String>::new();
-    let mut major_time_and_place = Vec::<String>::new();
-    let minor_teacher: Option<Vec<String>>;
-    let minor_time_and_place: Option<Vec<String>>;
-    
-    let course_info_fragment = scraper::Html::parse_fragment(course_info_html);
-    let div_selector = scraper::Selector::parse("div").unwrap();
-    let p_selector = scraper::Selector::parse("p").unwrap();
-    let a_selector = scraper::Selector::parse("a").unwrap();
-    let mut p_iter = course_info_fragment.select(&p_selector);
-    let mut div_iter = course_info_fragment.select(&div_selector);
-
-    if course_info_fragment.select(&div_selector).count() == 2 {   
-        let a_iter = p_iter.next()
-                                .unwrap()
-                                .select(&a_selector);
-        for a in a_iter {
-            major_teacher.push(a.inner_html());
-        }
-        let div_p_iter = div_iter.next()
-                                .unwrap()
-                                .select(&p_selector);
-        for p in div_p_iter {
-            major_time_and_place.push(p.inner_html());
-        }
-        minor_teacher = None;
-        minor_time_and_place = None;
-    } else {
-        #[cfg(debug_assertions)] {
-            let mut x = 0;
-            for p in p_iter {
-                x += 1;
-                println!("{}: {}", x, p.inner_html());
-            }
-        }
-        let mut p_iter = course_info_fragment.select(&p_selector);
-        let a_iter = p_iter.nth(1)
-                                .unwrap()
-                                .select(&a_selector);
-        for a in a_iter {
-            major_teacher.push(a.inner_html());
-        }
-        let div_p_iter = div_iter.next()
-                                .unwrap()
-                                .select(&p_selector);
-        for p in div_p_iter {
-            major_time_and_place.push(p.inner_html());
-        }
-        // println!("{:?}", p_iter.nth(8).unwrap().inner_html());
-        let mut minor_teacher_vec = Vec::<String>::new();
-        let a_iter = p_iter.nth(7)
-                                .unwrap()
-                                .select(&a_selector);
-        for a in a_iter {
-            minor_teacher_vec.push(a.inner_html());
-        }
-        minor_teacher = Some(minor_teacher_vec);
-
-        let div_p_iter = div_iter.nth(1)
-                            .unwrap()
-                            .select(&p_selector);
-        let mut minor_time_and_place_vec = Vec::<String>::new();
-        for p in div_p_iter {
-            minor_time_and_place_vec.push(p.inner_html());
-        }
-        minor_time_and_place = Some(minor_time_and_place_vec);
-    }
+#[rocket::get("/search_courses?<q>&<department>&<min_credits>&<max_credits>&<prefix>&<limit>")]
+pub async fn search_courses(
+    q: Option<&str>,
+    department: Option<&str>,
+    min_credits: Option<f32>,
+    max_credits: Option<f32>,
+    prefix: Option<&str>,
+    limit: Option<usize>,
+    catalogue: &State<Catalogue>,
+) -> Result<json::Json<CatalogueSearch>, Unauthorized<String>> {
+    let query = CatalogueQuery {
+        text: q,
+        department,
+        min_credits,
+        max_credits,
+        prefix,
+    };
+    Ok(json::Json(catalogue.search(&query, limit.unwrap_or(50)).await?))
+}
 
-    return (major_teacher, major_time_and_place, minor_teacher, minor_time_and_place);
+#[rocket::get("/course_detail?<course_id>")]
+pub async fn course_detail(
+    course_id: &str,
+    catalogue: &State<Catalogue>,
+) -> Result<json::Json<CatalogCourse>, Unauthorized<String>> {
+    Ok(json::Json(catalogue.course(course_id).await?))
 }
 
 #[rocket::get("/selected_courses?<username>&<password>&<semester_year>&<semester_no>")]
@@ -291,76 +170,21 @@ pub async fn selected_courses(
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
 
-    let mut post_form = std::collections::HashMap::<&str, &str>::new();
-    post_form.insert("p_pylx", "1");
-    post_form.insert("p_xkfsdm", "yixuan");
-    post_form.insert("p_xn", semester_year);
-    post_form.insert("p_xq", semester_no);
-    // post_form.insert("p_dqxn", semester_year);
-    // post_form.insert("p_dqxq", semester_no);
-    let v = client.post(SELECTED_COURSES_URL)
-                        .form(&post_form)
-                        .send()
-                        .await
-                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                        .json::<serde_json::Value>()
-                        .await
-                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
-    // println!("{:?}", v);
-    let selected_courses_value = v["yxkcList"].as_array().unwrap();
-    let mut selected_courses_vec = Vec::<SelectedCourse>::new();
-
-    for value in selected_courses_value {
-        let (major_teacher, 
-            major_time_and_place, 
-            minor_teacher, 
-            minor_time_and_place) = parse_course_info(value["kcxx"].as_str().unwrap()).await;
-        let course = SelectedCourse {
-            advanced_course: AdvancedCourse {
-                basic_course: Course {
-                    course_id: value["kcdm"].as_str().unwrap().to_owned(),
-                    course_name: value["kcmc"].as_str().unwrap().to_owned(),
-                    credits: value["xf"].as_str().unwrap().parse::<f32>().map_err(|_| Unauthorized(Some(String::from("Unable to parse HTML to fragment"))))?,
-                    department: value["kkyxmc"].as_str().unwrap().to_owned()
-                },
-                course_class: value["rwmc"].as_str().unwrap().to_owned(),
-                course_type: value["kclbmc"].as_str().unwrap().to_owned(),
-                id: value["id"].as_str().unwrap().to_owned(),
-                undergraduated_available: value["bksrl"].as_str().unwrap().parse::<u32>().unwrap(),
-                undergraduated_selected: value["bksyxrlrs"].as_str().unwrap().parse::<u32>().unwrap(),
-                graduated_available: value["yjsrl"].as_str().unwrap().parse::<u32>().unwrap(),
-                graduated_selected: value["yjsyxrlrs"].as_str().unwrap().parse::<u32>().unwrap(),
-                major_teacher,
-                major_time_and_place,
-                minor_teacher,
-                minor_time_and_place,
-            },
-            available: match value["sxbj"].as_str().unwrap() {
-                "0" => { false },
-                "1" => { true },
-                _ => {false}
-            },
-            points: {
-                if value["xkxs"].is_null() {
-                    None
-                } else {
-                    Some(value["xkxs"].as_str().unwrap().parse::<u32>().unwrap())
-                }
-            }
-        };
-        selected_courses_vec.push(course);
-    }
+    let selected_courses_vec = fetch_selected_courses(client, semester_year, semester_no).await?;
     Ok(json::Json(selected_courses_vec))
 }
 
-#[rocket::get("/available_courses?<username>&<password>&<semester_year>&<semester_no>&<courses_type>")]
+#[rocket::get("/available_courses?<username>&<password>&<semester_year>&<semester_no>&<courses_type>&<conflicts>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn available_courses(
     username: &str, 
     password: &str, 
     semester_year: &str, 
     semester_no: &str, 
     courses_type: &str,
-    client_storage: &State<Mutex<HashMap<String, Account>>>
+    conflicts: Option<bool>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    snapshots: &State<Snapshots>
 ) -> Result<json::Json<Vec<AvailableCourse>>, Unauthorized<String>> {
 
     // info!("available_courses {}", username);
@@ -371,64 +195,14 @@ pub async fn available_courses(
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
 
-    let mut post_form = std::collections::HashMap::<&str, &str>::new();
-    let code_p_xkfsdm = match courses_type {
-        "GR" => "bxxk", //  General Required
-        "GE" => "xxxk", //  General Elective
-        "TP" => "kzyxk", //  Courses within the training program
-        "NTP" => "zynknjxk", //  Courses without the training program
-        _ => ""
-    };
-
-    post_form.insert("p_pylx", "1");
-    post_form.insert("p_xkfsdm", code_p_xkfsdm);
-    post_form.insert("p_xn", semester_year);
-    post_form.insert("p_xq", semester_no);
-    let v: serde_json::Value = client.post(AVAILABLE_COURSES_URL)
-                                    .form(&post_form)
-                                    .send()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                                    .json::<serde_json::Value>()
-                                    .await.map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
-
-    let available_courses_value = v["kxrwList"]["list"].as_array().unwrap();
-    
-    let mut available_courses_vec = Vec::<AvailableCourse>::new();
-
-    for value in available_courses_value {
-        let (major_teacher, 
-            major_time_and_place, 
-            minor_teacher, 
-            minor_time_and_place) = parse_course_info(value["kcxx"].as_str().unwrap()).await;
-
-        let course = AvailableCourse {
-            advanced_course: AdvancedCourse {
-                basic_course: Course {
-                    course_id: value["kcdm"].as_str().unwrap().to_owned(),
-                    course_name: value["kcmc"].as_str().unwrap().to_owned(),
-                    credits: value["xf"].as_str().unwrap().parse::<f32>().map_err(|_| Unauthorized(Some(String::from("Unable to parse HTML to fragment"))))?,
-                    department: value["kkyxmc"].as_str().unwrap().to_owned(),
-                },
-                course_class: value["rwmc"].as_str().unwrap().to_owned(),
-                course_type: value["kclbmc"].as_str().unwrap().to_owned(),
-                id: value["id"].as_str().unwrap().to_owned(),
-                undergraduated_available: value["bksrl"].as_str().unwrap().parse::<u32>().unwrap(),
-                undergraduated_selected: value["bksyxrlrs"].as_str().unwrap().parse::<u32>().unwrap(),
-                graduated_available: value["yjsrl"].as_str().unwrap().parse::<u32>().unwrap(),
-                graduated_selected: value["yjsyxrlrs"].as_str().unwrap().parse::<u32>().unwrap(),
-                major_teacher,
-                major_time_and_place,
-                minor_teacher,
-                minor_time_and_place,
-            },
-            outline_id: value["kcid"].as_str().unwrap().to_owned(),
-            conflict_courses: {
-                if value["ctkcxx"].is_null() { None }
-                else { Some(value["ctkcxx"].as_str().unwrap().to_owned()) }
-            }
-        };
-        available_courses_vec.push(course);
+    let mut available_courses_vec = fetch_available_courses(client, semester_year, semester_no, courses_type).await?;
+    snapshots.record(semester_year, semester_no, &available_courses_vec).await;
+    // Linking the conflicts costs one more request to TIS, so only when asked.
+    // They are still listed when the selected courses cannot be fetched.
+    if conflicts.unwrap_or(false) {
+        if let Ok(selected_courses_vec) = fetch_selected_courses(client, semester_year, semester_no).await {
+            link_conflicts(&mut available_courses_vec, &selected_courses_vec);
+        }
     }
     Ok(json::Json(available_courses_vec))
 }
@@ -453,35 +227,35 @@ pub async fn select_course(
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
 
-    let code_p_xkfsdm = match course_type {
-        "GR" => "bxxk", //  General Required
-        "GE" => "xxxk", //  General Elective
-        "TP" => "kzyxk", //  Courses within the training program
-        "NTP" => "zynknjxk", //  Courses without the training program
-        _ => ""
-    };
-    let mut post_form = std::collections::HashMap::<&str, &str>::new();
-    post_form.insert("p_xn", semester_year);
-    post_form.insert("p_xq", semester_no);
-    post_form.insert("p_id", course_id);
-    post_form.insert("p_xkxs", points);
-    post_form.insert("p_pylx", "1");
-    post_form.insert("p_xkfsdm", code_p_xkfsdm);
-    post_form.insert("p_xktjz", "rwtjzyx");
-
-    let v: serde_json::Value = client.post(SELECT_COURSE_URL)
-                                    .form(&post_form)
-                                    .send()
-                                    .await.map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                                    .json::<serde_json::Value>()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
+    let v = submit_select_course(client, semester_year, semester_no, course_id, course_type, points).await?;
 
     #[cfg(debug_assertions)]
     println!("{}", serde_json::to_string_pretty(&v).map_err(|_| Unauthorized(Some("Unable to parse the result to JSON".to_owned())))?);
     Ok(json::Json(v))
 }
 
+#[rocket::post("/batch_select_courses?<username>&<password>&<semester_year>&<semester_no>", data = "<request>")]
+pub async fn batch_select_courses(
+    username: &str, 
+    password: &str, 
+    semester_year: &str, 
+    semester_no: &str, 
+    request: json::Json<BatchSelectionRequest>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>
+) -> Result<json::Json<BatchSelectionResult>, Unauthorized<String>> {
+
+    validate_batch(&request).map_err(|e| Unauthorized(Some(e)))?;
+
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client_storage = client_storage.lock().await;
+    let client = &client_storage.get(username).unwrap().client;
+
+    let result = batch_select(client, semester_year, semester_no, &request).await?;
+    Ok(json::Json(result))
+}
+
 #[rocket::get("/drop_course?<username>&<password>&<semester_year>&<semester_no>&<course_id>")]
 pub async fn drop_course(
     username: &str, 
@@ -500,21 +274,7 @@ pub async fn drop_course(
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
 
-    let mut post_form = std::collections::HashMap::<&str, &str>::new();
-    post_form.insert("p_xn", semester_year);
-    post_form.insert("p_xq", semester_no);
-    post_form.insert("p_id", course_id);
-    post_form.insert("p_pylx", "1");
-    post_form.insert("p_xkfsdm", "yixuan");
-
-    let v: serde_json::Value = client.post(DROP_COURSE_URL)
-                                    .form(&post_form)
-                                    .send()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                                    .json::<serde_json::Value>()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
+    let v = submit_drop_course(client, semester_year, semester_no, course_id).await?;
 
     #[cfg(debug_assertions)]
     println!("{}", serde_json::to_string_pretty(&v).map_err(|_| Unauthorized(Some("Unable to parse the result to JSON".to_owned())))?);
@@ -562,34 +322,30 @@ pub async fn update_points(
     Ok(json::Json(v))
 }
 
-#[rocket::get("/course_outline?<username>&<password>&<outline_id>")]
+#[rocket::get("/course_outline?<username>&<password>&<outline_id>&<format>")]
 pub async fn course_outline(
     username: &str,
     password: &str,
     outline_id: &str,
-    client_storage: &State<Mutex<HashMap<String, Account>>>
-) -> Result<json::Json<serde_json::Value>, Unauthorized<String>> {
+    format: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    outlines: &State<Outlines>,
+) -> Result<json::Json<CourseOutline>, Unauthorized<String>> {
 
     // info!("course_outline {}", username);
 
+    let format = MarkupFormat::parse(format.unwrap_or("html"))
+                    .ok_or_else(|| Unauthorized(Some("The format should be html, markdown or text".to_owned())))?;
+
     let tis_login_result = tis_login(username, password, client_storage).await?;
     if !tis_login_result { return Err(Unauthorized(None)); }
 
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
 
-    let mut post_form = std::collections::HashMap::<&str, &str>::new();
-    post_form.insert("kcid", outline_id);
-
-    let v: serde_json::Value = client.post(OUTLINE_URL)
-                                    .form(&post_form)
-                                    .send()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                                    .json::<serde_json::Value>()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
-    Ok(json::Json(v["content"]["kcdgbentity"]["kczwjj"].to_owned()))
+    let entity = fetch_outline(client, outline_id).await?;
+    outlines.add(outline_id, None, None, &entity).await;
+    Ok(json::Json(parse_course_outline(outline_id, &entity, format)))
 }
 
 #[rocket::get("/current_semester?<username>&<password>")]
@@ -605,24 +361,7 @@ pub async fn current_semester(
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
 
-    let mut post_form = std::collections::HashMap::<&str, &str>::new();
-    post_form.insert("p_pylx", "1");
-    post_form.insert("mxpylx", "1");
-
-    let v: serde_json::Value = client.post(CURRENT_SEMESTER_URL)
-                                    .form(&post_form)
-                                    .send()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                                    .json::<serde_json::Value>()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
-    #[cfg(debug_assertions)]
-    println!("{:?}", v);
-    let current_semester = CurrentSemester {
-        semester_year: v["p_xn"].as_str().unwrap().to_owned(),
-        semester_no: v["p_xq"].as_str().unwrap().to_owned(),
-    };
+    let current_semester = fetch_current_semester(client).await?;
 
     Ok(json::Json(current_semester))
 }
@@ -641,40 +380,862 @@ pub async fn course_table(
     let client_storage = client_storage.lock().await;
     let client = &client_storage.get(username).unwrap().client;
 
-    let mut post_form = std::collections::HashMap::<&str, &str>::new();
-    post_form.insert("bs", "2");
-    post_form.insert("xn", semester_year);
-    post_form.insert("xq", semester_no);
+    Ok(json::Json(fetch_course_table(client, semester_year, semester_no).await?))
+}
 
-    let v: serde_json::Value = client.post(COURSE_TABLE_URL)
-                                    .form(&post_form)
-                                    .send()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
-                                    .json::<serde_json::Value>()
-                                    .await
-                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
+#[rocket::get("/watch_course?<username>&<password>&<semester_year>&<semester_no>&<courses_type>&<class_id>&<webhook>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn watch_course(
+    username: &str,
+    password: &str,
+    semester_year: &str,
+    semester_no: &str,
+    courses_type: &str,
+    class_id: &str,
+    webhook: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    watcher: &State<Watcher>,
+) -> Result<json::Json<Vec<WatchTarget>>, Unauthorized<String>> {
+    if course_type_code(courses_type).is_none() {
+        return Err(Unauthorized(Some(format!("Unknown courses type {}", courses_type))));
+    }
+    if let Some(webhook) = webhook {
+        watcher.check_webhook(webhook).await.map_err(|e| Unauthorized(Some(e)))?;
+    }
 
-    #[cfg(debug_assertions)]
-    println!("{:?}", v);
-                                    
-    let mut course_table_items_vec = Vec::<CourseTableItem>::new();
-    let json_array = v.as_array().unwrap();
-    for item in json_array {
-        let key = item["key"].as_str()
-                                .unwrap();
-        let day = key.chars().nth(2).unwrap().to_digit(10).unwrap();
-        let time = key.chars().nth(6).unwrap().to_digit(10).unwrap();
-        let course_table_item = CourseTableItem {
-            day,
-            time,
-            course_info: item["kbxx"].as_str()
-                                    .unwrap()
-                                    .to_owned(),
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let target = WatchTarget {
+        class_id: class_id.to_owned(),
+        semester_year: semester_year.to_owned(),
+        semester_no: semester_no.to_owned(),
+        courses_type: courses_type.to_owned(),
+        webhook: webhook.map(|w| w.to_owned()),
+        course_name: None,
+        capacity: None,
+    };
+    Ok(json::Json(watcher.watch(username, client, target).await))
+}
+
+#[rocket::get("/unwatch_course?<username>&<password>&<class_id>")]
+pub async fn unwatch_course(
+    username: &str,
+    password: &str,
+    class_id: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    watcher: &State<Watcher>,
+) -> Result<json::Json<Vec<WatchTarget>>, Unauthorized<String>> {
+    if !login(username, password, client_storage).await? {
+        return Err(Unauthorized(None));
+    }
+    Ok(json::Json(watcher.unwatch(username, class_id).await))
+}
+
+#[rocket::get("/watched_courses?<username>&<password>")]
+pub async fn watched_courses(
+    username: &str,
+    password: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    watcher: &State<Watcher>,
+) -> Result<json::Json<Vec<WatchTarget>>, Unauthorized<String>> {
+    if !login(username, password, client_storage).await? {
+        return Err(Unauthorized(None));
+    }
+    Ok(json::Json(watcher.watched(username).await))
+}
+
+#[rocket::get("/watch_events?<username>&<password>")]
+pub async fn watch_events(
+    username: &str,
+    password: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    watcher: &State<Watcher>,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], Unauthorized<String>> {
+    if !login(username, password, client_storage).await? {
+        return Err(Unauthorized(None));
+    }
+    let mut events = watcher.subscribe();
+    let username = username.to_owned();
+    Ok(EventStream! {
+        loop {
+            let event = rocket::tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+            if event.username == username {
+                yield Event::json(&event);
+            }
+        }
+    })
+}
+
+#[rocket::post("/waitlist?<username>&<password>", data = "<request>")]
+pub async fn add_waitlist(
+    username: &str,
+    password: &str,
+    request: json::Json<WaitlistRequest>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    waitlists: &State<Waitlists>,
+) -> Result<json::Json<Waitlist>, Unauthorized<String>> {
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let current = fetch_current_semester(&client).await?;
+    let waitlist = waitlists.add(username, client, &current, &request)
+                            .await
+                            .map_err(|e| Unauthorized(Some(e)))?;
+    Ok(json::Json(waitlist))
+}
+
+#[rocket::get("/waitlists?<username>&<password>")]
+pub async fn get_waitlists(
+    username: &str,
+    password: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    waitlists: &State<Waitlists>,
+) -> Result<json::Json<Vec<Waitlist>>, Unauthorized<String>> {
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    waitlists.refresh(username, client).await;
+    Ok(json::Json(waitlists.lists(username).await))
+}
+
+#[rocket::get("/cancel_waitlist?<username>&<password>&<waitlist_id>")]
+pub async fn cancel_waitlist(
+    username: &str,
+    password: &str,
+    waitlist_id: u64,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    waitlists: &State<Waitlists>,
+) -> Result<json::Json<Waitlist>, Unauthorized<String>> {
+    if !login(username, password, client_storage).await? {
+        return Err(Unauthorized(None));
+    }
+    let waitlist = waitlists.cancel(username, waitlist_id)
+                            .await
+                            .ok_or_else(|| Unauthorized(Some(format!("Cannot find the waitlist {}", waitlist_id))))?;
+    Ok(json::Json(waitlist))
+}
+
+#[rocket::get("/waitlist_audit?<username>&<password>")]
+pub async fn waitlist_audit(
+    username: &str,
+    password: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    waitlists: &State<Waitlists>,
+) -> Result<json::Json<Vec<AuditEntry>>, Unauthorized<String>> {
+    if !login(username, password, client_storage).await? {
+        return Err(Unauthorized(None));
+    }
+    Ok(json::Json(waitlists.audit(username).await))
+}
+
+#[rocket::post("/schedule_selection?<username>&<password>", data = "<request>")]
+pub async fn schedule_selection(
+    username: &str,
+    password: &str,
+    request: json::Json<ScheduledSelectionRequest>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    scheduler: &State<Scheduler>,
+) -> Result<json::Json<ScheduledSelection>, Unauthorized<String>> {
+    if !login(username, password, client_storage).await? {
+        return Err(Unauthorized(None));
+    }
+    scheduler.check(username, &request).await.map_err(|e| Unauthorized(Some(e)))?;
+    let client = job_session(username, password).await.map_err(|e| Unauthorized(Some(e)))?;
+    let selection = scheduler.add(username, client, &request)
+                            .await
+                            .map_err(|e| Unauthorized(Some(e)))?;
+    Ok(json::Json(selection))
+}
+
+#[rocket::get("/scheduled_selections?<username>&<password>")]
+pub async fn scheduled_selections(
+    username: &str,
+    password: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    scheduler: &State<Scheduler>,
+) -> Result<json::Json<Vec<ScheduledSelection>>, Unauthorized<String>> {
+    if !login(username, password, client_storage).await? {
+        return Err(Unauthorized(None));
+    }
+    Ok(json::Json(scheduler.list(username).await))
+}
+
+#[rocket::get("/cancel_scheduled_selection?<username>&<password>&<schedule_id>")]
+pub async fn cancel_scheduled_selection(
+    username: &str,
+    password: &str,
+    schedule_id: u64,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    scheduler: &State<Scheduler>,
+) -> Result<json::Json<ScheduledSelection>, Unauthorized<String>> {
+    if !login(username, password, client_storage).await? {
+        return Err(Unauthorized(None));
+    }
+    let selection = scheduler.cancel(username, schedule_id)
+                            .await
+                            .ok_or_else(|| Unauthorized(Some(format!("Cannot find the scheduled selection {}", schedule_id))))?;
+    Ok(json::Json(selection))
+}
+
+#[rocket::get("/recommend_points?<username>&<password>&<semester_year>&<semester_no>&<budget>&<class_ids>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn recommend_points(
+    username: &str,
+    password: &str,
+    semester_year: &str,
+    semester_no: &str,
+    budget: u32,
+    class_ids: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    snapshots: &State<Snapshots>,
+) -> Result<json::Json<PointsAllocation>, Unauthorized<String>> {
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let class_ids: Vec<&str> = class_ids.split(',')
+                                    .map(|id| id.trim())
+                                    .filter(|id| !id.is_empty())
+                                    .collect();
+    if class_ids.is_empty() {
+        return Err(Unauthorized(Some("No class to recommend points for".to_owned())));
+    }
+    Ok(json::Json(snapshots.recommend(semester_year, semester_no, budget, &class_ids).await))
+}
+
+#[rocket::get("/timetable.ics?<username>&<password>&<semester_year>&<semester_no>&<semester_start>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn timetable_ics(
+    username: &str,
+    password: &str,
+    semester_year: Option<&str>,
+    semester_no: Option<&str>,
+    semester_start: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    timetable: &State<TimetableConfig>,
+    calendars: &State<Calendars>,
+) -> Result<(ContentType, String), Unauthorized<String>> {
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let semester = match (semester_year, semester_no) {
+        (Some(semester_year), Some(semester_no)) => Some(CurrentSemester {
+            semester_year: semester_year.to_owned(),
+            semester_no: semester_no.to_owned(),
+        }),
+        _ => None
+    };
+    let semester_start = match semester_start {
+        Some(date) => Some(NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                            .map_err(|_| Unauthorized(Some(format!("Unable to parse {} as a YYYY-MM-DD date", date))))?),
+        None => None
+    };
+    let ics = timetable_calendar(&client, semester, semester_start, timetable, calendars).await?;
+    Ok((ContentType::Calendar, ics))
+}
+
+/// Log in, then find the current semester, its academic calendar and its
+/// course table, as `/today` and `/next_class` both need.
+async fn current_timetable(
+    username: &str,
+    password: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    timetable: &TimetableConfig,
+    calendars: &Calendars,
+) -> Result<(CurrentSemester, AcademicCalendar, Vec<CourseTableItem>), Unauthorized<String>> {
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let semester = fetch_current_semester(&client).await?;
+    let calendar = calendars.semester(
+                        &semester.semester_year,
+                        &semester.semester_no,
+                        timetable.semester_start(&semester.semester_year, &semester.semester_no)
+                    )
+                    .ok_or_else(|| Unauthorized(Some(format!(
+                        "No academic calendar or start date for the semester {}-{}",
+                        semester.semester_year,
+                        semester.semester_no
+                    ))))?;
+    let table = fetch_course_table(&client, &semester.semester_year, &semester.semester_no).await?;
+    Ok((semester, calendar, table))
+}
+
+#[rocket::get("/today?<username>&<password>&<date>")]
+pub async fn today(
+    username: &str,
+    password: &str,
+    date: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    timetable: &State<TimetableConfig>,
+    calendars: &State<Calendars>,
+) -> Result<json::Json<TodayClasses>, Unauthorized<String>> {
+    let bell_schedule = timetable.bell_schedule().map_err(|e| Unauthorized(Some(e)))?;
+    let date = match date {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                            .map_err(|_| Unauthorized(Some(format!("Unable to parse {} as a YYYY-MM-DD date", date))))?,
+        None => local_now().date()
+    };
+
+    let (semester, calendar, table) = current_timetable(username, password, client_storage, timetable, calendars).await?;
+    Ok(json::Json(TodayClasses {
+        semester_year: semester.semester_year,
+        semester_no: semester.semester_no,
+        day: calendar.day(date),
+        classes: agenda::classes_on(&table, &calendar, date, &bell_schedule),
+    }))
+}
+
+#[rocket::get("/next_class?<username>&<password>")]
+pub async fn next_class(
+    username: &str,
+    password: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    timetable: &State<TimetableConfig>,
+    calendars: &State<Calendars>,
+) -> Result<json::Json<Option<NextClass>>, Unauthorized<String>> {
+    let bell_schedule = timetable.bell_schedule().map_err(|e| Unauthorized(Some(e)))?;
+
+    let (_, calendar, table) = current_timetable(username, password, client_storage, timetable, calendars).await?;
+    Ok(json::Json(agenda::next_class(&table, &calendar, local_now(), &bell_schedule)))
+}
+
+#[rocket::get("/calendar_subscription?<username>&<password>&<semester_year>&<semester_no>")]
+pub async fn calendar_subscription(
+    username: &str,
+    password: &str,
+    semester_year: Option<&str>,
+    semester_no: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    subscriptions: &State<Subscriptions>,
+) -> Result<json::Json<CalendarSubscription>, Unauthorized<String>> {
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let semester = match (semester_year, semester_no) {
+        (Some(semester_year), Some(semester_no)) => Some(CurrentSemester {
+            semester_year: semester_year.to_owned(),
+            semester_no: semester_no.to_owned(),
+        }),
+        _ => None
+    };
+    let token = subscriptions.subscribe(username, client, semester)
+                            .await
+                            .ok_or_else(|| Unauthorized(Some("Unable to generate a subscription token".to_owned())))?;
+    Ok(json::Json(CalendarSubscription {
+        path: format!("/webcal/{}/timetable.ics", token),
+        token,
+    }))
+}
+
+#[rocket::get("/revoke_calendar_subscription?<username>&<password>")]
+pub async fn revoke_calendar_subscription(
+    username: &str,
+    password: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    subscriptions: &State<Subscriptions>,
+) -> Result<json::Json<bool>, Unauthorized<String>> {
+    if !login(username, password, client_storage).await? {
+        return Err(Unauthorized(None));
+    }
+    Ok(json::Json(subscriptions.revoke(username).await))
+}
+
+#[rocket::get("/webcal/<token>/timetable.ics")]
+pub async fn webcal_timetable(
+    token: &str,
+    if_none_match: IfNoneMatch,
+    subscriptions: &State<Subscriptions>,
+    timetable: &State<TimetableConfig>,
+    calendars: &State<Calendars>,
+) -> Result<WebCalendar, Unauthorized<String>> {
+    subscriptions.calendar(token, &if_none_match, timetable, calendars).await
+}
+
+#[rocket::get("/timetable_image?<username>&<password>&<semester_year>&<semester_no>&<week>&<theme>&<format>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn timetable_image(
+    username: &str,
+    password: &str,
+    semester_year: Option<&str>,
+    semester_no: Option<&str>,
+    week: Option<u32>,
+    theme: Option<&str>,
+    format: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    timetable: &State<TimetableConfig>,
+    render: &State<RenderConfig>,
+) -> Result<(ContentType, Vec<u8>), Unauthorized<String>> {
+    let theme = match theme {
+        Some(theme) => Theme::parse(theme).ok_or_else(|| Unauthorized(Some(format!("Unknown theme {}, use light or dark", theme))))?,
+        None => Theme::Light
+    };
+    let format = format.unwrap_or("svg");
+    if format != "svg" && format != "png" {
+        return Err(Unauthorized(Some(format!("Unknown format {}, use svg or png", format))));
+    }
+    let bell_schedule = timetable.bell_schedule().map_err(|e| Unauthorized(Some(e)))?;
+
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let semester = match (semester_year, semester_no) {
+        (Some(semester_year), Some(semester_no)) => CurrentSemester {
+            semester_year: semester_year.to_owned(),
+            semester_no: semester_no.to_owned(),
+        },
+        _ => fetch_current_semester(&client).await?
+    };
+    let table = fetch_course_table(&client, &semester.semester_year, &semester.semester_no).await?;
+    let title = match week {
+        Some(week) => format!("{}-{} 第{}周", semester.semester_year, semester.semester_no, week),
+        None => format!("{}-{}", semester.semester_year, semester.semester_no),
+    };
+    let svg = render_timetable(&title, &table, week, &bell_schedule, theme);
+
+    if format == "svg" {
+        return Ok((ContentType::SVG, svg.into_bytes()));
+    }
+    #[cfg(feature = "png")]
+    {
+        let png = super::render::rasterize(&svg, render).map_err(|e| Unauthorized(Some(e)))?;
+        Ok((ContentType::PNG, png))
+    }
+    #[cfg(not(feature = "png"))]
+    {
+        let _ = render;
+        Err(Unauthorized(Some("PNG output is not enabled on this server, build it with the png feature".to_owned())))
+    }
+}
+
+#[rocket::get("/plan_timetable?<username>&<password>&<semester_year>&<semester_no>&<course_ids>&<earliest>&<latest>&<free_days>&<only_open>&<top>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn plan_timetable(
+    username: &str,
+    password: &str,
+    semester_year: &str,
+    semester_no: &str,
+    course_ids: &str,
+    earliest: Option<&str>,
+    latest: Option<&str>,
+    free_days: Option<&str>,
+    only_open: Option<bool>,
+    top: Option<usize>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    timetable: &State<TimetableConfig>,
+    planner_config: &State<PlannerConfig>,
+) -> Result<json::Json<TimetablePlan>, Unauthorized<String>> {
+    let mut wanted = Vec::<&str>::new();
+    for course_id in course_ids.split(',').map(|id| id.trim()).filter(|id| !id.is_empty()) {
+        if !wanted.contains(&course_id) {
+            wanted.push(course_id);
+        }
+    }
+    if wanted.is_empty() {
+        return Err(Unauthorized(Some("No course to plan".to_owned())));
+    }
+    let time = |time: Option<&str>| match time {
+        Some(time) => NaiveTime::parse_from_str(time, "%H:%M")
+                        .map(Some)
+                        .map_err(|_| Unauthorized(Some(format!("Unable to parse {} as a HH:MM time", time)))),
+        None => Ok(None)
+    };
+    let mut free_weekdays = Vec::<u32>::new();
+    for day in free_days.unwrap_or_default().split(',').map(|day| day.trim()).filter(|day| !day.is_empty()) {
+        match day.parse::<u32>() {
+            Ok(day) if (1..=7).contains(&day) => free_weekdays.push(day),
+            _ => return Err(Unauthorized(Some(format!("Unknown weekday {}, use 1 for Monday to 7 for Sunday", day))))
+        }
+    }
+    let constraints = PlanConstraints {
+        earliest: time(earliest)?,
+        latest: time(latest)?,
+        free_weekdays,
+        only_open: only_open.unwrap_or(false),
+    };
+    let bell_schedule = timetable.bell_schedule().map_err(|e| Unauthorized(Some(e)))?;
+
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    // A class may be listed under several tags, it is kept under the first.
+    // A tag which fails is skipped, unless the session has expired or they
+    // all fail, as the wanted courses would all look unplaced.
+    let mut courses = Vec::<(&str, AvailableCourse)>::new();
+    let mut last_error = None;
+    let mut fetched = false;
+    for courses_type in ["GR", "GE", "TP", "NTP"] {
+        let available = match fetch_available_courses(&client, semester_year, semester_no, courses_type).await {
+            Ok(available) => available,
+            Err(FetchError::SessionExpired) => return Err(FetchError::SessionExpired.into()),
+            Err(e) => {
+                last_error = Some(e);
+                continue;
+            }
         };
-        course_table_items_vec.push(course_table_item);
+        fetched = true;
+        for course in available {
+            let duplicate = courses.iter().any(|(_, c)| c.advanced_course.id == course.advanced_course.id);
+            if !duplicate && wanted.contains(&course.advanced_course.basic_course.course_id.as_str()) {
+                courses.push((courses_type, course));
+            }
+        }
+    }
+    if let (false, Some(e)) = (fetched, last_error) {
+        return Err(e.into());
+    }
+    Ok(json::Json(planner::plan_timetable(&courses, &wanted, &constraints, &bell_schedule, top.unwrap_or(5), planner_config)))
+}
+
+#[rocket::get("/index_outlines?<username>&<password>&<semester_year>&<semester_no>")]
+pub async fn index_outlines(
+    username: &str,
+    password: &str,
+    semester_year: &str,
+    semester_no: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    outlines: &State<Outlines>,
+) -> Result<json::Json<OutlineIndexStatus>, Unauthorized<String>> {
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let mut added = 0;
+    for courses_type in ["GR", "GE", "TP", "NTP"] {
+        if let Ok(courses) = fetch_available_courses(&client, semester_year, semester_no, courses_type).await {
+            added += outlines.enqueue(username, &client, &courses).await;
+        }
+    }
+    Ok(json::Json(outlines.status(added).await))
+}
+
+#[rocket::get("/search_outlines?<username>&<password>&<q>&<limit>")]
+pub async fn search_outlines(
+    username: &str,
+    password: &str,
+    q: &str,
+    limit: Option<usize>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    outlines: &State<Outlines>,
+) -> Result<json::Json<OutlineSearch>, Unauthorized<String>> {
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    Ok(json::Json(outlines.search(q, limit.unwrap_or(20)).await))
+}
+
+#[rocket::get("/prerequisites?<course_id>")]
+pub async fn prerequisites(
+    course_id: &str,
+    catalogue: &State<Catalogue>,
+) -> Result<json::Json<PrerequisiteInfo>, Unauthorized<String>> {
+    // Read the detail pages of the course first if they have not been yet.
+    catalogue.course(course_id).await?;
+    let graph = PrerequisiteGraph::new(&catalogue.catalog_courses().await?);
+    graph.info(course_id)
+        .map(json::Json)
+        .ok_or_else(|| Unauthorized(Some("Unable to find the course in the catalogue".to_owned())))
+}
+
+#[rocket::get("/check_prerequisites?<username>&<password>&<course_ids>")]
+pub async fn check_prerequisites(
+    username: &str,
+    password: &str,
+    course_ids: &str,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    catalogue: &State<Catalogue>,
+) -> Result<json::Json<Vec<Eligibility>>, Unauthorized<String>> {
+    let mut wanted = Vec::<&str>::new();
+    for course_id in course_ids.split(',').map(|id| id.trim()).filter(|id| !id.is_empty()) {
+        if !wanted.contains(&course_id) {
+            wanted.push(course_id);
+        }
+    }
+    if wanted.is_empty() {
+        return Err(Unauthorized(Some("No course to check".to_owned())));
+    }
+
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let passed = passed_courses(&fetch_course_grades(&client).await?);
+
+    for course_id in wanted.iter() {
+        // A course missing from the catalogue is reported by the check.
+        let _ = catalogue.course(course_id).await;
+    }
+    let graph = PrerequisiteGraph::new(&catalogue.catalog_courses().await?);
+    Ok(json::Json(wanted.iter().map(|course_id| graph.check(course_id, &passed)).collect()))
+}
+
+#[rocket::get("/training_plans")]
+pub async fn training_plans(
+    plans: &State<TrainingPlans>,
+) -> json::Json<Vec<TrainingPlanKey>> {
+    json::Json(plans.list())
+}
+
+#[rocket::get("/degree_audit?<username>&<password>&<major>&<year>")]
+pub async fn degree_audit(
+    username: &str,
+    password: &str,
+    major: Option<&str>,
+    year: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    plans: &State<TrainingPlans>,
+    grades_config: &State<GradesConfig>,
+) -> Result<json::Json<DegreeAudit>, Unauthorized<String>> {
+
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let basic_info = fetch_basic_info(&client).await?;
+    let major = major.unwrap_or(&basic_info.major);
+    let year = year.unwrap_or(&basic_info.year);
+    let plan = plans.find(major, year)
+                    .ok_or_else(|| Unauthorized(Some(format!("No training plan of {} for the {} cohort", major, year))))?;
+
+    let mut grades = fetch_course_grades(&client).await?;
+    mark_attempts(&mut grades, grades_config.retake_policy);
+    Ok(json::Json(audit(plan, &grades)))
+}
+
+#[rocket::get("/study_plan?<username>&<password>&<start>&<summer>&<max_credits>&<skip>&<caps>&<pins>&<extra>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn study_plan(
+    username: &str,
+    password: &str,
+    start: Option<&str>,
+    summer: Option<bool>,
+    max_credits: Option<f32>,
+    skip: Option<&str>,
+    caps: Option<&str>,
+    pins: Option<&str>,
+    extra: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    plans: &State<TrainingPlans>,
+    catalogue: &State<Catalogue>,
+    snapshots: &State<Snapshots>,
+    config: &State<RoadmapConfig>,
+    grades_config: &State<GradesConfig>,
+) -> Result<json::Json<StudyPlan>, Unauthorized<String>> {
+    let list = |text: Option<&str>| -> Vec<String> {
+        text.unwrap_or_default().split(',').map(|item| item.trim().to_owned()).filter(|item| !item.is_empty()).collect()
+    };
+    let term = |text: &str| parse_term(text).ok_or_else(|| Unauthorized(Some(format!("Unable to parse {} as a semester such as 2023-2024-1", text))));
+    let summer = summer.unwrap_or(false);
+
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let current = fetch_current_semester(&client).await?;
+    let first = match start {
+        Some(start) => term(start)?,
+        None => next_term(term(&format!("{}-{}", current.semester_year, current.semester_no))?, summer)
+    };
+    let mut terms: Vec<Term> = terms_from(first, config.max_semesters, summer)
+                                .into_iter()
+                                .map(|(semester_year, semester_no)| Term {
+                                    semester_year,
+                                    semester_no,
+                                    max_credits: max_credits.unwrap_or(config.max_credits),
+                                    skipped: false,
+                                })
+                                .collect();
+    let term_index = |terms: &[Term], text: &str| -> Result<usize, Unauthorized<String>> {
+        let (year, no) = term(text)?;
+        terms.iter()
+            .position(|t| t.semester_year == format!("{}-{}", year, year + 1) && t.semester_no == no.to_string())
+            .ok_or_else(|| Unauthorized(Some(format!("{} is not one of the semesters planned", text))))
+    };
+    for skipped in list(skip) {
+        let t = term_index(&terms, &skipped)?;
+        terms[t].skipped = true;
+    }
+    for cap in list(caps) {
+        let (semester, credits) = cap.split_once(':')
+                                    .and_then(|(semester, credits)| Some((semester, credits.trim().parse::<f32>().ok()?)))
+                                    .ok_or_else(|| Unauthorized(Some(format!("Unable to parse {} as SEMESTER:CREDITS", cap))))?;
+        let t = term_index(&terms, semester)?;
+        terms[t].max_credits = credits;
+    }
+    let mut pinned = Vec::<(String, usize)>::new();
+    for pin in list(pins) {
+        let (course_id, semester) = pin.split_once('@')
+                                        .ok_or_else(|| Unauthorized(Some(format!("Unable to parse {} as COURSE@SEMESTER", pin))))?;
+        pinned.push((course_id.trim().to_uppercase(), term_index(&terms, semester)?));
+    }
+
+    // The courses of this semester count as passed, they are being taken.
+    let mut grades = fetch_course_grades(&client).await?;
+    mark_attempts(&mut grades, grades_config.retake_policy);
+    let mut passed = passed_courses(&grades);
+    if let Ok(selected) = fetch_selected_courses(&client, &current.semester_year, &current.semester_no).await {
+        passed.extend(selected.iter().map(|course| course.advanced_course.basic_course.course_id.trim().to_uppercase()));
+    }
+
+    let offerings = snapshots.offerings().await;
+    let mut needed = Vec::<String>::new();
+    let mut electives = Vec::<(String, f32)>::new();
+    let basic_info = fetch_basic_info(&client).await?;
+    if let Some(plan) = plans.find(&basic_info.major, &basic_info.year) {
+        let report = audit(plan, &grades);
+        needed.extend(report.courses_needed.iter().map(|entry| choose_alternative(entry, &offerings)));
+        electives.extend(report.remaining_by_category.into_iter().filter(|(_, credits)| *credits > 0.0));
+    }
+    needed.extend(list(extra).into_iter().map(|course_id| course_id.to_uppercase()));
+    needed.extend(pinned.iter().map(|(course_id, _)| course_id.clone()));
+    let mut seen = std::collections::HashSet::new();
+    needed.retain(|course_id| !passed.contains(course_id) && seen.insert(course_id.clone()));
+    if needed.is_empty() && electives.is_empty() {
+        return Err(Unauthorized(Some(format!("No course left to plan, and no training plan of {} for the {} cohort", basic_info.major, basic_info.year))));
+    }
+
+    let catalog_courses = catalogue.catalog_courses().await.unwrap_or_default();
+    let graph = PrerequisiteGraph::new(&catalog_courses);
+    let courses: Vec<RemainingCourse> = needed.iter()
+                                            .map(|course_id| {
+                                                let course = catalog_courses.iter().find(|c| c.course_id.eq_ignore_ascii_case(course_id));
+                                                RemainingCourse {
+                                                    course_id: course_id.clone(),
+                                                    course_name: course.map(|c| c.course_name.clone()),
+                                                    credits: course.map_or(0.0, |c| c.credits),
+                                                    pinned: pinned.iter().find(|(id, _)| id == course_id).map(|(_, t)| *t),
+                                                }
+                                            })
+                                            .collect();
+    let mut plan = plan_semesters(&courses, &electives, &passed, &terms, &graph, &offerings);
+    let unknown: Vec<&str> = courses.iter()
+                                .filter(|course| graph.info(&course.course_id).is_none_or(|info| !info.unknown.is_empty()))
+                                .map(|course| course.course_id.as_str())
+                                .collect();
+    if !unknown.is_empty() {
+        plan.warnings.push(format!("The prerequisites of {} are not known yet, see /course_detail.", unknown.join(", ")));
+    }
+    Ok(json::Json(plan))
+}
+
+#[rocket::get("/grade_history?<username>&<password>&<policy>")]
+pub async fn grade_history(
+    username: &str,
+    password: &str,
+    policy: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    config: &State<GradesConfig>,
+) -> Result<json::Json<GradeHistory>, Unauthorized<String>> {
+    let policy = match policy {
+        Some(policy) => RetakePolicy::parse(policy)
+                            .ok_or_else(|| Unauthorized(Some(format!("Unknown retake policy {}, expected latest or highest", policy))))?,
+        None => config.retake_policy
+    };
+
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let course_grades = fetch_course_grades(&client).await?;
+    Ok(json::Json(grades::grade_history(&course_grades, policy)))
+}
+
+#[rocket::get("/transcript_summary?<username>&<password>&<scales>&<policy>")]
+pub async fn transcript_summary(
+    username: &str,
+    password: &str,
+    scales: Option<&str>,
+    policy: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    grades_config: &State<GradesConfig>,
+    config: &State<ConversionConfig>,
+) -> Result<json::Json<TranscriptSummary>, Unauthorized<String>> {
+    let policy = match policy {
+        Some(policy) => RetakePolicy::parse(policy)
+                            .ok_or_else(|| Unauthorized(Some(format!("Unknown retake policy {}, expected latest or highest", policy))))?,
+        None => grades_config.retake_policy
+    };
+    let tables: Vec<&ScaleTable> = match scales {
+        Some(scales) => scales.split(',')
+                            .map(|name| name.trim())
+                            .filter(|name| !name.is_empty())
+                            .map(|name| config.scales.iter().find(|scale| scale.name == name).ok_or_else(|| {
+                                let known: Vec<&str> = config.scales.iter().map(|scale| scale.name.as_str()).collect();
+                                Unauthorized(Some(format!("Unknown scale {}, expected one of {}", name, known.join(", "))))
+                            }))
+                            .collect::<Result<_, _>>()?,
+        None => config.scales.iter().collect()
+    };
+
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let course_grades = fetch_course_grades(&client).await?;
+    Ok(json::Json(conversion::transcript_summary(&course_grades, policy, config, &tables)))
+}
+
+#[rocket::get("/transcript?<username>&<password>&<format>&<policy>")]
+pub async fn transcript(
+    username: &str,
+    password: &str,
+    format: Option<&str>,
+    policy: Option<&str>,
+    client_storage: &State<Mutex<HashMap<String, Account>>>,
+    grades_config: &State<GradesConfig>,
+    config: &State<TranscriptConfig>,
+) -> Result<(ContentType, Vec<u8>), Unauthorized<String>> {
+    let format = format.unwrap_or_else(|| config.default_format());
+    if format != "pdf" && format != "csv" {
+        return Err(Unauthorized(Some(format!("Unknown format {}, use pdf or csv", format))));
+    }
+    if format == "pdf" {
+        config.check_pdf().map_err(|e| Unauthorized(Some(e)))?;
+    }
+    let policy = match policy {
+        Some(policy) => RetakePolicy::parse(policy)
+                            .ok_or_else(|| Unauthorized(Some(format!("Unknown retake policy {}, expected latest or highest", policy))))?,
+        None => grades_config.retake_policy
+    };
+
+    let tis_login_result = tis_login(username, password, client_storage).await?;
+    if !tis_login_result { return Err(Unauthorized(None)); }
+
+    let client = client_storage.lock().await.get(username).unwrap().client.clone();
+    let basic_info = fetch_basic_info(&client).await?;
+    let student_gpa = fetch_student_gpa(&client).await?;
+    let course_grades = fetch_course_grades(&client).await?;
+    let transcript = build_transcript(basic_info, &student_gpa, &course_grades, policy, local_now());
+
+    if format == "csv" {
+        let csv = transcript_csv(&transcript).map_err(|e| Unauthorized(Some(e)))?;
+        return Ok((ContentType::CSV, csv));
+    }
+    #[cfg(feature = "pdf")]
+    {
+        let pdf = super::transcript::transcript_pdf(&transcript, config).map_err(|e| Unauthorized(Some(e)))?;
+        Ok((ContentType::PDF, pdf))
+    }
+    #[cfg(not(feature = "pdf"))]
+    {
+        Err(Unauthorized(config.check_pdf().err()))
     }
-    Ok(json::Json(course_table_items_vec))
 }
 
 #[cfg(test)]
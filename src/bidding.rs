@@ -0,0 +1,444 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::DateTime;
+use futures::lock::Mutex;
+use rocket::serde::Deserialize;
+use rocket::tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use super::structures::*;
+
+/// Ratios above this are treated the same, a class with no seat at all
+/// would be infinite otherwise.
+const MAX_RATIO: f64 = 3.0;
+/// Below this ratio a class is expected to have free seats at the end of
+/// the round, so it only gets the minimum points.
+const SAFE_RATIO: f64 = 0.8;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct BiddingConfig {
+    /// JSON lines file the snapshots are appended to, memory only if unset.
+    pub snapshot_file: Option<String>,
+    /// Minimum seconds between two snapshots of the same class.
+    pub snapshot_interval: i64,
+    /// Points given to a class which is not expected to be contested.
+    pub min_points: u32,
+    /// The most snapshots kept, in memory and in `snapshot_file`.
+    pub max_snapshots: usize,
+}
+
+impl Default for BiddingConfig {
+    fn default() -> Self {
+        BiddingConfig {
+            snapshot_file: Some("snapshots.jsonl".to_owned()),
+            snapshot_interval: 1800,
+            min_points: 1,
+            max_snapshots: 200_000,
+        }
+    }
+}
+
+/// Enrolment history of one target class, as used by [`recommend`].
+pub struct ClassDemand {
+    pub class_id: String,
+    pub course_name: Option<String>,
+    /// Snapshots of this round in time order.
+    pub current: Vec<Capacity>,
+    /// Final ratios of the same course in the previous rounds.
+    pub history: Vec<f64>,
+}
+
+fn ratio(capacity: &Capacity) -> f64 {
+    if capacity.available == 0 {
+        MAX_RATIO
+    } else {
+        (capacity.selected as f64 / capacity.available as f64).min(MAX_RATIO)
+    }
+}
+
+/// Split `budget` between the targets according to how contested each one
+/// is: its current demand ratio, the trend of this round and its history.
+pub fn recommend(
+    budget: u32,
+    min_points: u32,
+    targets: &[ClassDemand]
+) -> PointsAllocation {
+    let mut pressures = Vec::<f64>::new();
+    let mut recommendations = Vec::<PointsRecommendation>::new();
+    for target in targets {
+        let demand_ratio = target.current.last().map(ratio);
+        let projected_ratio = match (target.current.first(), target.current.last()) {
+            (Some(first), Some(last)) if target.current.len() > 1 => {
+                let (first, last) = (ratio(first), ratio(last));
+                Some((last + (last - first) / 2.0).clamp(0.0, MAX_RATIO))
+            },
+            _ => None
+        };
+        let historical_ratio = if target.history.is_empty() {
+            None
+        } else {
+            Some(target.history.iter().sum::<f64>() / target.history.len() as f64)
+        };
+        let known: Vec<f64> = [demand_ratio, projected_ratio, historical_ratio].iter().flatten().cloned().collect();
+        let pressure = known.iter().cloned().fold(None, |max: Option<f64>, r| Some(max.map_or(r, |m| m.max(r))));
+        let rationale = match pressure {
+            None => "No enrolment data for this class, treated as fully subscribed".to_owned(),
+            Some(p) if p < SAFE_RATIO => format!("Expected demand ratio {:.2} leaves free seats, minimum points", p),
+            Some(p) => format!("Expected demand ratio {:.2}", p),
+        };
+        pressures.push(pressure.unwrap_or(1.0));
+        recommendations.push(PointsRecommendation {
+            class_id: target.class_id.clone(),
+            course_name: target.course_name.clone(),
+            points: 0,
+            demand_ratio,
+            projected_ratio,
+            historical_ratio,
+            snapshots: target.current.len(),
+            rationale,
+        });
+    }
+
+    let mut remaining = budget;
+    for (recommendation, pressure) in recommendations.iter_mut().zip(&pressures) {
+        if *pressure < SAFE_RATIO {
+            recommendation.points = min_points.min(remaining);
+            remaining -= recommendation.points;
+        }
+    }
+    let contested: Vec<usize> = (0..pressures.len()).filter(|i| pressures[*i] >= SAFE_RATIO).collect();
+    let total_weight: f64 = contested.iter().map(|i| pressures[*i]).sum();
+    if total_weight > 0.0 {
+        let shares: Vec<f64> = contested.iter()
+                                    .map(|i| remaining as f64 * pressures[*i] / total_weight)
+                                    .collect();
+        let mut given = 0;
+        for (i, share) in contested.iter().zip(&shares) {
+            recommendations[*i].points = share.floor() as u32;
+            given += recommendations[*i].points;
+        }
+        // Hand the points lost to rounding to the largest remainders.
+        let mut order: Vec<usize> = (0..contested.len()).collect();
+        order.sort_by(|a, b| (shares[*b] - shares[*b].floor()).partial_cmp(&(shares[*a] - shares[*a].floor())).unwrap());
+        for k in order.into_iter().take((remaining - given) as usize) {
+            recommendations[contested[k]].points += 1;
+        }
+    }
+
+    PointsAllocation {
+        budget,
+        allocated: recommendations.iter().map(|r| r.points).sum(),
+        recommendations,
+    }
+}
+
+/// The capacity of a course in each earlier round, from the last snapshot
+/// of each of its classes in that round.
+fn final_capacities(
+    snapshots: &[EnrolmentSnapshot],
+    course_code: &str,
+    semester_year: &str,
+    semester_no: &str,
+) -> Vec<Capacity> {
+    let mut last = HashMap::<(&str, &str, &str), &EnrolmentSnapshot>::new();
+    for snapshot in snapshots.iter().filter(|s| s.course_code == course_code) {
+        if snapshot.semester_year == semester_year && snapshot.semester_no == semester_no {
+            continue;
+        }
+        last.insert((&snapshot.semester_year, &snapshot.semester_no, &snapshot.class_id), snapshot);
+    }
+    let mut rounds = BTreeMap::<(&str, &str), Capacity>::new();
+    for ((semester_year, semester_no, _), snapshot) in last {
+        let capacity = rounds.entry((semester_year, semester_no)).or_insert(Capacity { available: 0, selected: 0 });
+        capacity.available += snapshot.available;
+        capacity.selected += snapshot.selected;
+    }
+    rounds.into_values().collect()
+}
+
+/// Bring the snapshots down to `max` once they are over it. The rounds
+/// other than the given one are first reduced to the last snapshot of each
+/// class, which is all that is used of them, then the oldest are dropped.
+/// Returns whether any snapshot was removed.
+fn compact(
+    snapshots: &mut Vec<EnrolmentSnapshot>,
+    semester_year: &str,
+    semester_no: &str,
+    max: usize,
+) -> bool {
+    if snapshots.len() <= max {
+        return false;
+    }
+    let mut last = HashMap::<(&str, &str, &str), usize>::new();
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        last.insert((&snapshot.semester_year, &snapshot.semester_no, &snapshot.class_id), i);
+    }
+    let keep: HashSet<usize> = last.into_values().collect();
+    let mut i = 0;
+    snapshots.retain(|snapshot| {
+        i += 1;
+        keep.contains(&(i - 1)) || (snapshot.semester_year == semester_year && snapshot.semester_no == semester_no)
+    });
+    // Leave some room, so that the file is not rewritten on every snapshot.
+    let target = max - max / 10;
+    if snapshots.len() > target {
+        let excess = snapshots.len() - target;
+        snapshots.drain(..excess);
+    }
+    true
+}
+
+/// Replace the snapshot file with the given snapshots.
+async fn rewrite(file: &Path, snapshots: &[EnrolmentSnapshot]) -> std::io::Result<()> {
+    let mut lines = String::new();
+    for snapshot in snapshots {
+        if let Ok(line) = serde_json::to_string(snapshot) {
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+    }
+    let temp = file.with_extension("tmp");
+    rocket::tokio::fs::write(&temp, lines).await?;
+    rocket::tokio::fs::rename(&temp, file).await
+}
+
+struct SnapshotsInner {
+    file: Option<PathBuf>,
+    interval: i64,
+    min_points: u32,
+    max_snapshots: usize,
+    snapshots: Mutex<Vec<EnrolmentSnapshot>>,
+}
+
+/// Enrolment snapshots of classes, taken whenever the server sees the
+/// available courses of a round, either for a user or for the seat watcher.
+#[derive(Clone)]
+pub struct Snapshots {
+    inner: Arc<SnapshotsInner>,
+}
+
+fn timestamp(time: &str) -> i64 {
+    DateTime::parse_from_rfc3339(time).map(|t| t.timestamp()).unwrap_or_default()
+}
+
+impl Snapshots {
+    pub fn load(config: &BiddingConfig) -> Self {
+        let file = config.snapshot_file.as_ref().map(PathBuf::from);
+        let mut snapshots: Vec<EnrolmentSnapshot> = file.as_ref()
+                            .and_then(|file| std::fs::read_to_string(file).ok())
+                            .map(|content| {
+                                content.lines()
+                                        .filter_map(|line| serde_json::from_str::<EnrolmentSnapshot>(line).ok())
+                                        .collect()
+                            })
+                            .unwrap_or_default();
+        // A smaller `max_snapshots` than when the file was written.
+        if snapshots.len() > config.max_snapshots {
+            let excess = snapshots.len() - config.max_snapshots;
+            snapshots.drain(..excess);
+        }
+        Snapshots {
+            inner: Arc::new(SnapshotsInner {
+                file,
+                interval: config.snapshot_interval,
+                min_points: config.min_points,
+                max_snapshots: config.max_snapshots.max(1),
+                snapshots: Mutex::new(snapshots),
+            })
+        }
+    }
+
+    /// Record the classes whose enrolment changed since their last snapshot,
+    /// at most once per `snapshot_interval`.
+    pub async fn record(
+        &self,
+        semester_year: &str,
+        semester_no: &str,
+        courses: &[AvailableCourse],
+    ) {
+        let now = chrono::Local::now();
+        let mut snapshots = self.inner.snapshots.lock().await;
+        let mut last = HashMap::<&str, &EnrolmentSnapshot>::new();
+        for snapshot in snapshots.iter().filter(|s| s.semester_year == semester_year && s.semester_no == semester_no) {
+            last.insert(&snapshot.class_id, snapshot);
+        }
+
+        let mut new_snapshots = Vec::<EnrolmentSnapshot>::new();
+        for course in courses {
+            let course = &course.advanced_course;
+            let capacity = Capacity::of(course);
+            let due = match last.get(&course.id[..]) {
+                None => true,
+                Some(snapshot) => {
+                    now.timestamp() - timestamp(&snapshot.time) >= self.inner.interval
+                        && (snapshot.available, snapshot.selected) != (capacity.available, capacity.selected)
+                }
+            };
+            if due {
+                new_snapshots.push(EnrolmentSnapshot {
+                    time: now.to_rfc3339(),
+                    semester_year: semester_year.to_owned(),
+                    semester_no: semester_no.to_owned(),
+                    class_id: course.id.clone(),
+                    course_code: course.basic_course.course_id.clone(),
+                    course_name: format!("{}[{}]", course.basic_course.course_name, course.course_class),
+                    available: capacity.available,
+                    selected: capacity.selected,
+                });
+            }
+        }
+        if new_snapshots.is_empty() {
+            return;
+        }
+
+        snapshots.extend(new_snapshots.iter().cloned());
+        if compact(&mut snapshots, semester_year, semester_no, self.inner.max_snapshots) {
+            if let Some(file) = &self.inner.file {
+                if let Err(_e) = rewrite(file, &snapshots).await {
+                    #[cfg(debug_assertions)]
+                    println!("Unable to save the enrolment snapshots: {}", _e);
+                }
+            }
+        } else if let Some(file) = &self.inner.file {
+            let mut lines = String::new();
+            for snapshot in &new_snapshots {
+                if let Ok(line) = serde_json::to_string(snapshot) {
+                    lines.push_str(&line);
+                    lines.push('\n');
+                }
+            }
+            let result = match OpenOptions::new().create(true).append(true).open(file).await {
+                Ok(mut f) => f.write_all(lines.as_bytes()).await,
+                Err(e) => Err(e)
+            };
+            if let Err(_e) = result {
+                #[cfg(debug_assertions)]
+                println!("Unable to save the enrolment snapshots: {}", _e);
+            }
+        }
+    }
+
+    /// The semester numbers each course has been seen in, 1 for autumn, 2
+    /// for spring and 3 for summer.
+    pub async fn offerings(&self) -> HashMap<String, BTreeSet<String>> {
+        let mut offerings = HashMap::<String, BTreeSet<String>>::new();
+        for snapshot in self.inner.snapshots.lock().await.iter() {
+            offerings.entry(snapshot.course_code.trim().to_uppercase())
+                .or_default()
+                .insert(snapshot.semester_no.clone());
+        }
+        offerings
+    }
+
+    pub async fn recommend(
+        &self,
+        semester_year: &str,
+        semester_no: &str,
+        budget: u32,
+        class_ids: &[&str],
+    ) -> PointsAllocation {
+        let snapshots = self.inner.snapshots.lock().await;
+        let mut targets = Vec::<ClassDemand>::new();
+        for class_id in class_ids {
+            let current: Vec<&EnrolmentSnapshot> = snapshots.iter()
+                                                    .filter(|s| s.class_id == *class_id && s.semester_year == semester_year && s.semester_no == semester_no)
+                                                    .collect();
+            // The final ratio of each earlier round of the same course.
+            let history = current.last()
+                                .map(|s| final_capacities(&snapshots, &s.course_code, semester_year, semester_no))
+                                .unwrap_or_default();
+            targets.push(ClassDemand {
+                class_id: class_id.to_string(),
+                course_name: current.last().map(|s| s.course_name.clone()),
+                current: current.iter().map(|s| Capacity { available: s.available, selected: s.selected }).collect(),
+                history: history.iter().map(ratio).collect(),
+            });
+        }
+        recommend(budget, self.inner.min_points, &targets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::structures::{Capacity, EnrolmentSnapshot};
+    use super::{compact, final_capacities, recommend, ClassDemand};
+
+    fn snapshot(semester_no: &str, class_id: &str, selected: u32) -> EnrolmentSnapshot {
+        EnrolmentSnapshot {
+            time: String::new(),
+            semester_year: "2020-2021".to_owned(),
+            semester_no: semester_no.to_owned(),
+            class_id: class_id.to_owned(),
+            course_code: "CS101".to_owned(),
+            course_name: String::new(),
+            available: 50,
+            selected,
+        }
+    }
+
+    fn demand(class_id: &str, current: &[(u32, u32)], history: &[f64]) -> ClassDemand {
+        ClassDemand {
+            class_id: class_id.to_owned(),
+            course_name: None,
+            current: current.iter().map(|(available, selected)| Capacity { available: *available, selected: *selected }).collect(),
+            history: history.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_recommend_splits_budget_by_pressure() {
+        let targets = [
+            demand("popular", &[(50, 80), (50, 100)], &[]),
+            demand("normal", &[(50, 50)], &[]),
+            demand("quiet", &[(50, 10)], &[0.3]),
+        ];
+        let allocation = recommend(100, 1, &targets);
+        assert_eq!(allocation.allocated, 100);
+        let points: Vec<u32> = allocation.recommendations.iter().map(|r| r.points).collect();
+        assert_eq!(points[2], 1);
+        assert!(points[0] > points[1]);
+        assert!((allocation.recommendations[0].projected_ratio.unwrap() - 2.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_recommend_uses_history_without_current_data() {
+        let targets = [
+            demand("unknown", &[], &[]),
+            demand("was_popular", &[], &[2.0, 2.0]),
+        ];
+        let allocation = recommend(30, 1, &targets);
+        assert_eq!(allocation.recommendations[0].points, 10);
+        assert_eq!(allocation.recommendations[1].points, 20);
+        assert_eq!(allocation.recommendations[1].historical_ratio, Some(2.0));
+    }
+
+    #[test]
+    fn test_final_capacities() {
+        let snapshots = vec![
+            snapshot("1", "a", 10),
+            snapshot("1", "b", 20),
+            snapshot("1", "a", 60),
+            snapshot("2", "a", 30),
+            snapshot("3", "a", 5),
+        ];
+        let capacities = final_capacities(&snapshots, "CS101", "2020-2021", "3");
+        assert_eq!(capacities, vec![Capacity { available: 100, selected: 80 }, Capacity { available: 50, selected: 30 }]);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut snapshots: Vec<EnrolmentSnapshot> = (0..10).map(|i| snapshot("1", "a", i)).collect();
+        snapshots.extend((0..10).map(|i| snapshot("2", "a", i)));
+        assert!(!compact(&mut snapshots, "2020-2021", "2", 20));
+        assert!(compact(&mut snapshots, "2020-2021", "2", 15));
+        // The last snapshot of the earlier round, then the newest of this one.
+        assert_eq!(snapshots.len(), 11);
+        assert_eq!((snapshots[0].semester_no.as_str(), snapshots[0].selected), ("1", 9));
+        assert_eq!(snapshots.last().unwrap().selected, 9);
+        assert!(compact(&mut snapshots, "2020-2021", "2", 10));
+        assert_eq!(snapshots.len(), 9);
+        assert_eq!(snapshots[0].semester_no, "2");
+    }
+}
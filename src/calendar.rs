@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use rocket::serde::Deserialize;
+
+use super::structures::*;
+
+/// Asia/Shanghai has no daylight saving time.
+pub const UTC_OFFSET_HOURS: i64 = 8;
+
+/// The wall clock time in Shenzhen, whatever the timezone of the server.
+pub fn local_now() -> NaiveDateTime {
+    Utc::now().naive_utc() + Duration::hours(UTC_OFFSET_HOURS)
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CalendarConfig {
+    /// Directory of the academic calendar files, one JSON file per semester.
+    pub directory: String,
+}
+
+impl Default for CalendarConfig {
+    fn default() -> Self {
+        CalendarConfig { directory: "calendars".to_owned() }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Holiday {
+    pub name: String,
+    pub start: NaiveDate,
+    /// Last day of the holiday, the same as `start` if missing.
+    pub end: Option<NaiveDate>,
+}
+
+/// A make-up day (调休): the classes of `follows` are held on `date`.
+#[derive(Deserialize, Clone)]
+pub struct SwappedDay {
+    pub date: NaiveDate,
+    pub follows: NaiveDate,
+}
+
+/// The academic calendar of one semester, as read from its file.
+#[derive(Deserialize, Clone)]
+pub struct AcademicCalendar {
+    pub semester_year: String,
+    pub semester_no: String,
+    /// Monday of the first week.
+    pub start: NaiveDate,
+    pub weeks: u32,
+    #[serde(default)]
+    pub holidays: Vec<Holiday>,
+    #[serde(default)]
+    pub swapped_days: Vec<SwappedDay>,
+}
+
+impl AcademicCalendar {
+    /// A calendar with no holiday, when only the first Monday is known.
+    pub fn plain(
+        semester_year: &str,
+        semester_no: &str,
+        start: NaiveDate
+    ) -> Self {
+        AcademicCalendar {
+            semester_year: semester_year.to_owned(),
+            semester_no: semester_no.to_owned(),
+            start,
+            weeks: WeekSet::MAX_WEEK,
+            holidays: Vec::new(),
+            swapped_days: Vec::new(),
+        }
+    }
+
+    /// The date of `weekday` in `week` without holidays, both counted from 1.
+    pub fn date_of(&self, week: u32, weekday: u32) -> NaiveDate {
+        self.start + Duration::days((week as i64 - 1) * 7 + weekday as i64 - 1)
+    }
+
+    /// The week and the weekday of `date` without holidays.
+    fn position(&self, date: NaiveDate) -> Option<(u32, u32)> {
+        let days = (date - self.start).num_days();
+        if days < 0 || days >= self.weeks as i64 * 7 {
+            return None;
+        }
+        Some((days as u32 / 7 + 1, date.weekday().number_from_monday()))
+    }
+
+    fn holiday(&self, date: NaiveDate) -> Option<&Holiday> {
+        self.holidays.iter().find(|h| h.start <= date && date <= h.end.unwrap_or(h.start))
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.position(date).is_some() || self.swapped_days.iter().any(|s| s.date == date)
+    }
+
+    /// Which week and day schedule applies on `date`, if any.
+    pub fn day(&self, date: NaiveDate) -> CalendarDay {
+        let mut day = CalendarDay {
+            date: date.to_string(),
+            week: self.position(date).map(|(week, _)| week),
+            schedule: None,
+            holiday: None,
+            follows: None,
+        };
+        if let Some(swapped) = self.swapped_days.iter().find(|s| s.date == date) {
+            day.follows = Some(swapped.follows.to_string());
+            day.schedule = self.position(swapped.follows).map(|(week, weekday)| DaySchedule { week, weekday });
+            return day;
+        }
+        if let Some(holiday) = self.holiday(date) {
+            day.holiday = Some(holiday.name.clone());
+            return day;
+        }
+        day.schedule = self.position(date).map(|(week, weekday)| DaySchedule { week, weekday });
+        day
+    }
+
+    /// All the dates on which the classes of `weekday` in `week` are held:
+    /// its own date unless it is a holiday, and the make-up days following it.
+    pub fn dates_of(&self, week: u32, weekday: u32) -> Vec<NaiveDate> {
+        let date = self.date_of(week, weekday);
+        let mut dates = Vec::<NaiveDate>::new();
+        if self.position(date).is_some()
+            && self.holiday(date).is_none()
+            && !self.swapped_days.iter().any(|s| s.date == date) {
+            dates.push(date);
+        }
+        dates.extend(self.swapped_days.iter().filter(|s| s.follows == date).map(|s| s.date));
+        dates
+    }
+}
+
+/// The academic calendars of all the semesters, loaded once at launch.
+#[derive(Clone)]
+pub struct Calendars {
+    calendars: Arc<HashMap<String, AcademicCalendar>>,
+}
+
+fn key(semester_year: &str, semester_no: &str) -> String {
+    format!("{}-{}", semester_year, semester_no)
+}
+
+impl Calendars {
+    pub fn load(config: &CalendarConfig) -> Self {
+        let mut calendars = HashMap::<String, AcademicCalendar>::new();
+        let entries = std::fs::read_dir(Path::new(&config.directory)).into_iter().flatten().flatten();
+        for entry in entries {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let calendar = std::fs::read_to_string(&path)
+                                .map_err(|e| e.to_string())
+                                .and_then(|content| serde_json::from_str::<AcademicCalendar>(&content).map_err(|e| e.to_string()));
+            match calendar {
+                Ok(calendar) => {
+                    calendars.insert(key(&calendar.semester_year, &calendar.semester_no), calendar);
+                },
+                Err(_e) => {
+                    #[cfg(debug_assertions)]
+                    println!("Unable to load the academic calendar {}: {}", path.display(), _e);
+                }
+            }
+        }
+        Calendars { calendars: Arc::new(calendars) }
+    }
+
+    pub fn get(
+        &self,
+        semester_year: &str,
+        semester_no: &str
+    ) -> Option<&AcademicCalendar> {
+        self.calendars.get(&key(semester_year, semester_no))
+    }
+
+    /// The calendar of a semester, or one without holidays starting on
+    /// `fallback_start` when the semester has no calendar file.
+    pub fn semester(
+        &self,
+        semester_year: &str,
+        semester_no: &str,
+        fallback_start: Option<NaiveDate>
+    ) -> Option<AcademicCalendar> {
+        self.get(semester_year, semester_no)
+            .cloned()
+            .or_else(|| fallback_start.map(|start| AcademicCalendar::plain(semester_year, semester_no, start)))
+    }
+
+    /// The calendar of the semester `date` is in.
+    pub fn find(&self, date: NaiveDate) -> Option<&AcademicCalendar> {
+        self.calendars.values().find(|c| c.contains(date))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::AcademicCalendar;
+    use super::super::structures::DaySchedule;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2021, month, day).unwrap()
+    }
+
+    fn calendar() -> AcademicCalendar {
+        serde_json::from_str(r#"{
+            "semester_year": "2021-2022",
+            "semester_no": "1",
+            "start": "2021-09-06",
+            "weeks": 16,
+            "holidays": [
+                { "name": "中秋节", "start": "2021-09-20", "end": "2021-09-21" },
+                { "name": "国庆节", "start": "2021-10-01", "end": "2021-10-07" }
+            ],
+            "swapped_days": [
+                { "date": "2021-09-18", "follows": "2021-09-20" },
+                { "date": "2021-10-09", "follows": "2021-10-07" }
+            ]
+        }"#).unwrap()
+    }
+
+    #[test]
+    fn test_day() {
+        let calendar = calendar();
+        assert_eq!(calendar.day(date(9, 8)).schedule, Some(DaySchedule { week: 1, weekday: 3 }));
+        let holiday = calendar.day(date(10, 4));
+        assert_eq!((holiday.week, holiday.schedule, holiday.holiday.as_deref()), (Some(5), None, Some("国庆节")));
+        let swapped = calendar.day(date(10, 9));
+        assert_eq!(swapped.schedule, Some(DaySchedule { week: 5, weekday: 4 }));
+        assert_eq!(swapped.follows.as_deref(), Some("2021-10-07"));
+        assert_eq!(calendar.day(date(9, 12)).schedule, Some(DaySchedule { week: 1, weekday: 7 }));
+        assert_eq!(calendar.day(date(12, 27)).schedule, None);
+        assert_eq!(calendar.day(date(9, 1)).week, None);
+    }
+
+    #[test]
+    fn test_dates_of() {
+        let calendar = calendar();
+        assert_eq!(calendar.dates_of(1, 1), vec![date(9, 6)]);
+        assert_eq!(calendar.dates_of(3, 1), vec![date(9, 18)]);
+        assert_eq!(calendar.dates_of(4, 5), Vec::<NaiveDate>::new());
+        assert_eq!(calendar.dates_of(2, 6), Vec::<NaiveDate>::new());
+        assert_eq!(calendar.dates_of(17, 1), Vec::<NaiveDate>::new());
+    }
+}
@@ -0,0 +1,571 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::DateTime;
+use futures::lock::Mutex;
+use rocket::response::status::Unauthorized;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio;
+
+use super::courses::{fetch_course_detail, fetch_course_list};
+use super::structures::*;
+use super::throttle::{Throttle, ThrottleConfig};
+use super::urls::{COURSES_URL, ENGLISH_COURSES_URL};
+
+/// Seconds before a failed refresh is tried again.
+const RETRY_INTERVAL: u64 = 300;
+/// Courses whose detail pages are read before the catalogue is saved.
+const DETAIL_BATCH: usize = 20;
+/// GB2312 codes where the level 1 characters, which are sorted by pinyin,
+/// start with each initial.
+const GB2312_INITIALS: [(u16, char); 23] = [
+    (0xB0A1, 'a'), (0xB0C5, 'b'), (0xB2C1, 'c'), (0xB4EE, 'd'), (0xB6EA, 'e'), (0xB7A2, 'f'),
+    (0xB8C1, 'g'), (0xB9FE, 'h'), (0xBBF7, 'j'), (0xBFA6, 'k'), (0xC0AC, 'l'), (0xC2E8, 'm'),
+    (0xC4C3, 'n'), (0xC5B6, 'o'), (0xC5BE, 'p'), (0xC6DA, 'q'), (0xC8BB, 'r'), (0xC8F6, 's'),
+    (0xCBFA, 't'), (0xCDDA, 'w'), (0xCEF4, 'x'), (0xD1B9, 'y'), (0xD4D1, 'z'),
+];
+const GB2312_LEVEL1_END: u16 = 0xD7F9;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CatalogueConfig {
+    /// Seconds between two downloads of the TAO course list.
+    pub refresh_interval: u64,
+    /// JSON file the catalogue is kept in across restarts, memory only if unset.
+    pub cache_file: Option<String>,
+    /// Whether the detail pages of the courses are read in the background.
+    pub fetch_details: bool,
+    /// The minimum gap between two detail pages downloaded from TAO.
+    pub detail_interval_ms: u64,
+    /// Seconds before the detail pages of a course are read again.
+    pub detail_refresh_interval: u64,
+}
+
+impl Default for CatalogueConfig {
+    fn default() -> Self {
+        CatalogueConfig {
+            refresh_interval: 86400,
+            cache_file: None,
+            fetch_details: true,
+            detail_interval_ms: 2000,
+            detail_refresh_interval: 30 * 86400,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CatalogueData {
+    /// RFC 3339 time of the download.
+    fetched_at: Option<String>,
+    courses: Vec<CatalogCourse>,
+}
+
+/// A course with the keys it is searched by.
+struct Entry {
+    course: CatalogCourse,
+    code: String,
+    name: String,
+    english: String,
+    initials: String,
+}
+
+struct Indexed {
+    fetched_at: Option<String>,
+    entries: Vec<Entry>,
+}
+
+struct CatalogueInner {
+    refresh_interval: Duration,
+    cache_file: Option<String>,
+    fetch_details: bool,
+    detail_refresh_interval: Duration,
+    /// Keeps the detail pages apart, TAO being a different site from TIS.
+    throttle: Throttle,
+    indexed: Mutex<Arc<Indexed>>,
+    /// Set while the last download failed.
+    failing: AtomicBool,
+    running: AtomicBool,
+}
+
+/// The TAO course list, downloaded again every `refresh_interval` and
+/// searched in memory. When TAO cannot be reached the last list is served,
+/// flagged as stale. The English list gives the English names, and the
+/// detail pages of each course, read slowly in the background, give the
+/// hours, descriptions and prerequisites.
+#[derive(Clone)]
+pub struct Catalogue {
+    inner: Arc<CatalogueInner>,
+}
+
+/// Search and filters of the catalogue, all of them optional.
+#[derive(Default)]
+pub struct CatalogueQuery<'a> {
+    /// Matched against the course code, the Chinese and English names and the
+    /// pinyin initials of the name, exactly or fuzzily.
+    pub text: Option<&'a str>,
+    /// Part of the department name, in Chinese or English.
+    pub department: Option<&'a str>,
+    pub min_credits: Option<f32>,
+    pub max_credits: Option<f32>,
+    /// Start of the course code, such as `CS`.
+    pub prefix: Option<&'a str>,
+}
+
+/// The pinyin initials of the level 1 characters of GB2312, which hold the
+/// common ones. Other characters are kept as they are, lowercased.
+pub fn pinyin_initials(text: &str) -> String {
+    let mut initials = String::new();
+    let mut buffer = [0u8; 4];
+    for c in text.chars() {
+        if c.is_ascii() {
+            initials.extend(c.to_lowercase());
+            continue;
+        }
+        let (bytes, _, unmappable) = encoding_rs::GBK.encode(c.encode_utf8(&mut buffer));
+        let initial = match (unmappable, &bytes[..]) {
+            (false, [high, low]) => {
+                let code = u16::from_be_bytes([*high, *low]);
+                GB2312_INITIALS.iter()
+                    .rev()
+                    .find(|(start, _)| code >= *start)
+                    .filter(|_| code <= GB2312_LEVEL1_END)
+                    .map(|(_, initial)| *initial)
+            },
+            _ => None
+        };
+        initials.push(initial.unwrap_or(c));
+    }
+    initials
+}
+
+/// Whether the characters of `query` appear in `text` in order, with how
+/// much of `text` they cover.
+fn subsequence(query: &str, text: &str) -> Option<f64> {
+    let mut chars = text.chars();
+    for q in query.chars() {
+        chars.find(|c| *c == q)?;
+    }
+    Some(query.chars().count() as f64 / text.chars().count().max(1) as f64)
+}
+
+/// How well `query` matches a course, up to 100.
+fn score(query: &str, entry: &Entry) -> Option<f64> {
+    let keys = [(&entry.code, 100.0), (&entry.name, 95.0), (&entry.english, 95.0), (&entry.initials, 75.0)];
+    let mut best: Option<f64> = None;
+    for (key, weight) in keys.iter() {
+        let score = if *key == query {
+            Some(*weight)
+        } else if key.starts_with(query) {
+            Some(weight * 0.9)
+        } else if key.contains(query) {
+            Some(weight * 0.8)
+        } else {
+            subsequence(query, key).map(|coverage| weight * (0.4 + 0.2 * coverage))
+        };
+        best = match (best, score) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b)
+        };
+    }
+    best
+}
+
+fn index(data: CatalogueData) -> Indexed {
+    let entries = data.courses
+                    .into_iter()
+                    .map(|course| Entry {
+                        code: course.course_id.to_lowercase(),
+                        name: course.course_name.to_lowercase(),
+                        english: course.english_name.as_deref().unwrap_or_default().to_lowercase(),
+                        initials: pinyin_initials(&course.course_name),
+                        course,
+                    })
+                    .collect();
+    Indexed { fetched_at: data.fetched_at, entries }
+}
+
+fn data(indexed: &Indexed) -> CatalogueData {
+    CatalogueData {
+        fetched_at: indexed.fetched_at.clone(),
+        courses: indexed.entries.iter().map(|e| e.course.clone()).collect(),
+    }
+}
+
+/// Add the names, departments and links of the English list to the courses
+/// of the Chinese one.
+fn merge_english(courses: &mut [CatalogCourse], english: Vec<CatalogCourse>) {
+    for english in english {
+        if let Some(course) = courses.iter_mut().find(|c| c.course_id == english.course_id) {
+            course.english_name = Some(english.course_name).filter(|name| !name.is_empty());
+            course.english_department = Some(english.department).filter(|name| !name.is_empty());
+            course.english_detail_url = english.detail_url;
+        }
+    }
+}
+
+/// Keep what the detail pages told about the courses of a new list, so
+/// that they are not all read again.
+fn carry_details(courses: &mut [CatalogCourse], previous: &Indexed) {
+    for course in courses.iter_mut() {
+        let old = match previous.entries.iter().find(|e| e.course.course_id == course.course_id) {
+            Some(entry) if entry.course.details_fetched_at.is_some() => &entry.course,
+            _ => continue
+        };
+        course.english_name = course.english_name.take().or_else(|| old.english_name.clone());
+        course.hours = course.hours.or(old.hours);
+        course.description = old.description.clone();
+        course.english_description = old.english_description.clone();
+        course.prerequisites = old.prerequisites.clone();
+        course.english_prerequisites = old.english_prerequisites.clone();
+        course.details_fetched_at = old.details_fetched_at.clone();
+    }
+}
+
+/// Fill a course with its Chinese and English detail pages.
+fn apply_details(course: &mut CatalogCourse, chinese: CourseDetail, english: Option<CourseDetail>) {
+    course.english_name = course.english_name.take().or(chinese.english_name);
+    course.hours = course.hours.or(chinese.hours);
+    course.description = chinese.description;
+    course.prerequisites = chinese.prerequisites;
+    if let Some(english) = english {
+        course.hours = course.hours.or(english.hours);
+        course.english_description = english.description;
+        course.english_prerequisites = english.prerequisites;
+    }
+}
+
+fn search(indexed: &Indexed, query: &CatalogueQuery, limit: usize) -> (usize, Vec<CatalogueMatch>) {
+    let text = query.text
+                    .map(|text| text.split_whitespace().collect::<String>().to_lowercase())
+                    .filter(|text| !text.is_empty());
+    let prefix = query.prefix.map(|prefix| prefix.trim().to_lowercase());
+    let mut matches: Vec<(f64, &Entry)> = indexed.entries
+                                            .iter()
+                                            .filter(|e| prefix.as_ref().is_none_or(|p| e.code.starts_with(p.as_str())))
+                                            .filter(|e| query.department.is_none_or(|d| {
+                                                let d = d.trim().to_lowercase();
+                                                e.course.department.contains(&d)
+                                                    || e.course.english_department.as_ref().is_some_and(|e| e.to_lowercase().contains(&d))
+                                            }))
+                                            .filter(|e| query.min_credits.is_none_or(|min| e.course.credits >= min))
+                                            .filter(|e| query.max_credits.is_none_or(|max| e.course.credits <= max))
+                                            .filter_map(|e| match &text {
+                                                Some(text) => score(text, e).map(|score| (score, e)),
+                                                None => Some((100.0, e))
+                                            })
+                                            .collect();
+    matches.sort_by(|(a_score, a), (b_score, b)| {
+        b_score.partial_cmp(a_score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.code.cmp(&b.code))
+    });
+    let total = matches.len();
+    let matches = matches.into_iter()
+                    .take(limit)
+                    .map(|(score, e)| CatalogueMatch { course: e.course.clone(), score })
+                    .collect();
+    (total, matches)
+}
+
+impl Catalogue {
+    pub fn load(config: &CatalogueConfig) -> Self {
+        let data = config.cache_file
+                        .as_ref()
+                        .and_then(|file| std::fs::read_to_string(file).ok())
+                        .and_then(|content| serde_json::from_str::<CatalogueData>(&content).ok())
+                        .unwrap_or_default();
+        Catalogue {
+            inner: Arc::new(CatalogueInner {
+                refresh_interval: Duration::from_secs(config.refresh_interval),
+                cache_file: config.cache_file.clone(),
+                fetch_details: config.fetch_details,
+                detail_refresh_interval: Duration::from_secs(config.detail_refresh_interval),
+                throttle: Throttle::new(&ThrottleConfig { min_interval_ms: config.detail_interval_ms }),
+                indexed: Mutex::new(Arc::new(index(data))),
+                failing: AtomicBool::new(false),
+                running: AtomicBool::new(false),
+            })
+        }
+    }
+
+    /// Spawn the refresh loop, at most once.
+    pub fn start(&self) {
+        if self.inner.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let catalogue = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let started = tokio::time::Instant::now();
+                let wait = match catalogue.age().await {
+                    Some(age) if age < catalogue.inner.refresh_interval => catalogue.inner.refresh_interval - age,
+                    _ => match catalogue.refresh().await {
+                        Ok(()) => catalogue.inner.refresh_interval,
+                        Err(_) => Duration::from_secs(RETRY_INTERVAL)
+                    }
+                };
+                if catalogue.inner.fetch_details {
+                    catalogue.fetch_details().await;
+                }
+                tokio::time::sleep(wait.saturating_sub(started.elapsed())).await;
+            }
+        });
+    }
+
+    async fn age(&self) -> Option<Duration> {
+        let indexed = self.inner.indexed.lock().await.clone();
+        let fetched_at = DateTime::parse_from_rfc3339(indexed.fetched_at.as_ref()?).ok()?;
+        (chrono::Local::now().signed_duration_since(fetched_at)).to_std().ok()
+    }
+
+    async fn save(&self, data: &CatalogueData) {
+        if let Some(file) = &self.inner.cache_file {
+            if let Ok(content) = serde_json::to_string(data) {
+                // The catalogue still works from memory if it cannot be saved.
+                let _ = tokio::fs::write(file, content).await;
+            }
+        }
+    }
+
+    /// Download the course list again, keeping the current one on failure.
+    /// The English list is optional, the Chinese one gives the courses.
+    pub async fn refresh(&self) -> Result<(), Unauthorized<String>> {
+        let mut courses = match fetch_course_list(COURSES_URL).await {
+            Ok(courses) => courses,
+            Err(e) => {
+                self.inner.failing.store(true, Ordering::SeqCst);
+                return Err(e);
+            }
+        };
+        if let Ok(english) = fetch_course_list(ENGLISH_COURSES_URL).await {
+            merge_english(&mut courses, english);
+        }
+        let mut indexed = self.inner.indexed.lock().await;
+        carry_details(&mut courses, &indexed);
+        let data = CatalogueData {
+            fetched_at: Some(chrono::Local::now().to_rfc3339()),
+            courses,
+        };
+        self.save(&data).await;
+        *indexed = Arc::new(index(data));
+        self.inner.failing.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn details_due(&self, course: &CatalogCourse) -> bool {
+        course.detail_url.is_some() && course.details_fetched_at
+                                            .as_ref()
+                                            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+                                            .and_then(|at| chrono::Local::now().signed_duration_since(at).to_std().ok())
+                                            .is_none_or(|age| age > self.inner.detail_refresh_interval)
+    }
+
+    /// Read the Chinese and English detail pages of a course.
+    async fn course_details(&self, course: &CatalogCourse) -> Result<CatalogCourse, Unauthorized<String>> {
+        let mut course = course.clone();
+        if let Some(url) = &course.detail_url {
+            self.inner.throttle.wait().await;
+            let chinese = fetch_course_detail(url).await?;
+            let english = match &course.english_detail_url {
+                Some(url) => {
+                    self.inner.throttle.wait().await;
+                    fetch_course_detail(url).await.ok()
+                },
+                None => None
+            };
+            apply_details(&mut course, chinese, english);
+        }
+        course.details_fetched_at = Some(chrono::Local::now().to_rfc3339());
+        Ok(course)
+    }
+
+    /// Put courses read again into the catalogue and save it.
+    async fn update(&self, courses: Vec<CatalogCourse>) {
+        let mut indexed = self.inner.indexed.lock().await;
+        let mut data = data(&indexed);
+        for course in courses {
+            if let Some(old) = data.courses.iter_mut().find(|c| c.course_id == course.course_id) {
+                *old = course;
+            }
+        }
+        self.save(&data).await;
+        *indexed = Arc::new(index(data));
+    }
+
+    /// Read the detail pages of the courses which have not been read for
+    /// `detail_refresh_interval`, saving the catalogue after each batch. It
+    /// waits `RETRY_INTERVAL` after a page which cannot be downloaded, then
+    /// carries on with the rest.
+    pub async fn fetch_details(&self) {
+        loop {
+            let indexed = self.inner.indexed.lock().await.clone();
+            let due: Vec<CatalogCourse> = indexed.entries
+                                            .iter()
+                                            .map(|e| &e.course)
+                                            .filter(|course| self.details_due(course))
+                                            .take(DETAIL_BATCH)
+                                            .cloned()
+                                            .collect();
+            if due.is_empty() {
+                return;
+            }
+            let mut read = Vec::new();
+            let mut failed = false;
+            for mut course in due {
+                match self.course_details(&course).await {
+                    Ok(course) => read.push(course),
+                    Err(_) => {
+                        // Tried again after `detail_refresh_interval`, so that
+                        // a broken page does not hold up the others.
+                        course.details_fetched_at = Some(chrono::Local::now().to_rfc3339());
+                        read.push(course);
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+            self.update(read).await;
+            if failed {
+                tokio::time::sleep(Duration::from_secs(RETRY_INTERVAL)).await;
+            }
+        }
+    }
+
+    /// A course of the catalogue, with its detail pages read first if they
+    /// have not been yet.
+    pub async fn course(&self, course_id: &str) -> Result<CatalogCourse, Unauthorized<String>> {
+        let course = self.indexed()
+                        .await?
+                        .entries
+                        .iter()
+                        .map(|e| &e.course)
+                        .find(|course| course.course_id.eq_ignore_ascii_case(course_id.trim()))
+                        .cloned()
+                        .ok_or_else(|| Unauthorized(Some("Unable to find the course in the catalogue".to_owned())))?;
+        if course.details_fetched_at.is_some() || course.detail_url.is_none() {
+            return Ok(course);
+        }
+        let course = self.course_details(&course).await?;
+        self.update(vec![course.clone()]).await;
+        Ok(course)
+    }
+
+    /// The catalogue, downloaded first if there is none yet.
+    async fn indexed(&self) -> Result<Arc<Indexed>, Unauthorized<String>> {
+        let indexed = self.inner.indexed.lock().await.clone();
+        if !indexed.entries.is_empty() {
+            return Ok(indexed);
+        }
+        self.refresh().await?;
+        Ok(self.inner.indexed.lock().await.clone())
+    }
+
+    pub async fn courses(&self) -> Result<Vec<Course>, Unauthorized<String>> {
+        Ok(self.indexed().await?.entries.iter().map(|e| Course::from(&e.course)).collect())
+    }
+
+    pub async fn catalog_courses(&self) -> Result<Vec<CatalogCourse>, Unauthorized<String>> {
+        Ok(self.indexed().await?.entries.iter().map(|e| e.course.clone()).collect())
+    }
+
+    pub async fn search(
+        &self,
+        query: &CatalogueQuery<'_>,
+        limit: usize,
+    ) -> Result<CatalogueSearch, Unauthorized<String>> {
+        let indexed = self.indexed().await?;
+        let expired = self.age().await.is_none_or(|age| age > self.inner.refresh_interval);
+        let (total, courses) = search(&indexed, query, limit);
+        Ok(CatalogueSearch {
+            fetched_at: indexed.fetched_at.clone(),
+            stale: expired || self.inner.failing.load(Ordering::SeqCst),
+            total,
+            courses,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::structures::{CatalogCourse, CourseDetail};
+    use super::{apply_details, carry_details, index, merge_english, pinyin_initials, search, CatalogueData, CatalogueQuery};
+
+    fn course(course_id: &str, course_name: &str, credits: f32, department: &str) -> CatalogCourse {
+        CatalogCourse {
+            course_id: course_id.to_owned(),
+            course_name: course_name.to_owned(),
+            credits,
+            department: department.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pinyin_initials() {
+        assert_eq!(pinyin_initials("高等数学(上)A"), "gdsx(s)a");
+        assert_eq!(pinyin_initials("计算机程序设计基础"), "jsjcxsjjc");
+    }
+
+    #[test]
+    fn test_search() {
+        let indexed = index(CatalogueData {
+            fetched_at: None,
+            courses: vec![
+                course("MA101B", "高等数学(上)A", 4.0, "数学系"),
+                course("MA102B", "高等数学(下)A", 4.0, "数学系"),
+                course("CS102A", "计算机程序设计基础A", 3.0, "计算机科学与工程系"),
+                course("CS205", "C/C++程序设计", 3.0, "计算机科学与工程系"),
+                course("PE101", "体育I", 1.0, "体育中心"),
+            ],
+        });
+        let codes = |query: &CatalogueQuery| -> Vec<String> {
+            search(&indexed, query, 10).1.into_iter().map(|m| m.course.course_id).collect()
+        };
+        assert_eq!(codes(&CatalogueQuery { text: Some("ma101b"), ..Default::default() })[0], "MA101B");
+        assert_eq!(codes(&CatalogueQuery { text: Some("高数"), ..Default::default() }), vec!["MA101B", "MA102B"]);
+        assert_eq!(codes(&CatalogueQuery { text: Some("gdsx"), ..Default::default() }), vec!["MA101B", "MA102B"]);
+        assert_eq!(codes(&CatalogueQuery { text: Some("程序设计"), ..Default::default() }), vec!["CS102A", "CS205"]);
+        assert_eq!(codes(&CatalogueQuery { prefix: Some("cs"), min_credits: Some(3.0), ..Default::default() }), vec!["CS102A", "CS205"]);
+        assert_eq!(codes(&CatalogueQuery { department: Some("体育"), max_credits: Some(2.0), ..Default::default() }), vec!["PE101"]);
+        assert!(codes(&CatalogueQuery { text: Some("量子力学"), ..Default::default() }).is_empty());
+    }
+
+    #[test]
+    fn test_english_and_details() {
+        let mut courses = vec![course("MA101B", "高等数学(上)A", 4.0, "数学系"), course("PE101", "体育I", 1.0, "体育中心")];
+        courses[0].detail_url = Some("https://tao/zh?kcdm=MA101B".to_owned());
+        let mut english = course("MA101B", "Calculus I A", 4.0, "Department of Mathematics");
+        english.detail_url = Some("https://tao/en?kcdm=MA101B".to_owned());
+        merge_english(&mut courses, vec![english]);
+        assert_eq!(courses[0].english_name.as_deref(), Some("Calculus I A"));
+        assert_eq!(courses[0].english_detail_url.as_deref(), Some("https://tao/en?kcdm=MA101B"));
+        assert_eq!(courses[1].english_name, None);
+
+        let chinese = CourseDetail {
+            hours: Some(64.0),
+            description: Some("一元函数微积分。".to_owned()),
+            prerequisites: Some("无".to_owned()),
+            ..Default::default()
+        };
+        let english = CourseDetail { description: Some("Calculus of one variable.".to_owned()), ..Default::default() };
+        apply_details(&mut courses[0], chinese, Some(english));
+        courses[0].details_fetched_at = Some("2026-09-01T00:00:00+08:00".to_owned());
+        assert_eq!(courses[0].hours, Some(64.0));
+        assert_eq!(courses[0].english_description.as_deref(), Some("Calculus of one variable."));
+
+        let indexed = index(CatalogueData { fetched_at: None, courses: courses.clone() });
+        let mut fresh = vec![course("MA101B", "高等数学(上)A", 4.0, "数学系")];
+        carry_details(&mut fresh, &indexed);
+        assert_eq!(fresh[0].description.as_deref(), Some("一元函数微积分。"));
+        assert_eq!(fresh[0].english_name.as_deref(), Some("Calculus I A"));
+        assert_eq!(fresh[0].details_fetched_at, courses[0].details_fetched_at);
+
+        let codes: Vec<String> = search(&indexed, &CatalogueQuery { text: Some("calculus"), ..Default::default() }, 10).1
+                                    .into_iter()
+                                    .map(|m| m.course.course_id)
+                                    .collect();
+        assert_eq!(codes, vec!["MA101B"]);
+        assert_eq!(search(&indexed, &CatalogueQuery { department: Some("mathematics"), ..Default::default() }, 10).0, 1);
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+
+use rocket::serde::Deserialize;
+
+use super::grades::{mark_attempts, score, RetakePolicy, LETTER_GRADES};
+use super::prerequisites::is_passed;
+use super::structures::*;
+
+/// The scores from `min_score` up to the next band, and what they convert to.
+#[derive(Deserialize, Clone)]
+pub struct ScaleBand {
+    pub min_score: f64,
+    pub point: f64,
+    pub letter: Option<String>,
+}
+
+/// A table converting percentage scores into another scale.
+#[derive(Deserialize, Clone)]
+pub struct ScaleTable {
+    pub name: String,
+    pub description: String,
+    pub bands: Vec<ScaleBand>,
+    /// Whether a letter grade from TIS which is the letter of a band is
+    /// taken as it is, before the score.
+    #[serde(default)]
+    pub use_letters: bool,
+}
+
+impl ScaleTable {
+    fn band(&self, grade: &CourseGrade, percentage: f64) -> Option<&ScaleBand> {
+        let level = grade.final_level.trim();
+        if self.use_letters {
+            if let Some(band) = self.bands.iter().find(|band| band.letter.as_deref() == Some(level)) {
+                return Some(band);
+            }
+        }
+        self.bands
+            .iter()
+            .filter(|band| percentage >= band.min_score)
+            .max_by(|a, b| a.min_score.partial_cmp(&b.min_score).unwrap_or(std::cmp::Ordering::Equal))
+    }
+
+    /// The bands from the highest down, such as `90 and above: 4 (A)`.
+    fn rules(&self) -> Vec<String> {
+        let mut bands: Vec<&ScaleBand> = self.bands.iter().collect();
+        bands.sort_by(|a, b| b.min_score.partial_cmp(&a.min_score).unwrap_or(std::cmp::Ordering::Equal));
+        let mut rules: Vec<String> = bands.iter()
+                                        .map(|band| match &band.letter {
+                                            Some(letter) => format!("{} and above: {} ({})", band.min_score, band.point, letter),
+                                            None => format!("{} and above: {}", band.min_score, band.point)
+                                        })
+                                        .collect();
+        if self.use_letters {
+            rules.push("A letter grade from TIS which is the letter of a band takes that band, whatever the score".to_owned());
+        }
+        rules
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ConversionConfig {
+    pub scales: Vec<ScaleTable>,
+    /// The percentage taken for a grade with a letter but no score.
+    pub letter_scores: BTreeMap<String, f64>,
+    /// Course types of the courses of the major, for the major average.
+    pub major_course_types: Vec<String>,
+    /// Whether failed courses count in the averages.
+    pub include_failed: bool,
+}
+
+fn band(min_score: f64, point: f64, letter: &str) -> ScaleBand {
+    ScaleBand { min_score, point, letter: Some(letter.to_owned()) }
+}
+
+impl Default for ConversionConfig {
+    fn default() -> Self {
+        ConversionConfig {
+            scales: vec![
+                ScaleTable {
+                    name: "sustech".to_owned(),
+                    description: "The 4.0 scale of SUSTech, as on the official transcript".to_owned(),
+                    bands: LETTER_GRADES.iter().map(|(letter, point, min)| band(*min, *point, letter)).collect(),
+                    use_letters: true,
+                },
+                ScaleTable {
+                    name: "standard_4".to_owned(),
+                    description: "The standard 4.0 scale, by tens of percentage points".to_owned(),
+                    bands: vec![band(90.0, 4.0, "A"), band(80.0, 3.0, "B"), band(70.0, 2.0, "C"), band(60.0, 1.0, "D"), band(0.0, 0.0, "F")],
+                    use_letters: false,
+                },
+                ScaleTable {
+                    name: "wes".to_owned(),
+                    description: "The conversion of WES for percentage grades from China".to_owned(),
+                    bands: vec![band(85.0, 4.0, "A"), band(75.0, 3.0, "B"), band(60.0, 2.0, "C"), band(0.0, 0.0, "F")],
+                    use_letters: false,
+                },
+            ],
+            letter_scores: LETTER_GRADES.iter().map(|(letter, _, min)| ((*letter).to_owned(), *min)).collect(),
+            major_course_types: vec!["专业必修".to_owned(), "专业选修".to_owned(), "专业核心".to_owned(), "专业基础".to_owned()],
+            include_failed: true,
+        }
+    }
+}
+
+/// The percentage of a grade, from its score or else its letter, and which
+/// of them it comes from.
+fn percentage(grade: &CourseGrade, config: &ConversionConfig) -> Option<(f64, &'static str)> {
+    score(grade).map(|score| (score, "score"))
+        .or_else(|| config.letter_scores.get(grade.final_level.trim()).map(|score| (*score, "letter")))
+}
+
+/// The credit-weighted average of some values, with the credits.
+fn weighted(values: &[(f64, f64)]) -> (f64, Option<f64>) {
+    let credits: f64 = values.iter().map(|(credits, _)| credits).sum();
+    let average = if credits > 0.0 {
+        Some(values.iter().map(|(credits, value)| credits * value).sum::<f64>() / credits)
+    } else {
+        None
+    };
+    (credits, average)
+}
+
+/// Convert the counted grades into percentage averages and the scales of
+/// `scales`, with every course used or left out, and the rules applied.
+pub fn transcript_summary(grades: &[CourseGrade], policy: RetakePolicy, config: &ConversionConfig, scales: &[&ScaleTable]) -> TranscriptSummary {
+    let mut grades = grades.to_vec();
+    mark_attempts(&mut grades, policy);
+
+    let mut courses = Vec::<ConvertedCourse>::new();
+    let mut excluded = Vec::<ExcludedGrade>::new();
+    for grade in grades.iter() {
+        let converted = percentage(grade, config);
+        let reason = if !grade.counted {
+            grade.excluded_reason.clone()
+        } else if converted.is_none() {
+            Some("Neither a score nor a letter grade, such as a pass".to_owned())
+        } else if !config.include_failed && !is_passed(grade) {
+            Some("Failed, and failed courses are left out".to_owned())
+        } else {
+            None
+        };
+        match (reason, converted) {
+            (None, Some((percentage, source))) => courses.push(ConvertedCourse {
+                code: grade.code.trim().to_owned(),
+                name: grade.name.clone(),
+                semester: grade.semester.clone(),
+                credit: grade.credit,
+                course_type: grade.course_type.clone(),
+                final_grade: grade.final_grade.clone(),
+                final_level: grade.final_level.clone(),
+                percentage,
+                source: source.to_owned(),
+                major: config.major_course_types.iter().any(|course_type| grade.course_type.trim() == course_type),
+                points: scales.iter()
+                            .filter_map(|scale| Some((scale.name.clone(), scale.band(grade, percentage)?.point)))
+                            .collect(),
+            }),
+            (reason, _) => excluded.push(ExcludedGrade {
+                code: grade.code.trim().to_owned(),
+                name: grade.name.clone(),
+                semester: grade.semester.clone(),
+                final_grade: grade.final_grade.clone(),
+                final_level: grade.final_level.clone(),
+                reason: reason.unwrap_or_default(),
+            })
+        }
+    }
+
+    let percentages: Vec<(f64, f64)> = courses.iter().map(|course| (course.credit, course.percentage)).collect();
+    let (credits, percentage_average) = weighted(&percentages);
+    let major: Vec<(f64, f64)> = courses.iter().filter(|course| course.major).map(|course| (course.credit, course.percentage)).collect();
+    let (major_credits, major_average) = weighted(&major);
+    let scales = scales.iter()
+                    .map(|scale| {
+                        let points = |major_only: bool| -> Vec<(f64, f64)> {
+                            courses.iter()
+                                .filter(|course| !major_only || course.major)
+                                .filter_map(|course| Some((course.credit, *course.points.get(&scale.name)?)))
+                                .collect()
+                        };
+                        let (credits, gpa) = weighted(&points(false));
+                        ScaleSummary {
+                            name: scale.name.clone(),
+                            description: scale.description.clone(),
+                            credits,
+                            gpa,
+                            major_gpa: weighted(&points(true)).1,
+                            rules: scale.rules(),
+                        }
+                    })
+                    .collect();
+
+    let letter_scores: Vec<String> = config.letter_scores.iter().map(|(letter, score)| format!("{} = {}", letter, score)).collect();
+    let rules = vec![
+        format!("One attempt of each course counts, the {} one", policy.name()),
+        "The percentage of a course is its score, or else comes from its letter grade".to_owned(),
+        format!("Letter grades without a score: {}", letter_scores.join(", ")),
+        if config.include_failed { "Failed courses count".to_owned() } else { "Failed courses are left out".to_owned() },
+        "Averages and GPAs are weighted by credits".to_owned(),
+        format!("Courses of the major are those of type {}", config.major_course_types.join(", ")),
+    ];
+
+    TranscriptSummary {
+        credits,
+        percentage_average,
+        arithmetic_average: if courses.is_empty() {
+            None
+        } else {
+            Some(courses.iter().map(|course| course.percentage).sum::<f64>() / courses.len() as f64)
+        },
+        major_credits,
+        major_average,
+        scales,
+        rules,
+        courses,
+        excluded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grades::RetakePolicy;
+    use super::super::test_util::course_grade;
+    use super::{transcript_summary, ConversionConfig};
+
+    #[test]
+    fn test_transcript_summary() {
+        let grades = vec![
+            course_grade("CS203", 3.0, "2021-2022学年第2学期", "55", "F", "专业必修"),
+            course_grade("CS203", 3.0, "2022-2023学年第1学期", "88", "B+", "专业必修"),
+            course_grade("MA101B", 4.0, "2021-2022学年第1学期", "", "A-", "通识必修"),
+            course_grade("CS205", 3.0, "2022-2023学年第1学期", "78", "C+", "专业选修"),
+            course_grade("PE101", 1.0, "2021-2022学年第1学期", "", "P", "体育"),
+        ];
+        let config = ConversionConfig::default();
+        let scales: Vec<_> = config.scales.iter().collect();
+        let summary = transcript_summary(&grades, RetakePolicy::Latest, &config, &scales);
+
+        assert_eq!(summary.courses.iter().map(|course| course.code.as_str()).collect::<Vec<_>>(), vec!["CS203", "MA101B", "CS205"]);
+        assert_eq!(summary.courses[1].percentage, 90.0);
+        assert_eq!(summary.courses[1].source, "letter");
+        assert_eq!(summary.excluded.len(), 2);
+        assert_eq!(summary.excluded[1].code, "PE101");
+        assert_eq!(summary.credits, 10.0);
+        assert!((summary.percentage_average.unwrap() - (88.0 * 3.0 + 90.0 * 4.0 + 78.0 * 3.0) / 10.0).abs() < 1e-9);
+        assert_eq!(summary.major_credits, 6.0);
+        assert_eq!(summary.major_average, Some(83.0));
+
+        let sustech = &summary.scales[0];
+        assert!((sustech.gpa.unwrap() - (3.73 * 3.0 + 3.85 * 4.0 + 3.09 * 3.0) / 10.0).abs() < 1e-9);
+        let wes = &summary.scales[2];
+        assert_eq!(wes.gpa, Some((4.0 * 3.0 + 4.0 * 4.0 + 3.0 * 3.0) / 10.0));
+        assert_eq!(wes.major_gpa, Some(3.5));
+        assert_eq!(wes.rules[0], "85 and above: 4 (A)");
+    }
+}
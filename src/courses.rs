@@ -0,0 +1,445 @@
+use rocket::response::status::Unauthorized;
+
+use super::parser::{parse_catalog_list, parse_conflict_courses, parse_course_detail, parse_course_info, parse_course_table_info, parse_table_key};
+use super::planner::clashing_slots;
+use super::selection::course_type_code;
+use super::structures::*;
+use super::urls::*;
+
+/// Why a request to TIS failed, for the background subsystems which have to
+/// tell an expired session from a failure worth trying again.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FetchError {
+    /// TIS sent the CAS login page back, the user has to log in again.
+    SessionExpired,
+    /// The request could not be sent or timed out.
+    Network(String),
+    /// The response was not what was expected.
+    Parse(String),
+}
+
+impl FetchError {
+    pub fn message(&self) -> String {
+        match self {
+            FetchError::SessionExpired => "The TIS session has expired, log in again".to_owned(),
+            FetchError::Network(message) | FetchError::Parse(message) => message.clone(),
+        }
+    }
+}
+
+impl From<FetchError> for Unauthorized<String> {
+    fn from(error: FetchError) -> Self {
+        Unauthorized(Some(error.message()))
+    }
+}
+
+/// Send a request to TIS and read the JSON it answers. An expired session
+/// is redirected to the login page of CAS, which is not JSON.
+pub async fn send_json(request: reqwest::RequestBuilder) -> Result<serde_json::Value, FetchError> {
+    let response = request.send()
+                        .await
+                        .map_err(|e| FetchError::Network(format!("Unable to reach TIS: {}", e)))?;
+    let on_cas = response.url().host_str() == reqwest::Url::parse(LOGIN_URL).ok().as_ref().and_then(|url| url.host_str());
+    let text = response.text()
+                    .await
+                    .map_err(|e| FetchError::Network(format!("Unable to read the response of TIS: {}", e)))?;
+    match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(value) => Ok(value),
+        Err(_) if on_cas || text.contains("name=\"execution\"") => Err(FetchError::SessionExpired),
+        Err(e) => Err(FetchError::Parse(format!("Unable to parse the response of TIS: {}", e)))
+    }
+}
+
+fn str_field<'a>(value: &'a serde_json::Value, key: &str) -> Result<&'a str, FetchError> {
+    value[key].as_str().ok_or_else(|| FetchError::Parse(format!("Unable to find {} in a course", key)))
+}
+
+fn parsed_field<T: std::str::FromStr>(value: &serde_json::Value, key: &str) -> Result<T, FetchError> {
+    let field = str_field(value, key)?;
+    field.trim().parse::<T>().map_err(|_| FetchError::Parse(format!("Unable to parse {} {} of a course", key, field)))
+}
+
+/// A number TIS sends either as a JSON number or as a string, such as the
+/// credits of a course which may be `2.5` or `"2.5"`.
+fn number_field(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(text) => text.trim().parse::<f64>().ok(),
+        _ => None
+    }
+}
+
+/// The fields shared by the selected and the available classes.
+fn parse_advanced_course(value: &serde_json::Value) -> Result<AdvancedCourse, FetchError> {
+    Ok(AdvancedCourse {
+        basic_course: Course {
+            course_id: str_field(value, "kcdm")?.to_owned(),
+            course_name: str_field(value, "kcmc")?.to_owned(),
+            credits: parsed_field::<f32>(value, "xf")?,
+            department: str_field(value, "kkyxmc")?.to_owned(),
+        },
+        course_class: str_field(value, "rwmc")?.to_owned(),
+        course_type: str_field(value, "kclbmc")?.to_owned(),
+        id: str_field(value, "id")?.to_owned(),
+        undergraduated_available: parsed_field::<u32>(value, "bksrl")?,
+        undergraduated_selected: parsed_field::<u32>(value, "bksyxrlrs")?,
+        graduated_available: parsed_field::<u32>(value, "yjsrl")?,
+        graduated_selected: parsed_field::<u32>(value, "yjsyxrlrs")?,
+        sections: parse_course_info(value["kcxx"].as_str().unwrap_or_default()),
+    })
+}
+
+pub async fn fetch_selected_courses(
+    client: &reqwest::Client,
+    semester_year: &str,
+    semester_no: &str,
+) -> Result<Vec<SelectedCourse>, FetchError> {
+    let mut post_form = std::collections::HashMap::<&str, &str>::new();
+    post_form.insert("p_pylx", "1");
+    post_form.insert("p_xkfsdm", "yixuan");
+    post_form.insert("p_xn", semester_year);
+    post_form.insert("p_xq", semester_no);
+    // post_form.insert("p_dqxn", semester_year);
+    // post_form.insert("p_dqxq", semester_no);
+    let v = send_json(client.post(SELECTED_COURSES_URL).form(&post_form)).await?;
+    // println!("{:?}", v);
+    let selected_courses_value = v["yxkcList"].as_array()
+                                        .ok_or_else(|| FetchError::Parse("Unable to find the selected courses in the response".to_owned()))?;
+    let mut selected_courses_vec = Vec::<SelectedCourse>::new();
+
+    for value in selected_courses_value {
+        let course = SelectedCourse {
+            advanced_course: parse_advanced_course(value)?,
+            available: value["sxbj"].as_str() == Some("1"),
+            points: {
+                if value["xkxs"].is_null() {
+                    None
+                } else {
+                    Some(parsed_field::<u32>(value, "xkxs")?)
+                }
+            }
+        };
+        selected_courses_vec.push(course);
+    }
+    Ok(selected_courses_vec)
+}
+
+pub async fn fetch_available_courses(
+    client: &reqwest::Client,
+    semester_year: &str,
+    semester_no: &str,
+    courses_type: &str,
+) -> Result<Vec<AvailableCourse>, FetchError> {
+    let mut post_form = std::collections::HashMap::<&str, &str>::new();
+    let code_p_xkfsdm = course_type_code(courses_type).unwrap_or_default();
+
+    post_form.insert("p_pylx", "1");
+    post_form.insert("p_xkfsdm", code_p_xkfsdm);
+    post_form.insert("p_xn", semester_year);
+    post_form.insert("p_xq", semester_no);
+    let v = send_json(client.post(AVAILABLE_COURSES_URL).form(&post_form)).await?;
+
+    let available_courses_value = v["kxrwList"]["list"].as_array()
+                                        .ok_or_else(|| FetchError::Parse("Unable to find the available courses in the response".to_owned()))?;
+
+    let mut available_courses_vec = Vec::<AvailableCourse>::new();
+
+    for value in available_courses_value {
+        let course = AvailableCourse {
+            advanced_course: parse_advanced_course(value)?,
+            outline_id: str_field(value, "kcid")?.to_owned(),
+            conflict_courses: value["ctkcxx"].as_str().map(|conflicts| conflicts.to_owned()),
+            conflicts: parse_conflict_courses(value["ctkcxx"].as_str().unwrap_or_default()),
+        };
+        available_courses_vec.push(course);
+    }
+    Ok(available_courses_vec)
+}
+
+/// Link the conflicts of the available classes to the selected classes they
+/// name, matched by course code when TIS gives it or else by course name,
+/// and preferring the selected class of the same class name. The clashing
+/// slots of both classes are filled in as well.
+pub fn link_conflicts(available: &mut [AvailableCourse], selected: &[SelectedCourse]) {
+    for course in available.iter_mut() {
+        for reference in course.conflicts.iter_mut() {
+            let candidates: Vec<&AdvancedCourse> = selected.iter()
+                                                    .map(|s| &s.advanced_course)
+                                                    .filter(|s| match &reference.course_id {
+                                                        Some(course_id) => s.basic_course.course_id == *course_id,
+                                                        None => s.basic_course.course_name == reference.course_name,
+                                                    })
+                                                    .collect();
+            let same_class = |s: &&&AdvancedCourse| match &reference.course_class {
+                Some(class) if !s.course_class.is_empty() => s.course_class.contains(class.as_str()) || class.contains(s.course_class.as_str()),
+                _ => false
+            };
+            let linked = candidates.iter().find(same_class).or_else(|| candidates.first());
+            if let Some(linked) = linked {
+                reference.selected_class_id = Some(linked.id.clone());
+                reference.clashing_slots = clashing_slots(&course.advanced_course, linked)
+                                            .into_iter()
+                                            .map(|(slot, selected_slot)| ClashingSlots {
+                                                slot: slot.clone(),
+                                                selected_slot: selected_slot.clone(),
+                                            })
+                                            .collect();
+            }
+        }
+    }
+}
+
+pub async fn fetch_basic_info(
+    client: &reqwest::Client,
+) -> Result<BasicInfo, Unauthorized<String>> {
+    let v = client.post(BASIC_INFO_URL)
+                        .send()
+                        .await
+                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
+                        .json::<serde_json::Value>()
+                        .await
+                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
+    
+    let basic_info = BasicInfo {
+        id: v["ID"].as_str().unwrap_or_default().to_owned(),
+        sid: v["XH"].as_str().unwrap_or_default().to_owned(),
+        name: v["XM"].as_str().unwrap_or_default().to_owned(),
+        email: v["DZYX"].as_str().unwrap_or_default().to_owned(),
+        year: v["NJMC"].as_str().unwrap_or_default().to_owned(),
+        department: v["YXMC"].as_str().unwrap_or_default().to_owned(),
+        major: v["ZYMC"].as_str().unwrap_or_default().to_owned()
+    };
+    Ok(basic_info)
+}
+
+/// The GPA of each semester, the average GPA and the rank.
+pub async fn fetch_student_gpa(
+    client: &reqwest::Client,
+) -> Result<StudentGPA, Unauthorized<String>> {
+    let v = client.post(SEMESTER_GPA_URL)
+                        .send()
+                        .await
+                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
+                        .json::<serde_json::Value>()
+                        .await
+                        .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
+
+    let gpa_value_array = v["xnanxqxfj"].as_array().ok_or_else(|| Unauthorized(Some("Unable to find the semester GPA".to_owned())))?;
+    let mut gpa_vec = Vec::<SemesterGPA>::new();
+    for gpa in gpa_value_array {
+        let semester_gpa = SemesterGPA {
+            semester_full_name: gpa["XNXQ"].as_str().unwrap_or_default().to_owned(),
+            semester_year: gpa["XN"].as_str().unwrap_or_default().to_owned(),
+            semester_number: gpa["XQ"].as_str().unwrap_or_default().to_owned(),
+            gpa: gpa["XQXFJ"].as_f64()
+        };
+        gpa_vec.push(semester_gpa);
+    }
+
+    Ok(StudentGPA {
+        all_gpa: gpa_vec,
+        average_gpa: v["xfjandpm"]["PJXFJ"].as_f64().unwrap_or_default(),
+        rank: v["xfjandpm"]["PM"].as_str().unwrap_or_default().to_owned()
+    })
+}
+
+/// The grades of all the courses taken so far.
+pub async fn fetch_course_grades(
+    client: &reqwest::Client,
+) -> Result<Vec<CourseGrade>, Unauthorized<String>> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("Content-Type", reqwest::header::HeaderValue::from_static("application/json"));
+    let body = r#"{"xn":null,"xq":null,"kcmc":null,"cxbj":"-1","pylx":"1","current":1,"pageSize":100}"#;
+    let v: serde_json::Value = client.post(COURSE_GRADES_URL)
+                                    .headers(headers)
+                                    .body(body)
+                                    .send()
+                                    .await
+                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
+                                    .json::<serde_json::Value>()
+                                    .await
+                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
+    
+    let course_grades_value_vec = v["content"]["list"].as_array().ok_or_else(|| Unauthorized(Some("Unable to find the course grades".to_owned())))?;
+    let mut course_grades_vec = Vec::<CourseGrade>::new();
+    for course_grade_value in course_grades_value_vec {
+        let course_grade = CourseGrade {
+            code: course_grade_value["kcdm"].as_str().unwrap_or_default().to_owned(),
+            name: course_grade_value["kcmc"].as_str().unwrap_or_default().to_owned(),
+            class_hour: course_grade_value["xs"].as_str().unwrap_or_default().to_owned(),
+            credit: number_field(&course_grade_value["xf"]).unwrap_or_default(),
+            semester: course_grade_value["xnxqmc"].as_str().unwrap_or_default().to_owned(),
+            final_grade: course_grade_value["zzcj"].as_str().unwrap_or_default().to_owned(),
+            final_level: course_grade_value["xscj"].as_str().unwrap_or_default().to_owned(),
+            department: course_grade_value["yxmc"].as_str().unwrap_or_default().to_owned(),
+            course_type: course_grade_value["kclb"].as_str().unwrap_or_default().to_owned(),
+            retake: match &course_grade_value["cxbj"] {
+                serde_json::Value::String(flag) => flag == "1" || flag == "是",
+                serde_json::Value::Number(flag) => flag.as_u64() == Some(1),
+                serde_json::Value::Bool(flag) => *flag,
+                _ => false
+            },
+            attempt: 1,
+            attempts: 1,
+            counted: true,
+            excluded_reason: None,
+        };
+        course_grades_vec.push(course_grade);
+    }
+    Ok(course_grades_vec)
+}
+
+/// Download a course list of TAO, Chinese or English, which needs no login.
+/// The links to the detail pages are made absolute.
+pub async fn fetch_course_list(url: &str) -> Result<Vec<CatalogCourse>, Unauthorized<String>> {
+    let courses_html = reqwest::get(url)
+                                        .await
+                                        .map_err(|_| Unauthorized(Some("Unable to get courses from the web".to_owned())))?
+                                        .text()
+                                        .await
+                                        .map_err(|_| Unauthorized(Some("Unable to get courses from the web".to_owned())))?;
+    let mut courses = parse_catalog_list(&courses_html);
+    if courses.is_empty() {
+        return Err(Unauthorized(Some("Unable to find any course in the course list".to_owned())));
+    }
+    let base = reqwest::Url::parse(url).ok();
+    for course in courses.iter_mut() {
+        course.detail_url = course.detail_url
+                                .take()
+                                .and_then(|href| base.as_ref()?.join(&href).ok())
+                                .map(|url| url.to_string());
+    }
+    Ok(courses)
+}
+
+/// Download the detail page of a course on TAO.
+pub async fn fetch_course_detail(url: &str) -> Result<CourseDetail, Unauthorized<String>> {
+    let detail_html = reqwest::get(url)
+                                        .await
+                                        .and_then(|response| response.error_for_status())
+                                        .map_err(|_| Unauthorized(Some("Unable to get the course detail from the web".to_owned())))?
+                                        .text()
+                                        .await
+                                        .map_err(|_| Unauthorized(Some("Unable to get the course detail from the web".to_owned())))?;
+    Ok(parse_course_detail(&detail_html))
+}
+
+/// The `kcdgbentity` of the outline of a class, given its `outline_id`.
+pub async fn fetch_outline(
+    client: &reqwest::Client,
+    outline_id: &str,
+) -> Result<serde_json::Value, FetchError> {
+    let mut post_form = std::collections::HashMap::<&str, &str>::new();
+    post_form.insert("kcid", outline_id);
+
+    let v = send_json(client.post(OUTLINE_URL).form(&post_form)).await?;
+    Ok(v["content"]["kcdgbentity"].to_owned())
+}
+
+pub async fn fetch_current_semester(
+    client: &reqwest::Client,
+) -> Result<CurrentSemester, FetchError> {
+    let mut post_form = std::collections::HashMap::<&str, &str>::new();
+    post_form.insert("p_pylx", "1");
+    post_form.insert("mxpylx", "1");
+
+    let v = send_json(client.post(CURRENT_SEMESTER_URL).form(&post_form)).await?;
+    #[cfg(debug_assertions)]
+    println!("{:?}", v);
+    let current_semester = CurrentSemester {
+        semester_year: v["p_xn"].as_str()
+                            .ok_or_else(|| FetchError::Parse("Unable to find the current semester in the response".to_owned()))?
+                            .to_owned(),
+        semester_no: v["p_xq"].as_str()
+                            .ok_or_else(|| FetchError::Parse("Unable to find the current semester in the response".to_owned()))?
+                            .to_owned(),
+    };
+
+    Ok(current_semester)
+}
+
+pub async fn fetch_course_table(
+    client: &reqwest::Client,
+    semester_year: &str,
+    semester_no: &str,
+) -> Result<Vec<CourseTableItem>, Unauthorized<String>> {
+    let mut post_form = std::collections::HashMap::<&str, &str>::new();
+    post_form.insert("bs", "2");
+    post_form.insert("xn", semester_year);
+    post_form.insert("xq", semester_no);
+
+    let v: serde_json::Value = client.post(COURSE_TABLE_URL)
+                                    .form(&post_form)
+                                    .send()
+                                    .await
+                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?
+                                    .json::<serde_json::Value>()
+                                    .await
+                                    .map_err(|_| Unauthorized(Some("Unable to send the login redirect request to CAS".to_owned())))?;
+
+    #[cfg(debug_assertions)]
+    println!("{:?}", v);
+                                    
+    let mut course_table_items_vec = Vec::<CourseTableItem>::new();
+    let json_array = v.as_array()
+                        .ok_or_else(|| Unauthorized(Some("Unable to find the course table in the response".to_owned())))?;
+    for item in json_array {
+        let (day, time) = match item["key"].as_str().and_then(parse_table_key) {
+            Some(key) => key,
+            None => continue
+        };
+        let course_info = item["kbxx"].as_str()
+                                    .unwrap_or_default()
+                                    .to_owned();
+        let course_table_item = CourseTableItem {
+            day,
+            time,
+            entries: parse_course_table_info(&course_info),
+            course_info,
+        };
+        course_table_items_vec.push(course_table_item);
+    }
+    Ok(course_table_items_vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::parse_conflict_courses;
+    use super::super::structures::*;
+    use super::super::test_util::advanced_course;
+    use super::{link_conflicts, number_field};
+
+    #[test]
+    fn test_link_conflicts() {
+        let ctkcxx = "与[CS101]计算机导论[02班]、高等数学(上)[01班]冲突";
+        let mut available = vec![AvailableCourse {
+            advanced_course: advanced_course("PH103", "大学物理", "01班", "ph-1", &["1-16周 星期一 第3-4节 二教101"]),
+            outline_id: String::new(),
+            conflict_courses: Some(ctkcxx.to_owned()),
+            conflicts: parse_conflict_courses(ctkcxx),
+        }];
+        let selected: Vec<SelectedCourse> = vec![
+            advanced_course("CS101", "计算机导论", "01班", "cs-1", &["1-16周 星期二 第3-4节 一教101"]),
+            advanced_course("CS101", "计算机导论", "02班", "cs-2", &["1-8周 星期一 第4-5节 一教201"]),
+            advanced_course("MA101", "高等数学(上)", "01班", "ma-1", &["1-16周 星期三 第1-2节 一教301"]),
+        ].into_iter().map(|advanced_course| SelectedCourse { advanced_course, available: true, points: None }).collect();
+
+        link_conflicts(&mut available, &selected);
+        let conflicts = &available[0].conflicts;
+        assert_eq!(conflicts[0].selected_class_id.as_deref(), Some("cs-2"));
+        assert_eq!(conflicts[0].clashing_slots.len(), 1);
+        assert_eq!(conflicts[0].clashing_slots[0].selected_slot.raw, "1-8周 星期一 第4-5节 一教201");
+        // TIS knows better, so the reference is linked even if the slots do not clash.
+        assert_eq!(conflicts[1].selected_class_id.as_deref(), Some("ma-1"));
+        assert!(conflicts[1].clashing_slots.is_empty());
+    }
+
+    #[test]
+    fn test_number_field() {
+        assert_eq!(number_field(&serde_json::json!(3)), Some(3.0));
+        assert_eq!(number_field(&serde_json::json!(2.5)), Some(2.5));
+        assert_eq!(number_field(&serde_json::json!(" 0.5")), Some(0.5));
+        assert_eq!(number_field(&serde_json::json!("")), None);
+        assert_eq!(number_field(&serde_json::Value::Null), None);
+    }
+}
@@ -0,0 +1,223 @@
+use std::cmp::Ordering;
+
+use rocket::serde::Deserialize;
+
+use super::prerequisites::is_passed;
+use super::structures::*;
+
+/// Which attempt at a course counts when it has been taken more than once.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetakePolicy {
+    Latest,
+    Highest,
+}
+
+impl RetakePolicy {
+    pub fn parse(policy: &str) -> Option<RetakePolicy> {
+        match policy {
+            "latest" => Some(RetakePolicy::Latest),
+            "highest" => Some(RetakePolicy::Highest),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RetakePolicy::Latest => "latest",
+            RetakePolicy::Highest => "highest",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct GradesConfig {
+    pub retake_policy: RetakePolicy,
+}
+
+impl Default for GradesConfig {
+    fn default() -> Self {
+        GradesConfig { retake_policy: RetakePolicy::Latest }
+    }
+}
+
+/// Letter grades with their grade points and the lowest score of each, on
+/// the 4.0 scale of SUSTech.
+pub const LETTER_GRADES: [(&str, f64, f64); 13] = [
+    ("A+", 4.00, 97.0), ("A", 3.94, 93.0), ("A-", 3.85, 90.0),
+    ("B+", 3.73, 87.0), ("B", 3.55, 83.0), ("B-", 3.32, 80.0),
+    ("C+", 3.09, 77.0), ("C", 2.78, 73.0), ("C-", 2.42, 70.0),
+    ("D+", 2.08, 67.0), ("D", 1.63, 63.0), ("D-", 1.15, 60.0),
+    ("F", 0.0, 0.0),
+];
+
+pub fn score(grade: &CourseGrade) -> Option<f64> {
+    grade.final_grade.trim().parse::<f64>().ok()
+}
+
+/// The grade point of a grade, from its letter or else its score. Grades
+/// such as `P` or 通过 have none and are left out of the GPA.
+pub fn grade_point(grade: &CourseGrade) -> Option<f64> {
+    let level = grade.final_level.trim();
+    LETTER_GRADES.iter()
+        .find(|(letter, _, _)| *letter == level)
+        .map(|(_, point, _)| *point)
+        .or_else(|| {
+            let score = score(grade)?;
+            LETTER_GRADES.iter().find(|(_, _, min)| score >= *min).map(|(_, point, _)| *point)
+        })
+}
+
+/// A semester name such as `2021-2022学年第1学期` or `2021-2022秋季` as
+/// something to sort by.
+pub fn semester_key(semester: &str) -> Vec<u32> {
+    let mut key: Vec<u32> = semester.split(|c: char| !c.is_ascii_digit())
+                                .filter_map(|part| part.parse::<u32>().ok())
+                                .collect();
+    if key.len() < 3 {
+        let seasons = [('秋', 1), ('春', 2), ('夏', 3)];
+        key.extend(seasons.iter().find(|(season, _)| semester.contains(*season)).map(|(_, no)| *no));
+    }
+    key
+}
+
+/// How good an attempt is, for the highest policy.
+fn rank(grade: &CourseGrade) -> (bool, f64) {
+    (is_passed(grade), score(grade).or_else(|| grade_point(grade).map(|point| point * 25.0)).unwrap_or(0.0))
+}
+
+/// Number the attempts at each course in the order they were taken, and
+/// mark the one which counts under `policy`. The others are excluded from
+/// the credits and the GPA, with the reason.
+pub fn mark_attempts(grades: &mut [CourseGrade], policy: RetakePolicy) {
+    let mut codes: Vec<String> = Vec::new();
+    for grade in grades.iter() {
+        let code = grade.code.trim().to_uppercase();
+        if !codes.contains(&code) {
+            codes.push(code);
+        }
+    }
+    for code in codes {
+        let mut attempts: Vec<usize> = (0..grades.len()).filter(|i| grades[*i].code.trim().eq_ignore_ascii_case(&code)).collect();
+        attempts.sort_by_key(|i| semester_key(&grades[*i].semester));
+        let counted = match policy {
+            RetakePolicy::Latest => attempts.last().cloned(),
+            // The later attempt wins a tie.
+            RetakePolicy::Highest => attempts.iter()
+                                        .max_by(|a, b| rank(&grades[**a]).partial_cmp(&rank(&grades[**b])).unwrap_or(Ordering::Equal))
+                                        .cloned(),
+        };
+        let counted_semester = counted.map(|i| grades[i].semester.clone()).unwrap_or_default();
+        for (n, i) in attempts.iter().enumerate() {
+            let grade = &mut grades[*i];
+            grade.attempt = n as u32 + 1;
+            grade.attempts = attempts.len() as u32;
+            grade.retake |= n > 0;
+            grade.counted = Some(*i) == counted;
+            grade.excluded_reason = if grade.counted {
+                None
+            } else {
+                Some(format!("The {} attempt, of {}, counts instead", policy.name(), counted_semester))
+            };
+        }
+    }
+}
+
+/// The attempts at each course, with the credits and the GPA of the
+/// counted ones.
+pub fn grade_history(grades: &[CourseGrade], policy: RetakePolicy) -> GradeHistory {
+    let mut grades = grades.to_vec();
+    mark_attempts(&mut grades, policy);
+
+    let mut courses = Vec::<CourseAttempts>::new();
+    for grade in grades.iter() {
+        let code = grade.code.trim().to_uppercase();
+        match courses.iter_mut().find(|course| course.code == code) {
+            Some(course) => course.attempts.push(grade.clone()),
+            None => courses.push(CourseAttempts {
+                code,
+                name: grade.name.clone(),
+                counted_attempt: None,
+                attempts: vec![grade.clone()],
+            })
+        }
+    }
+    for course in courses.iter_mut() {
+        course.attempts.sort_by_key(|grade| grade.attempt);
+        course.counted_attempt = course.attempts.iter().find(|grade| grade.counted).map(|grade| grade.attempt);
+    }
+
+    let counted: Vec<&CourseGrade> = grades.iter().filter(|grade| grade.counted).collect();
+    let graded: Vec<(f64, f64)> = counted.iter()
+                                    .filter_map(|grade| Some((grade.credit, grade_point(grade)?)))
+                                    .collect();
+    let gpa_credits: f64 = graded.iter().map(|(credits, _)| credits).sum();
+    GradeHistory {
+        retake_policy: policy.name().to_owned(),
+        attempted_credits: counted.iter().map(|grade| grade.credit).sum(),
+        earned_credits: counted.iter().filter(|grade| is_passed(grade)).map(|grade| grade.credit).sum(),
+        gpa: if gpa_credits > 0.0 {
+            Some(graded.iter().map(|(credits, point)| credits * point).sum::<f64>() / gpa_credits)
+        } else {
+            None
+        },
+        gpa_credits,
+        excluded: grades.iter().filter(|grade| !grade.counted).cloned().collect(),
+        courses,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::structures::CourseGrade;
+    use super::super::test_util::course_grade;
+    use super::{grade_history, grade_point, mark_attempts, RetakePolicy};
+
+    fn grades() -> Vec<CourseGrade> {
+        vec![
+            course_grade("CS203", 3.0, "2022-2023学年第1学期", "85", "B", ""),
+            course_grade("MA101B", 3.0, "2021-2022学年第1学期", "52", "F", ""),
+            course_grade("CS203", 3.0, "2021-2022学年第2学期", "91", "A-", ""),
+            course_grade("MA101B", 3.0, "2021-2022学年第2学期", "75", "C", ""),
+            course_grade("PE101", 3.0, "2021-2022学年第1学期", "", "P", ""),
+        ]
+    }
+
+    #[test]
+    fn test_grade_point() {
+        assert_eq!(grade_point(&course_grade("CS203", 3.0, "", "85", "B", "")), Some(3.55));
+        assert_eq!(grade_point(&course_grade("CS203", 3.0, "", "97", "", "")), Some(4.0));
+        assert_eq!(grade_point(&course_grade("CS203", 3.0, "", "59", "", "")), Some(0.0));
+        assert_eq!(grade_point(&course_grade("PE101", 3.0, "", "", "P", "")), None);
+    }
+
+    #[test]
+    fn test_mark_attempts() {
+        let mut latest = grades();
+        mark_attempts(&mut latest, RetakePolicy::Latest);
+        assert_eq!((latest[0].attempt, latest[0].attempts, latest[0].counted), (2, 2, true));
+        assert!(latest[0].retake);
+        assert!(!latest[2].counted);
+        assert_eq!(latest[2].excluded_reason.as_deref(), Some("The latest attempt, of 2022-2023学年第1学期, counts instead"));
+
+        let mut highest = grades();
+        mark_attempts(&mut highest, RetakePolicy::Highest);
+        assert!(!highest[0].counted);
+        assert!(highest[2].counted);
+        assert!(highest[3].counted);
+        assert!(highest[4].counted);
+    }
+
+    #[test]
+    fn test_grade_history() {
+        let history = grade_history(&grades(), RetakePolicy::Highest);
+        assert_eq!(history.courses.len(), 3);
+        assert_eq!(history.courses[0].code, "CS203");
+        assert_eq!(history.courses[0].counted_attempt, Some(1));
+        assert_eq!(history.courses[0].attempts[0].semester, "2021-2022学年第2学期");
+        assert_eq!((history.attempted_credits, history.earned_credits, history.gpa_credits), (9.0, 9.0, 6.0));
+        assert!((history.gpa.unwrap() - (3.85 + 2.78) / 2.0).abs() < 1e-9);
+        assert_eq!(history.excluded.len(), 2);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use rocket::serde::Deserialize;
+
+use rocket::response::status::Unauthorized;
+
+use super::calendar::{AcademicCalendar, Calendars, UTC_OFFSET_HOURS};
+use super::courses::{fetch_current_semester, fetch_selected_courses};
+use super::structures::*;
+
+const TIMEZONE: &str = "Asia/Shanghai";
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TimetableConfig {
+    /// Start and end time of each period, the first one being period 1.
+    pub periods: Vec<(String, String)>,
+    /// Monday of the first week of each semester, keyed by
+    /// `<semester_year>-<semester_no>` such as `2021-2022-1`.
+    pub semester_starts: HashMap<String, String>,
+}
+
+impl Default for TimetableConfig {
+    fn default() -> Self {
+        let periods = [
+            ("08:00", "08:50"), ("09:00", "09:50"), ("10:20", "11:10"), ("11:20", "12:10"),
+            ("14:00", "14:50"), ("15:00", "15:50"), ("16:20", "17:10"), ("17:20", "18:10"),
+            ("19:00", "19:50"), ("20:00", "20:50"), ("21:00", "21:50"),
+        ];
+        TimetableConfig {
+            periods: periods.iter().map(|(start, end)| (start.to_string(), end.to_string())).collect(),
+            semester_starts: HashMap::new(),
+        }
+    }
+}
+
+impl TimetableConfig {
+    /// The bell schedule, or an error naming the first unreadable time.
+    pub fn bell_schedule(&self) -> Result<Vec<(NaiveTime, NaiveTime)>, String> {
+        let parse = |time: &str| NaiveTime::parse_from_str(time, "%H:%M")
+                                    .map_err(|_| format!("Unable to parse the period time {}", time));
+        self.periods.iter()
+            .map(|(start, end)| Ok((parse(start)?, parse(end)?)))
+            .collect()
+    }
+
+    pub fn semester_start(
+        &self,
+        semester_year: &str,
+        semester_no: &str
+    ) -> Option<NaiveDate> {
+        self.semester_starts.get(&format!("{}-{}", semester_year, semester_no))
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+    }
+}
+
+/// One weekly recurring class of the timetable.
+pub struct ClassMeeting {
+    pub uid: String,
+    pub summary: String,
+    pub location: Option<String>,
+    pub description: Option<String>,
+    pub weekday: u32,
+    pub start_period: u32,
+    pub end_period: u32,
+    pub weeks: WeekSet,
+}
+
+/// The meetings of the selected courses, skipping the time slots which
+/// could not be read completely.
+pub fn class_meetings(courses: &[SelectedCourse]) -> Vec<ClassMeeting> {
+    let mut meetings = Vec::<ClassMeeting>::new();
+    for course in courses {
+        let course = &course.advanced_course;
+        for (i, section) in course.sections.iter().enumerate() {
+            let summary = match (&section.kind, &section.label) {
+                (SectionKind::Lecture, _) | (SectionKind::Unknown, None) => course.basic_course.course_name.clone(),
+                (_, Some(label)) => format!("{} ({})", course.basic_course.course_name, label),
+                (kind, None) => format!("{} ({:?})", course.basic_course.course_name, kind),
+            };
+            let mut description = format!("{}[{}]", course.basic_course.course_id, course.course_class);
+            if !section.teachers.is_empty() {
+                description.push('\n');
+                description.push_str(&section.teachers.join(", "));
+            }
+            for (j, slot) in section.time_slots.iter().enumerate() {
+                let (weekday, start_period, end_period) = match (slot.weekday, slot.start_period, slot.end_period) {
+                    (Some(weekday), Some(start), Some(end)) if !slot.weeks.is_empty() => (weekday, start, end),
+                    _ => continue
+                };
+                let location = match (&slot.building, &slot.room) {
+                    (Some(building), Some(room)) => Some(format!("{}{}", building, room)),
+                    (building, room) => building.clone().or_else(|| room.clone()),
+                };
+                meetings.push(ClassMeeting {
+                    uid: format!("{}-{}-{}@rustech", course.id, i, j),
+                    summary: summary.clone(),
+                    location,
+                    description: Some(description.clone()),
+                    weekday,
+                    start_period,
+                    end_period,
+                    weeks: slot.weeks,
+                });
+            }
+        }
+    }
+    meetings
+}
+
+/// Escape a TEXT value as RFC 5545 section 3.3.11 requires.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Append a content line, folded at 75 octets without splitting a character.
+fn push_line(ics: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+        ics.push(c);
+        width += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+fn local(time: NaiveDateTime) -> String {
+    time.format("%Y%m%dT%H%M%S").to_string()
+}
+
+fn utc(time: NaiveDateTime) -> String {
+    (time - Duration::hours(UTC_OFFSET_HOURS)).format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Render the meetings as an RFC 5545 calendar. Each meeting is one weekly
+/// event from its first to its last week. The days without class, such as
+/// the weeks it skips and the holidays, are excluded with EXDATE and the
+/// make-up days following its schedule are added with RDATE.
+pub fn render_calendar(
+    name: &str,
+    calendar: &AcademicCalendar,
+    bell_schedule: &[(NaiveTime, NaiveTime)],
+    meetings: &[ClassMeeting],
+) -> String {
+    let mut ics = String::new();
+    for line in [
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "PRODID:-//Rustech//SUSTech Timetable//EN",
+        "CALSCALE:GREGORIAN",
+        "METHOD:PUBLISH",
+    ] {
+        push_line(&mut ics, line);
+    }
+    push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape(name)));
+    push_line(&mut ics, &format!("X-WR-TIMEZONE:{}", TIMEZONE));
+    for line in [
+        "BEGIN:VTIMEZONE",
+        &format!("TZID:{}", TIMEZONE)[..],
+        "BEGIN:STANDARD",
+        "DTSTART:19700101T000000",
+        "TZOFFSETFROM:+0800",
+        "TZOFFSETTO:+0800",
+        "TZNAME:CST",
+        "END:STANDARD",
+        "END:VTIMEZONE",
+    ] {
+        push_line(&mut ics, line);
+    }
+
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    for meeting in meetings {
+        let period = |period: u32| (period as usize).checked_sub(1).and_then(|i| bell_schedule.get(i));
+        let (start_time, end_time) = match (period(meeting.start_period), period(meeting.end_period)) {
+            (Some((start, _)), Some((_, end))) => (*start, *end),
+            _ => continue
+        };
+        let weeks = meeting.weeks.weeks();
+        let (first, last) = match (weeks.first(), weeks.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => continue
+        };
+        let occurrences: Vec<NaiveDate> = weeks.iter()
+                                            .flat_map(|week| calendar.dates_of(*week, meeting.weekday))
+                                            .collect();
+        if occurrences.is_empty() {
+            continue;
+        }
+        let regular: Vec<NaiveDate> = (first..=last).map(|week| calendar.date_of(week, meeting.weekday)).collect();
+        let start = regular[0].and_time(start_time);
+        let end = regular[0].and_time(end_time);
+        let until = calendar.date_of(last, meeting.weekday).and_time(end_time);
+        let at_start = |dates: Vec<&NaiveDate>| {
+            dates.iter().map(|date| local(date.and_time(start_time))).collect::<Vec<String>>().join(",")
+        };
+
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:{}", meeting.uid));
+        push_line(&mut ics, &format!("DTSTAMP:{}", stamp));
+        push_line(&mut ics, &format!("DTSTART;TZID={}:{}", TIMEZONE, local(start)));
+        push_line(&mut ics, &format!("DTEND;TZID={}:{}", TIMEZONE, local(end)));
+        push_line(&mut ics, &format!("RRULE:FREQ=WEEKLY;UNTIL={}", utc(until)));
+        let excluded: Vec<&NaiveDate> = regular.iter().filter(|date| !occurrences.contains(date)).collect();
+        if !excluded.is_empty() {
+            push_line(&mut ics, &format!("EXDATE;TZID={}:{}", TIMEZONE, at_start(excluded)));
+        }
+        let added: Vec<&NaiveDate> = occurrences.iter().filter(|date| !regular.contains(date)).collect();
+        if !added.is_empty() {
+            push_line(&mut ics, &format!("RDATE;TZID={}:{}", TIMEZONE, at_start(added)));
+        }
+        push_line(&mut ics, &format!("SUMMARY:{}", escape(&meeting.summary)));
+        if let Some(location) = &meeting.location {
+            push_line(&mut ics, &format!("LOCATION:{}", escape(location)));
+        }
+        if let Some(description) = &meeting.description {
+            push_line(&mut ics, &format!("DESCRIPTION:{}", escape(description)));
+        }
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// The timetable of the selected courses of `semester`, the current one if
+/// not given, with a logged in TIS client.
+///
+/// An explicit `semester_start` wins over the academic calendar of the
+/// semester, which wins over the start dates of the config.
+pub async fn timetable_calendar(
+    client: &reqwest::Client,
+    semester: Option<CurrentSemester>,
+    semester_start: Option<NaiveDate>,
+    timetable: &TimetableConfig,
+    calendars: &Calendars,
+) -> Result<String, Unauthorized<String>> {
+    let bell_schedule = timetable.bell_schedule().map_err(|e| Unauthorized(Some(e)))?;
+    let semester = match semester {
+        Some(semester) => semester,
+        None => fetch_current_semester(client).await?
+    };
+    let calendar = match semester_start {
+        Some(start) => AcademicCalendar::plain(&semester.semester_year, &semester.semester_no, start),
+        None => calendars.semester(
+                    &semester.semester_year,
+                    &semester.semester_no,
+                    timetable.semester_start(&semester.semester_year, &semester.semester_no)
+                )
+                .ok_or_else(|| Unauthorized(Some(format!(
+                    "No academic calendar or start date for the semester {}-{}, give semester_start",
+                    semester.semester_year,
+                    semester.semester_no
+                ))))?
+    };
+
+    let courses = fetch_selected_courses(client, &semester.semester_year, &semester.semester_no).await?;
+    let name = format!("Timetable {}-{}", semester.semester_year, semester.semester_no);
+    Ok(render_calendar(&name, &calendar, &bell_schedule, &class_meetings(&courses)))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::super::calendar::{AcademicCalendar, Holiday, SwappedDay};
+    use super::super::structures::WeekSet;
+    use super::{push_line, render_calendar, ClassMeeting, TimetableConfig};
+
+    #[test]
+    fn test_push_line_folds_long_lines() {
+        let mut ics = String::new();
+        push_line(&mut ics, &format!("SUMMARY:{}", "课".repeat(40)));
+        for line in ics.trim_end().split("\r\n") {
+            assert!(line.len() <= 75);
+        }
+        assert_eq!(ics.replace("\r\n ", ""), format!("SUMMARY:{}\r\n", "课".repeat(40)));
+    }
+
+    #[test]
+    fn test_render_calendar() {
+        let mut weeks = WeekSet::default();
+        for week in [1, 3, 5, 6] {
+            weeks.insert(week);
+        }
+        let meetings = [ClassMeeting {
+            uid: "1-0-0@rustech".to_owned(),
+            summary: "高等数学(上)".to_owned(),
+            location: Some("一教101".to_owned()),
+            description: None,
+            weekday: 3,
+            start_period: 3,
+            end_period: 4,
+            weeks,
+        }];
+        let bell_schedule = TimetableConfig::default().bell_schedule().unwrap();
+        let date = |month, day| NaiveDate::from_ymd_opt(2021, month, day).unwrap();
+        let mut calendar = AcademicCalendar::plain("2021-2022", "1", date(9, 6));
+        let ics = render_calendar("Timetable", &calendar, &bell_schedule, &meetings);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert!(ics.contains("DTSTART;TZID=Asia/Shanghai:20210908T102000\r\n"));
+        assert!(ics.contains("DTEND;TZID=Asia/Shanghai:20210908T121000\r\n"));
+        assert!(ics.contains("RRULE:FREQ=WEEKLY;UNTIL=20211013T041000Z\r\n"));
+        assert!(ics.contains("EXDATE;TZID=Asia/Shanghai:20210915T102000,20210929T102000\r\n"));
+        assert!(ics.contains("LOCATION:一教101\r\n"));
+        assert!(!ics.contains("RDATE"));
+
+        calendar.holidays.push(Holiday { name: "国庆节".to_owned(), start: date(10, 1), end: Some(date(10, 7)) });
+        calendar.swapped_days.push(SwappedDay { date: date(10, 9), follows: date(10, 6) });
+        let ics = render_calendar("Timetable", &calendar, &bell_schedule, &meetings);
+        assert!(ics.contains("EXDATE;TZID=Asia/Shanghai:20210915T102000,20210929T102000,20211006T102000\r\n"));
+        assert!(ics.contains("RDATE;TZID=Asia/Shanghai:20211009T102000\r\n"));
+    }
+}
@@ -2,4 +2,29 @@ mod encrypt;
 pub mod structures;
 mod urls;
 mod login;
-pub mod apis;
\ No newline at end of file
+mod selection;
+mod courses;
+mod parser;
+pub mod throttle;
+pub mod bidding;
+pub mod watcher;
+pub mod waitlist;
+pub mod scheduler;
+pub mod calendar;
+pub mod ical;
+pub mod agenda;
+pub mod render;
+pub mod planner;
+pub mod catalogue;
+pub mod outlines;
+pub mod markup;
+pub mod prerequisites;
+pub mod training;
+pub mod roadmap;
+pub mod grades;
+pub mod conversion;
+pub mod transcript;
+pub mod webcal;
+pub mod apis;
+#[cfg(test)]
+mod test_util;
\ No newline at end of file
@@ -185,6 +185,16 @@ pub async fn tis_login(
     if !login(username, password, &client_storage).await? {
         return Ok(false);
     }
+    let account_storage = client_storage.lock().await;
+    let account = account_storage.get(username).unwrap();
+    redirect_to_tis(&account.client).await?;
+
+    Ok(true)
+}
+
+/// Follow the CAS redirect to TIS, which opens a TIS session for a client
+/// logged in to CAS.
+async fn redirect_to_tis(client: &reqwest::Client) -> Result<(), Unauthorized<String>> {
     let mut headers = reqwest::header::HeaderMap::new();
     headers.insert(
         "Referer",
@@ -205,9 +215,6 @@ pub async fn tis_login(
         reqwest::header::HeaderValue::from_static("gzip, deflate, br"),
     );
 
-    let account_storage = client_storage.lock().await;
-    let account = account_storage.get(username).unwrap();
-    let client = &account.client;
     client
         .get(TIS_CAS_URL)
         .headers(headers)
@@ -219,5 +226,15 @@ pub async fn tis_login(
             ))
         })?;
 
+    Ok(())
+}
+
+/// Open a new TIS session with the CAS session of `client`, without any
+/// password. Fails once the CAS session itself has expired.
+pub async fn refresh_tis_session(client: &reqwest::Client) -> Result<bool, Unauthorized<String>> {
+    if !use_client_login(client).await? {
+        return Ok(false);
+    }
+    redirect_to_tis(client).await?;
     Ok(true)
 }
@@ -1,27 +1,129 @@
-use std::{collections::HashMap};
+use std::{collections::HashMap, sync::Arc};
 
 use futures::lock::Mutex;
-use rustech::apis::{available_courses, basic_info, cas_login, course_outline, course_table, courses_grades, current_semester, drop_course, get_courses, index, select_course, selected_courses, semester_gpa, update_points};
+use rocket::fairing::AdHoc;
+use rustech::apis::{add_waitlist, available_courses, basic_info, batch_select_courses, calendar_subscription, cancel_scheduled_selection, cancel_waitlist, cas_login, check_prerequisites, course_detail, course_outline, course_table, courses_grades, current_semester, degree_audit, drop_course, get_courses, get_waitlists, grade_history, index, index_outlines, next_class, plan_timetable, prerequisites, recommend_points, revoke_calendar_subscription, schedule_selection, scheduled_selections, search_courses, search_outlines, select_course, selected_courses, semester_gpa, study_plan, timetable_ics, timetable_image, today, training_plans, transcript, transcript_summary, unwatch_course, update_points, waitlist_audit, watch_course, watch_events, watched_courses, webcal_timetable};
+use rustech::bidding::Snapshots;
+use rustech::catalogue::Catalogue;
+use rustech::calendar::Calendars;
+use rustech::conversion::ConversionConfig;
+use rustech::grades::GradesConfig;
+use rustech::ical::TimetableConfig;
+use rustech::outlines::Outlines;
+use rustech::planner::PlannerConfig;
+use rustech::render::RenderConfig;
+use rustech::roadmap::RoadmapConfig;
+use rustech::scheduler::Scheduler;
 use rustech::structures::Account;
+use rustech::throttle::Throttle;
+use rustech::training::TrainingPlans;
+use rustech::transcript::TranscriptConfig;
+use rustech::waitlist::Waitlists;
+use rustech::watcher::Watcher;
+use rustech::webcal::Subscriptions;
 
 #[rocket::launch]
 fn rocket() -> _ {
     // simple_logging::log_to_file("./log.txt", log::LevelFilter::Info)
     //                 .unwrap();
-    rocket::build()
+    let rocket = rocket::build();
+    let figment = rocket.figment();
+    let throttle = Arc::new(Throttle::new(&figment.extract_inner("upstream").unwrap_or_default()));
+    let snapshots = Snapshots::load(&figment.extract_inner("bidding").unwrap_or_default());
+    let watcher = Watcher::new(&figment.extract_inner("watcher").unwrap_or_default(), throttle.clone(), snapshots.clone());
+    let waitlists = Waitlists::new(&figment.extract_inner("waitlist").unwrap_or_default(), throttle.clone());
+    let scheduler = Scheduler::new(figment.extract_inner("scheduler").unwrap_or_default(), throttle.clone());
+    let subscriptions = Subscriptions::new(&figment.extract_inner("webcal").unwrap_or_default(), throttle.clone());
+    let outlines = Outlines::load(&figment.extract_inner("outlines").unwrap_or_default(), throttle);
+    let timetable: TimetableConfig = figment.extract_inner("timetable").unwrap_or_default();
+    let calendars = Calendars::load(&figment.extract_inner("calendar").unwrap_or_default());
+    let render: RenderConfig = figment.extract_inner("render").unwrap_or_default();
+    let planner: PlannerConfig = figment.extract_inner("planner").unwrap_or_default();
+    let catalogue = Catalogue::load(&figment.extract_inner("catalogue").unwrap_or_default());
+    let training_plans = TrainingPlans::load(&figment.extract_inner("training").unwrap_or_default());
+    let roadmap: RoadmapConfig = figment.extract_inner("roadmap").unwrap_or_default();
+    let grades: GradesConfig = figment.extract_inner("grades").unwrap_or_default();
+    let conversion: ConversionConfig = figment.extract_inner("conversion").unwrap_or_default();
+    let transcript: TranscriptConfig = figment.extract_inner("transcript").unwrap_or_default();
+    if let Err(e) = transcript.check_pdf() {
+        println!("{}, transcripts default to CSV", e);
+    }
+
+    rocket
             .manage(Mutex::new(HashMap::<String, Account>::new()))
-            .mount("/", rocket::routes![index, 
-                                                    cas_login, 
-                                                    basic_info, 
+            .manage(snapshots)
+            .manage(watcher)
+            .manage(waitlists)
+            .manage(scheduler)
+            .manage(timetable)
+            .manage(calendars)
+            .manage(subscriptions)
+            .manage(render)
+            .manage(planner)
+            .manage(catalogue)
+            .manage(outlines)
+            .manage(training_plans)
+            .manage(roadmap)
+            .manage(grades)
+            .manage(conversion)
+            .manage(transcript)
+            .attach(AdHoc::on_liftoff("Seat Watcher", |rocket| Box::pin(async move {
+                rocket.state::<Watcher>().unwrap().start();
+            })))
+            .attach(AdHoc::on_liftoff("Waitlists", |rocket| Box::pin(async move {
+                rocket.state::<Waitlists>().unwrap().start();
+            })))
+            .attach(AdHoc::on_liftoff("Catalogue", |rocket| Box::pin(async move {
+                rocket.state::<Catalogue>().unwrap().start();
+            })))
+            .attach(AdHoc::on_liftoff("Outlines", |rocket| Box::pin(async move {
+                rocket.state::<Outlines>().unwrap().start();
+            })))
+            .mount("/", rocket::routes![index,
+                                                    cas_login,
+                                                    basic_info,
                                                     semester_gpa,
                                                     courses_grades,
                                                     get_courses,
                                                     selected_courses,
                                                     available_courses,
                                                     select_course,
+                                                    batch_select_courses,
                                                     drop_course,
                                                     update_points,
                                                     course_outline,
                                                     current_semester,
-                                                    course_table])
+                                                    course_table,
+                                                    watch_course,
+                                                    unwatch_course,
+                                                    watched_courses,
+                                                    watch_events,
+                                                    add_waitlist,
+                                                    get_waitlists,
+                                                    cancel_waitlist,
+                                                    waitlist_audit,
+                                                    schedule_selection,
+                                                    scheduled_selections,
+                                                    cancel_scheduled_selection,
+                                                    recommend_points,
+                                                    timetable_ics,
+                                                    today,
+                                                    next_class,
+                                                    calendar_subscription,
+                                                    revoke_calendar_subscription,
+                                                    webcal_timetable,
+                                                    timetable_image,
+                                                    plan_timetable,
+                                                    search_courses,
+                                                    index_outlines,
+                                                    search_outlines,
+                                                    course_detail,
+                                                    prerequisites,
+                                                    check_prerequisites,
+                                                    training_plans,
+                                                    degree_audit,
+                                                    study_plan,
+                                                    grade_history,
+                                                    transcript_summary,
+                                                    transcript])
 }
@@ -0,0 +1,324 @@
+use scraper::{ElementRef, Html, Node};
+
+/// How the HTML of TIS is returned to the clients.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MarkupFormat {
+    /// Sanitised HTML, with only the formatting tags kept.
+    Html,
+    Markdown,
+    Text,
+}
+
+impl MarkupFormat {
+    pub fn parse(format: &str) -> Option<MarkupFormat> {
+        match format {
+            "html" => Some(MarkupFormat::Html),
+            "markdown" => Some(MarkupFormat::Markdown),
+            "text" => Some(MarkupFormat::Text),
+            _ => None
+        }
+    }
+}
+
+/// Elements dropped with everything inside them.
+const DROPPED: [&str; 12] = ["script", "style", "head", "title", "iframe", "object", "embed", "noscript", "template", "svg", "math", "form"];
+/// Elements kept as they are in sanitised HTML, without their attributes.
+const KEPT: [&str; 21] = [
+    "p", "br", "hr", "ul", "ol", "li", "strong", "b", "em", "i", "u", "sub", "sup",
+    "h1", "h2", "h3", "h4", "h5", "h6", "blockquote", "pre",
+];
+const TABLE: [&str; 6] = ["table", "thead", "tbody", "tr", "th", "td"];
+/// Elements starting a new block, whatever the format.
+const BLOCKS: [&str; 11] = ["p", "div", "section", "article", "blockquote", "pre", "h1", "h2", "h3", "h4", "h5"];
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_markdown(text: &str, out: &mut String) {
+    for c in text.chars() {
+        if "\\`*_[]<>".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// Only links to web pages and mail addresses are kept.
+fn safe_href(href: &str) -> Option<&str> {
+    let href = href.trim();
+    let lower = href.to_lowercase();
+    let safe = lower.starts_with("http://") || lower.starts_with("https://") || lower.starts_with("mailto:");
+    if safe { Some(href) } else { None }
+}
+
+struct Writer {
+    format: MarkupFormat,
+    out: String,
+    /// A space is due before the next text, unless a line starts there.
+    space: bool,
+    /// Prefixes of the open lists, numbered for ordered ones.
+    lists: Vec<Option<u32>>,
+}
+
+impl Writer {
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn text(&mut self, text: &str) {
+        let starts_with_space = text.starts_with(char::is_whitespace);
+        let ends_with_space = text.ends_with(char::is_whitespace);
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.is_empty() {
+            self.space |= starts_with_space;
+            return;
+        }
+        if (self.space || starts_with_space) && !self.at_line_start() {
+            self.out.push(' ');
+        }
+        let text = words.join(" ");
+        match self.format {
+            MarkupFormat::Html => escape_html(&text, &mut self.out),
+            MarkupFormat::Markdown => escape_markdown(&text, &mut self.out),
+            MarkupFormat::Text => self.out.push_str(&text),
+        }
+        self.space = ends_with_space;
+    }
+
+    fn raw(&mut self, text: &str) {
+        self.out.push_str(text);
+        self.space = false;
+    }
+
+    fn line_break(&mut self) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        if !self.at_line_start() {
+            self.out.push('\n');
+        }
+        self.space = false;
+    }
+
+    fn block_break(&mut self) {
+        self.line_break();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.text(text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.element(child);
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        let name = element.value().name();
+        if DROPPED.contains(&name) {
+            return;
+        }
+        match self.format {
+            MarkupFormat::Html => self.html_element(name, element),
+            MarkupFormat::Markdown | MarkupFormat::Text => self.plain_element(name, element),
+        }
+    }
+
+    fn html_element(&mut self, name: &str, element: ElementRef) {
+        let name = if name == "div" { "p" } else { name };
+        if name == "br" || name == "hr" {
+            self.raw(&format!("<{}>", name));
+        } else if name == "a" {
+            match element.value().attr("href").and_then(safe_href) {
+                Some(href) => {
+                    let mut tag = String::from("<a href=\"");
+                    escape_html(href, &mut tag);
+                    tag.push_str("\">");
+                    self.raw(&tag);
+                    self.children(element);
+                    self.raw("</a>");
+                },
+                None => self.children(element)
+            }
+        } else if KEPT.contains(&name) || TABLE.contains(&name) {
+            self.raw(&format!("<{}>", name));
+            self.children(element);
+            self.raw(&format!("</{}>", name));
+        } else {
+            self.children(element);
+        }
+    }
+
+    fn plain_element(&mut self, name: &str, element: ElementRef) {
+        let markdown = self.format == MarkupFormat::Markdown;
+        match name {
+            "br" => {
+                // Unlike the end of a block, a second line break leaves a blank line.
+                if self.at_line_start() && !self.out.is_empty() && !self.out.ends_with("\n\n") {
+                    self.out.push('\n');
+                }
+                self.line_break();
+            },
+            "hr" => {
+                self.block_break();
+                if markdown {
+                    self.raw("---");
+                    self.block_break();
+                }
+            },
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.block_break();
+                if markdown {
+                    let level = name[1..].parse::<usize>().unwrap_or(1);
+                    self.raw(&format!("{} ", "#".repeat(level)));
+                }
+                self.children(element);
+                self.block_break();
+            },
+            "strong" | "b" | "em" | "i" if markdown => {
+                let mark = if name == "strong" || name == "b" { "**" } else { "*" };
+                if self.space && !self.at_line_start() {
+                    self.raw(" ");
+                }
+                self.raw(mark);
+                self.children(element);
+                self.raw(mark);
+            },
+            "a" if markdown => match element.value().attr("href").and_then(safe_href) {
+                Some(href) => {
+                    if self.space && !self.at_line_start() {
+                        self.raw(" ");
+                    }
+                    self.raw("[");
+                    self.children(element);
+                    self.raw(&format!("]({})", href.replace(' ', "%20").replace(')', "%29")));
+                },
+                None => self.children(element)
+            },
+            "ul" | "ol" => {
+                self.line_break();
+                self.lists.push(if name == "ol" { Some(0) } else { None });
+                self.children(element);
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.block_break();
+                }
+            },
+            "li" => {
+                self.line_break();
+                let depth = self.lists.len().saturating_sub(1);
+                let prefix = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", number)
+                    },
+                    _ => "- ".to_owned()
+                };
+                self.raw(&format!("{}{}", "  ".repeat(depth), prefix));
+                self.children(element);
+                self.line_break();
+            },
+            "tr" => {
+                self.line_break();
+                let cells: Vec<ElementRef> = element.children()
+                                                .filter_map(ElementRef::wrap)
+                                                .filter(|cell| cell.value().name() == "td" || cell.value().name() == "th")
+                                                .collect();
+                let separator = if markdown { " | " } else { "\t" };
+                if markdown {
+                    self.raw("| ");
+                }
+                for (i, cell) in cells.iter().enumerate() {
+                    if i > 0 {
+                        self.raw(separator);
+                    }
+                    self.children(*cell);
+                }
+                if markdown {
+                    self.raw(" |");
+                }
+                self.line_break();
+            },
+            "table" => {
+                self.block_break();
+                self.children(element);
+                self.block_break();
+            },
+            name if BLOCKS.contains(&name) => {
+                self.block_break();
+                self.children(element);
+                self.block_break();
+            },
+            _ => self.children(element)
+        }
+    }
+}
+
+/// Convert a piece of HTML from TIS, which may hold anything, into safe
+/// HTML, Markdown or plain text. Scripts, styles, attributes and links to
+/// anything but web pages are dropped.
+pub fn convert(html: &str, format: MarkupFormat) -> String {
+    let fragment = Html::parse_fragment(html);
+    let mut writer = Writer {
+        format,
+        out: String::new(),
+        space: false,
+        lists: Vec::new(),
+    };
+    writer.children(fragment.root_element());
+    writer.out.trim().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert, MarkupFormat};
+
+    const OUTLINE: &str = "<p style=\"color:red\" onclick=\"alert(1)\">本课程介绍<b>机器学习</b>的&nbsp;基本方法。</p>\
+        <script>alert('x')</script>\
+        <ul><li>监督学习</li><li>无监督学习</li></ul>\
+        <div>参考：<a href=\"https://example.com/ml\">课程主页</a> <a href=\"javascript:alert(1)\">链接</a></div>";
+
+    #[test]
+    fn test_convert_html() {
+        assert_eq!(
+            convert(OUTLINE, MarkupFormat::Html),
+            "<p>本课程介绍<b>机器学习</b>的 基本方法。</p><ul><li>监督学习</li><li>无监督学习</li></ul>\
+             <p>参考：<a href=\"https://example.com/ml\">课程主页</a> 链接</p>"
+        );
+    }
+
+    #[test]
+    fn test_convert_markdown() {
+        assert_eq!(
+            convert(OUTLINE, MarkupFormat::Markdown),
+            "本课程介绍**机器学习**的 基本方法。\n\n- 监督学习\n- 无监督学习\n\n参考：[课程主页](https://example.com/ml) 链接"
+        );
+        assert_eq!(convert("<ol><li>a_b</li><li>c</li></ol>", MarkupFormat::Markdown), "1. a\\_b\n2. c");
+    }
+
+    #[test]
+    fn test_convert_text() {
+        assert_eq!(
+            convert(OUTLINE, MarkupFormat::Text),
+            "本课程介绍机器学习的 基本方法。\n\n- 监督学习\n- 无监督学习\n\n参考：课程主页 链接"
+        );
+        assert_eq!(convert("a<br><br><br>b", MarkupFormat::Text), "a\n\nb");
+        assert_eq!(convert("第一行<br>第二行<table><tr><td>作业</td><td>30%</td></tr></table>", MarkupFormat::Text), "第一行\n第二行\n\n作业\t30%");
+    }
+}
@@ -0,0 +1,500 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::lock::Mutex;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::{self, fs::OpenOptions, io::AsyncWriteExt};
+
+use super::courses::{fetch_outline, FetchError};
+use super::parser::html_text;
+use super::structures::*;
+use super::throttle::Throttle;
+
+/// Seconds the collector sleeps when there is no outline to fetch.
+const IDLE_INTERVAL: u64 = 5;
+/// Times an outline is fetched before it is given up, unless the session
+/// expired.
+const MAX_ATTEMPTS: u32 = 3;
+/// BM25 parameters.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+/// Characters of context kept before the first match of a snippet, and the
+/// length of the snippet.
+const SNIPPET_CONTEXT: usize = 30;
+const SNIPPET_LENGTH: usize = 120;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct OutlinesConfig {
+    /// JSON lines file the outlines are appended to, memory only if unset.
+    pub store_file: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct OutlineRecord {
+    outline_id: String,
+    course_id: Option<String>,
+    course_name: Option<String>,
+    text: String,
+    fetched_at: String,
+}
+
+struct Document {
+    record: OutlineRecord,
+    length: u32,
+}
+
+/// An inverted index of the outlines, searched with BM25.
+#[derive(Default)]
+struct SearchIndex {
+    documents: Vec<Document>,
+    ids: HashMap<String, usize>,
+    /// Term to document to term frequency.
+    postings: HashMap<String, HashMap<usize, u32>>,
+    total_length: u64,
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '\u{f900}'..='\u{faff}')
+}
+
+/// Split `text` into lowercase words for latin text and into overlapping
+/// pairs of characters for Chinese, which has no spaces between words.
+fn tokenize(text: &str) -> Vec<String> {
+    fn flush(run: &mut Vec<char>, cjk: bool, tokens: &mut Vec<String>) {
+        if cjk && run.len() > 1 {
+            tokens.extend(run.windows(2).map(|pair| pair.iter().collect::<String>()));
+        } else if !run.is_empty() {
+            tokens.push(run.iter().collect());
+        }
+        run.clear();
+    }
+
+    let mut tokens = Vec::<String>::new();
+    let mut run = Vec::<char>::new();
+    let mut run_cjk = false;
+    for c in text.chars().flat_map(|c| c.to_lowercase()) {
+        let cjk = is_cjk(c);
+        if !cjk && !c.is_alphanumeric() {
+            flush(&mut run, run_cjk, &mut tokens);
+            continue;
+        }
+        if cjk != run_cjk {
+            flush(&mut run, run_cjk, &mut tokens);
+            run_cjk = cjk;
+        }
+        run.push(c);
+    }
+    flush(&mut run, run_cjk, &mut tokens);
+    tokens
+}
+
+fn escape(c: char, out: &mut String) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        c => out.push(c),
+    }
+}
+
+/// A piece of `text` around the first match of the terms, HTML escaped and
+/// with the matches wrapped in `<mark>`.
+fn snippet(text: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    // One lowercase character per character, so that positions match.
+    let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+    let mut marked = vec![false; chars.len()];
+    for term in terms {
+        let term: Vec<char> = term.chars().collect();
+        if term.is_empty() || term.len() > lower.len() {
+            continue;
+        }
+        for start in 0..=lower.len() - term.len() {
+            if lower[start..start + term.len()] == term[..] {
+                marked[start..start + term.len()].iter_mut().for_each(|m| *m = true);
+            }
+        }
+    }
+
+    let first = marked.iter().position(|m| *m).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_CONTEXT);
+    let end = (start + SNIPPET_LENGTH).min(chars.len());
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    for i in start..end {
+        if marked[i] && (i == start || !marked[i - 1]) {
+            snippet.push_str("<mark>");
+        }
+        escape(chars[i], &mut snippet);
+        if marked[i] && (i + 1 == end || !marked[i + 1]) {
+            snippet.push_str("</mark>");
+        }
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+impl SearchIndex {
+    fn insert(&mut self, mut record: OutlineRecord) {
+        if let Some(&i) = self.ids.get(&record.outline_id) {
+            // Keep the names learnt from an earlier listing of the class.
+            let old = &self.documents[i].record;
+            record.course_id = record.course_id.or_else(|| old.course_id.clone());
+            record.course_name = record.course_name.or_else(|| old.course_name.clone());
+            for postings in self.postings.values_mut() {
+                postings.remove(&i);
+            }
+            self.postings.retain(|_, postings| !postings.is_empty());
+            self.total_length -= self.documents[i].length as u64;
+        }
+
+        let content = format!(
+            "{} {} {}",
+            record.course_id.as_deref().unwrap_or_default(),
+            record.course_name.as_deref().unwrap_or_default(),
+            record.text
+        );
+        let tokens = tokenize(&content);
+        let i = match self.ids.get(&record.outline_id) {
+            Some(&i) => i,
+            None => {
+                self.ids.insert(record.outline_id.clone(), self.documents.len());
+                self.documents.push(Document { record: record.clone(), length: 0 });
+                self.documents.len() - 1
+            }
+        };
+        for token in tokens.iter() {
+            *self.postings.entry(token.clone()).or_default().entry(i).or_default() += 1;
+        }
+        self.documents[i] = Document { record, length: tokens.len() as u32 };
+        self.total_length += tokens.len() as u64;
+    }
+
+    fn search(&self, query: &str, limit: usize) -> (usize, Vec<OutlineHit>) {
+        let mut terms = Vec::<String>::new();
+        for term in tokenize(query) {
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+        let count = self.documents.len() as f64;
+        let average_length = self.total_length as f64 / count.max(1.0);
+
+        let mut scores = HashMap::<usize, f64>::new();
+        for term in &terms {
+            let postings = match self.postings.get(term) {
+                Some(postings) => postings,
+                None => continue
+            };
+            let frequency = postings.len() as f64;
+            let idf = (1.0 + (count - frequency + 0.5) / (frequency + 0.5)).ln();
+            for (&i, &tf) in postings {
+                let tf = tf as f64;
+                let length = self.documents[i].length as f64;
+                let norm = K1 * (1.0 - B + B * length / average_length.max(1.0));
+                *scores.entry(i).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut ranked: Vec<(usize, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|(a, a_score), (b, b_score)| {
+            b_score.partial_cmp(a_score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| self.documents[*a].record.outline_id.cmp(&self.documents[*b].record.outline_id))
+        });
+        let total = ranked.len();
+        let hits = ranked.into_iter()
+                    .take(limit)
+                    .map(|(i, score)| {
+                        let record = &self.documents[i].record;
+                        OutlineHit {
+                            outline_id: record.outline_id.clone(),
+                            course_id: record.course_id.clone(),
+                            course_name: record.course_name.clone(),
+                            score,
+                            snippet: snippet(&record.text, &terms),
+                        }
+                    })
+                    .collect();
+        (total, hits)
+    }
+}
+
+struct Job {
+    username: String,
+    client: reqwest::Client,
+    outline_id: String,
+    course_id: String,
+    course_name: String,
+    attempts: u32,
+}
+
+struct OutlinesInner {
+    store_file: Option<String>,
+    throttle: Arc<Throttle>,
+    index: Mutex<SearchIndex>,
+    queue: Mutex<VecDeque<Job>>,
+    running: AtomicBool,
+}
+
+/// Full-text index of the course outlines. An outline is added whenever a
+/// user reads it, and the outlines of all the classes of a semester can be
+/// queued to be fetched in the background, sharing the rate limit of TIS.
+#[derive(Clone)]
+pub struct Outlines {
+    inner: Arc<OutlinesInner>,
+}
+
+/// The fields of an outline which are indexed, all of them HTML.
+const INDEXED_FIELDS: [&str; 4] = ["kczwjj", "kcywjj", "jxmb", "kcmb"];
+
+/// The text of the descriptions and objectives of the `kcdgbentity` of an
+/// outline.
+pub fn outline_text(entity: &serde_json::Value) -> String {
+    INDEXED_FIELDS.iter()
+        .filter_map(|key| entity[*key].as_str())
+        .map(html_text)
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Replace the store file with the outlines of the index.
+fn compact_store(file: &str, index: &SearchIndex) -> std::io::Result<()> {
+    let mut lines = String::new();
+    for document in &index.documents {
+        if let Ok(line) = serde_json::to_string(&document.record) {
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+    }
+    let temp = format!("{}.tmp", file);
+    std::fs::write(&temp, lines)?;
+    std::fs::rename(&temp, file)
+}
+
+impl Outlines {
+    pub fn load(config: &OutlinesConfig, throttle: Arc<Throttle>) -> Self {
+        let mut index = SearchIndex::default();
+        let records = config.store_file
+                            .as_ref()
+                            .and_then(|file| std::fs::read_to_string(file).ok())
+                            .unwrap_or_default();
+        let mut lines = 0;
+        for line in records.lines() {
+            lines += 1;
+            if let Ok(record) = serde_json::from_str::<OutlineRecord>(line) {
+                index.insert(record);
+            }
+        }
+        // Every new version of an outline is appended to the file, so it is
+        // rewritten with the latest versions only.
+        if let Some(file) = config.store_file.as_ref().filter(|_| lines > index.documents.len()) {
+            if let Err(_e) = compact_store(file, &index) {
+                #[cfg(debug_assertions)]
+                println!("Unable to compact the outlines in {}: {}", file, _e);
+            }
+        }
+        Outlines {
+            inner: Arc::new(OutlinesInner {
+                store_file: config.store_file.clone(),
+                throttle,
+                index: Mutex::new(index),
+                queue: Mutex::new(VecDeque::new()),
+                running: AtomicBool::new(false),
+            })
+        }
+    }
+
+    /// Spawn the collecting loop, at most once.
+    pub fn start(&self) {
+        if self.inner.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let outlines = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let job = outlines.inner.queue.lock().await.pop_front();
+                let job = match job {
+                    Some(job) => job,
+                    None => {
+                        tokio::time::sleep(Duration::from_secs(IDLE_INTERVAL)).await;
+                        continue;
+                    }
+                };
+                outlines.inner.throttle.wait().await;
+                match fetch_outline(&job.client, &job.outline_id).await {
+                    Ok(entity) => outlines.add(&job.outline_id, Some(&job.course_id), Some(&job.course_name), &entity).await,
+                    Err(FetchError::SessionExpired) => {
+                        let mut queue = outlines.inner.queue.lock().await;
+                        queue.retain(|other| other.username != job.username);
+                    },
+                    Err(_e) => {
+                        #[cfg(debug_assertions)]
+                        println!("Unable to fetch the outline {}: {:?}", job.outline_id, _e);
+                        if job.attempts + 1 < MAX_ATTEMPTS {
+                            outlines.inner.queue.lock().await.push_back(Job { attempts: job.attempts + 1, ..job });
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Index an outline, replacing the previous version of it.
+    pub async fn add(
+        &self,
+        outline_id: &str,
+        course_id: Option<&str>,
+        course_name: Option<&str>,
+        entity: &serde_json::Value,
+    ) {
+        let record = OutlineRecord {
+            outline_id: outline_id.to_owned(),
+            course_id: course_id.map(|id| id.to_owned()),
+            course_name: course_name.map(|name| name.to_owned()),
+            text: outline_text(entity),
+            fetched_at: chrono::Local::now().to_rfc3339(),
+        };
+        if let (Some(file), Ok(line)) = (&self.inner.store_file, serde_json::to_string(&record)) {
+            let result = match OpenOptions::new().create(true).append(true).open(file).await {
+                Ok(mut f) => f.write_all(format!("{}\n", line).as_bytes()).await,
+                Err(e) => Err(e)
+            };
+            if let Err(_e) = result {
+                #[cfg(debug_assertions)]
+                println!("Unable to save the outline {}: {}", outline_id, _e);
+            }
+        }
+        self.inner.index.lock().await.insert(record);
+    }
+
+    /// Queue the outlines of `courses` which are not indexed yet, to be
+    /// fetched with the client of `username`. Returns how many were queued.
+    pub async fn enqueue(
+        &self,
+        username: &str,
+        client: &reqwest::Client,
+        courses: &[AvailableCourse],
+    ) -> usize {
+        let index = self.inner.index.lock().await;
+        let mut queue = self.inner.queue.lock().await;
+        let mut seen: HashSet<String> = queue.iter().map(|job| job.outline_id.clone()).collect();
+        let mut queued = 0;
+        for course in courses {
+            if course.outline_id.is_empty() || index.ids.contains_key(&course.outline_id) || !seen.insert(course.outline_id.clone()) {
+                continue;
+            }
+            queue.push_back(Job {
+                username: username.to_owned(),
+                client: client.clone(),
+                outline_id: course.outline_id.clone(),
+                course_id: course.advanced_course.basic_course.course_id.clone(),
+                course_name: course.advanced_course.basic_course.course_name.clone(),
+                attempts: 0,
+            });
+            queued += 1;
+        }
+        queued
+    }
+
+    pub async fn status(&self, added: usize) -> OutlineIndexStatus {
+        OutlineIndexStatus {
+            added,
+            queued: self.inner.queue.lock().await.len(),
+            indexed: self.inner.index.lock().await.documents.len(),
+        }
+    }
+
+    pub async fn search(&self, query: &str, limit: usize) -> OutlineSearch {
+        let index = self.inner.index.lock().await;
+        let (total, hits) = index.search(query, limit);
+        OutlineSearch {
+            indexed: index.documents.len(),
+            total,
+            hits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::super::throttle::{Throttle, ThrottleConfig};
+    use super::{snippet, tokenize, OutlineRecord, Outlines, OutlinesConfig, SearchIndex};
+
+    fn record(outline_id: &str, course_name: &str, text: &str) -> OutlineRecord {
+        OutlineRecord {
+            outline_id: outline_id.to_owned(),
+            course_id: None,
+            course_name: Some(course_name.to_owned()),
+            text: text.to_owned(),
+            fetched_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("Machine Learning 机器学习, C++"), vec!["machine", "learning", "机器", "器学", "学习", "c"]);
+        assert_eq!(tokenize("第3章"), vec!["第", "3", "章"]);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut index = SearchIndex::default();
+        index.insert(record("1", "人工智能", "本课程介绍机器学习的基本方法，包括监督学习与强化学习。"));
+        index.insert(record("2", "数据库原理", "关系模型、SQL 以及事务处理。"));
+        index.insert(record("3", "模式识别", "Pattern recognition and machine learning, with a project on deep learning."));
+        index.insert(record("4", "统计学习", "统计学习理论"));
+
+        let (total, hits) = index.search("机器学习", 10);
+        assert_eq!(total, 2);
+        assert_eq!(hits[0].outline_id, "1");
+        assert!(hits[0].snippet.contains("<mark>机器学习</mark>"));
+
+        let (_, hits) = index.search("machine learning", 10);
+        assert_eq!(hits[0].outline_id, "3");
+        assert!(hits[0].snippet.starts_with("Pattern recognition and <mark>machine</mark> <mark>learning</mark>"));
+
+        // A new version of an outline replaces the old one.
+        index.insert(record("2", "数据库原理", "查询优化与机器学习"));
+        assert_eq!(index.search("机器学习", 10).0, 3);
+        assert_eq!(index.search("事务", 10).0, 0);
+        assert_eq!(index.documents.len(), 4);
+    }
+
+    #[test]
+    fn test_snippet() {
+        let text = format!("{}<深度学习>{}", "前言".repeat(30), "后记".repeat(60));
+        let snippet = snippet(&text, &["深度".to_owned(), "学习".to_owned()]);
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("&lt;<mark>深度学习</mark>&gt;"));
+    }
+
+    #[test]
+    fn test_load_compacts_store() {
+        let file = std::env::temp_dir().join(format!("outlines-{}.jsonl", std::process::id()));
+        let lines: Vec<String> = [record("1", "人工智能", "旧版本"), record("2", "数据库原理", "事务"), record("1", "人工智能", "新版本")]
+                                    .iter()
+                                    .map(|record| serde_json::to_string(record).unwrap())
+                                    .collect();
+        std::fs::write(&file, lines.join("\n")).unwrap();
+
+        let config = OutlinesConfig { store_file: Some(file.to_string_lossy().into_owned()) };
+        let outlines = Outlines::load(&config, Arc::new(Throttle::new(&ThrottleConfig::default())));
+        let stored = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(stored.lines().count(), 2);
+        assert!(stored.contains("新版本") && !stored.contains("旧版本"));
+        assert_eq!(outlines.inner.index.try_lock().unwrap().documents.len(), 2);
+    }
+}
@@ -0,0 +1,775 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+
+use super::markup::{convert, MarkupFormat};
+use super::structures::*;
+
+/// Collapse the whitespace of `text` the way a browser renders it.
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn element_text(element: &ElementRef) -> String {
+    normalize(&element.text().collect::<String>())
+}
+
+fn section_kind(text: &str) -> Option<SectionKind> {
+    let text = text.to_lowercase();
+    let kinds = [
+        (SectionKind::Lecture, ["理论", "讲授", "讲课", "lecture"]),
+        (SectionKind::Lab, ["实验", "上机", "实践", "lab"]),
+        (SectionKind::Tutorial, ["习题", "讨论", "辅导", "tutorial"]),
+    ];
+    kinds.iter()
+        .find(|(_, keywords)| keywords.iter().any(|k| text.contains(k)))
+        .map(|(kind, _)| *kind)
+}
+
+/// A short paragraph naming a section, such as `[实验]` or `Lab`.
+fn section_label(text: &str) -> Option<(SectionKind, String)> {
+    let label = text.trim_matches(|c| "[]【】()（）:：".contains(c)).trim();
+    let bracketed = text.starts_with(|c| "[【(（".contains(c)) && text.ends_with(|c| "]】)）:：".contains(c));
+    if label.is_empty() || label.chars().count() > 12 {
+        return None;
+    }
+    match section_kind(label) {
+        Some(kind) => Some((kind, label.to_owned())),
+        None if bracketed => Some((SectionKind::Unknown, label.to_owned())),
+        None => None
+    }
+}
+
+fn looks_like_time(text: &str) -> bool {
+    ["周", "星期", "节", "week", "Week"].iter().any(|k| text.contains(k))
+}
+
+fn in_div(element: &ElementRef) -> bool {
+    element.ancestors()
+            .filter_map(|node| node.value().as_element())
+            .any(|e| e.name() == "div")
+}
+
+fn weeks_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r"(\d+(?:\s*-\s*\d+)?(?:\s*[,，、]\s*\d+(?:\s*-\s*\d+)?)*)\s*(单|双)?\s*周\s*(?:[(（]\s*(单|双)\s*周?[)）])?").unwrap()
+    })
+}
+
+fn weekday_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"(?:星期|周)\s*([一二三四五六日天1-7])").unwrap())
+}
+
+fn periods_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"第?\s*(\d+)\s*(?:-\s*(\d+))?\s*节").unwrap())
+}
+
+/// Read a week specification such as `1-8,10-16` with an optional
+/// `单`/`双` (odd/even) filter.
+pub fn parse_weeks(spec: &str, parity: Option<&str>) -> WeekSet {
+    let mut weeks = WeekSet::default();
+    for part in spec.split(|c| ",，、".contains(c)) {
+        let mut bounds = part.split('-').map(|n| n.trim().parse::<u32>());
+        let (start, end) = match (bounds.next(), bounds.next()) {
+            (Some(Ok(start)), None) => (start, start),
+            (Some(Ok(start)), Some(Ok(end))) => (start, end),
+            _ => continue
+        };
+        for week in start..=end.min(WeekSet::MAX_WEEK) {
+            match parity {
+                Some("单") if week % 2 == 0 => {},
+                Some("双") if week % 2 == 1 => {},
+                _ => weeks.insert(week)
+            }
+        }
+    }
+    weeks
+}
+
+fn weekday_number(day: &str) -> Option<u32> {
+    match day {
+        "一" => Some(1),
+        "二" => Some(2),
+        "三" => Some(3),
+        "四" => Some(4),
+        "五" => Some(5),
+        "六" => Some(6),
+        "日" | "天" => Some(7),
+        _ => day.parse().ok()
+    }
+}
+
+/// Split a location such as `一教101` or `荔园1栋 B101` into its building
+/// and its room, the room being the trailing number.
+fn split_location(location: &str) -> (Option<String>, Option<String>) {
+    let location = location.trim();
+    if location.is_empty() {
+        return (None, None);
+    }
+    let (building, room) = match location.rsplit_once(' ') {
+        Some((building, room)) if room.chars().any(|c| c.is_ascii_digit()) => (building.trim(), room),
+        _ => {
+            let start = location.char_indices()
+                                .rev()
+                                .take_while(|(_, c)| c.is_ascii_alphanumeric() || *c == '-')
+                                .last()
+                                .map_or(location.len(), |(i, _)| i);
+            let (building, room) = location.split_at(start);
+            if room.chars().any(|c| c.is_ascii_digit()) {
+                (building, room)
+            } else {
+                (location, "")
+            }
+        }
+    };
+    let non_empty = |s: &str| if s.is_empty() { None } else { Some(s.to_owned()) };
+    (non_empty(building), non_empty(room))
+}
+
+/// Parse one time and place line of a teaching section. Whatever is left
+/// once the weeks, the weekday and the periods are taken out is the location.
+pub fn parse_time_slot(raw: &str) -> TimeSlot {
+    let mut rest = raw.to_owned();
+    let mut weeks = WeekSet::default();
+    if let Some(captures) = weeks_regex().captures(&rest) {
+        let parity = captures.get(2).or_else(|| captures.get(3)).map(|m| m.as_str());
+        weeks = parse_weeks(&captures[1], parity);
+        rest = rest.replacen(&captures[0], " ", 1);
+    }
+    let mut weekday = None;
+    if let Some(captures) = weekday_regex().captures(&rest) {
+        weekday = weekday_number(&captures[1]);
+        rest = rest.replacen(&captures[0], " ", 1);
+    }
+    let (mut start_period, mut end_period) = (None, None);
+    if let Some(captures) = periods_regex().captures(&rest) {
+        start_period = captures[1].parse().ok();
+        end_period = captures.get(2).map_or(start_period, |m| m.as_str().parse().ok());
+        rest = rest.replacen(&captures[0], " ", 1);
+    }
+    let location = normalize(&rest.replace(|c| ",，;；".contains(c), " "));
+    let location = location.trim_start_matches(|c| "地点:：".contains(c)).trim();
+    let (building, room) = split_location(location);
+    TimeSlot {
+        weeks,
+        weekday,
+        start_period,
+        end_period,
+        building,
+        room,
+        raw: raw.to_owned(),
+    }
+}
+
+/// Read the day and the period of a course table cell from its key, such
+/// as `xq1_jc10` for the tenth period on Monday.
+pub fn parse_table_key(key: &str) -> Option<(u32, u32)> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = REGEX.get_or_init(|| Regex::new(r"xq(\d+)_jc(\d+)").unwrap());
+    if let Some(captures) = regex.captures(key) {
+        return Some((captures[1].parse().ok()?, captures[2].parse().ok()?));
+    }
+    // Any other key with the day and the period in this order.
+    let mut numbers = key.split(|c: char| !c.is_ascii_digit())
+                            .filter(|n| !n.is_empty())
+                            .map(|n| n.parse::<u32>());
+    match (numbers.next(), numbers.next()) {
+        (Some(Ok(day)), Some(Ok(time))) => Some((day, time)),
+        _ => None
+    }
+}
+
+/// The lines of a `kbxx` blob, which is either plain text or HTML. An empty
+/// line separates two courses.
+fn kbxx_lines(kbxx: &str) -> Vec<String> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = REGEX.get_or_init(|| Regex::new(r"(?i)<br\s*/?>|</(?:p|div|li)>|<hr\s*/?>").unwrap());
+    let text = regex.replace_all(kbxx, |captures: &regex::Captures| {
+        if captures[0].to_lowercase().starts_with("<hr") { "\n\n" } else { "\n" }
+    });
+    let text = Html::parse_fragment(&text).root_element().text().collect::<String>();
+    text.lines().map(normalize).collect()
+}
+
+/// The text of an HTML blob, a line per line break or block.
+pub fn html_text(html: &str) -> String {
+    kbxx_lines(html).into_iter()
+                    .filter(|line| !line.is_empty())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+}
+
+/// A line such as `高等数学(上)[01班-英文]` naming a course and its class.
+fn course_title(line: &str) -> Option<(String, Option<String>)> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = REGEX.get_or_init(|| Regex::new(r"^([^\[【]+?)\s*[\[【]([^\]】]+)[\]】]$").unwrap());
+    let captures = regex.captures(line)?;
+    if looks_like_time(&captures[2]) {
+        return None;
+    }
+    Some((captures[1].to_owned(), Some(captures[2].to_owned())))
+}
+
+/// Parse the `kbxx` of a course table cell into its courses. The first line
+/// of a course is its name, then come in any order its weeks, its location
+/// and its teachers.
+pub fn parse_course_table_info(kbxx: &str) -> Vec<CourseTableEntry> {
+    let mut entries = Vec::<CourseTableEntry>::new();
+    let mut current: Option<CourseTableEntry> = None;
+    for line in kbxx_lines(kbxx) {
+        if line.is_empty() {
+            entries.extend(current.take());
+            continue;
+        }
+        let title = course_title(&line);
+        let entry = match current.as_mut() {
+            Some(entry) if title.is_none() => entry,
+            _ => {
+                entries.extend(current.take());
+                let (course_name, course_class) = title.unwrap_or_else(|| (line.clone(), None));
+                current = Some(CourseTableEntry {
+                    course_name,
+                    course_class,
+                    teachers: Vec::new(),
+                    building: None,
+                    room: None,
+                    weeks: WeekSet::default(),
+                    raw: line,
+                });
+                continue;
+            }
+        };
+        entry.raw.push('\n');
+        entry.raw.push_str(&line);
+
+        // Teachers may well be called 周, so only a week or period
+        // specification makes a line a time.
+        if weeks_regex().is_match(&line) || periods_regex().is_match(&line) {
+            let slot = parse_time_slot(&line.replace(|c| "[]【】".contains(c), " "));
+            entry.weeks = entry.weeks.union(&slot.weeks);
+            if entry.room.is_none() && slot.room.is_some() {
+                entry.building = slot.building;
+                entry.room = slot.room;
+            }
+        } else if entry.room.is_none() && line.chars().any(|c| c.is_ascii_digit()) {
+            let (building, room) = split_location(line.trim_matches(|c| "[]【】".contains(c)));
+            entry.building = building;
+            entry.room = room;
+        } else {
+            entry.teachers.extend(line.split(|c| ",，、;；".contains(c))
+                                        .map(|t| t.trim().to_owned())
+                                        .filter(|t| !t.is_empty()));
+        }
+    }
+    entries.extend(current);
+    entries
+}
+
+/// Split `text` on the separators which are not inside brackets, as class
+/// names such as `[01班,英文]` may hold some.
+fn split_outside_brackets<'a>(text: &'a str, separators: &str) -> Vec<&'a str> {
+    let mut parts = Vec::<&str>::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' | '【' | '(' | '（' => depth += 1,
+            ']' | '】' | ')' | '）' => depth = (depth - 1).max(0),
+            c if depth == 0 && separators.contains(c) => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            },
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Parse the `ctkcxx` of an available class, the classes its timetable
+/// clashes with, such as `与[CS101]计算机导论[01班]、高等数学(上)[02班]冲突`.
+/// Each class may come with its course code and its class name, and any
+/// wording around the list is dropped.
+pub fn parse_conflict_courses(ctkcxx: &str) -> Vec<ConflictReference> {
+    static CODE: OnceLock<Regex> = OnceLock::new();
+    static WORDING: OnceLock<Regex> = OnceLock::new();
+    let code_regex = CODE.get_or_init(|| Regex::new(r"[\[【(（]?([A-Z]{2,4}\d{3}[A-Z]?)[\]】)）]?").unwrap());
+    let wording = WORDING.get_or_init(|| {
+        Regex::new(r"^(?:.*[:：])?\s*与?\s*|\s*(?:(?:上课)?时间)?有?冲突[!！。.]?$").unwrap()
+    });
+
+    let mut references = Vec::<ConflictReference>::new();
+    for line in kbxx_lines(ctkcxx) {
+        for item in split_outside_brackets(&line, ",，、;；") {
+            let raw = item.trim();
+            let text = wording.replace_all(raw, "");
+            let course_id = code_regex.captures(&text).map(|captures| captures[1].to_owned());
+            let text = normalize(&code_regex.replace(&text, " "));
+            if text.is_empty() && course_id.is_none() {
+                continue;
+            }
+            let (course_name, course_class) = course_title(&text).unwrap_or((text, None));
+            references.push(ConflictReference {
+                course_id,
+                course_name,
+                course_class,
+                selected_class_id: None,
+                clashing_slots: Vec::new(),
+                raw: raw.to_owned(),
+            });
+        }
+    }
+    references
+}
+
+/// Parse a course list of TAO, Chinese or English, into `CatalogCourse`
+/// rows. Every table but the first one, which is the page header, lists
+/// courses with their code, name and credits in the first columns, their
+/// hours when the list has them, and their department in the last column;
+/// other rows are skipped. `detail_url` is the link to the detail page as
+/// it is in the page.
+pub fn parse_catalog_list(html: &str) -> Vec<CatalogCourse> {
+    let fragment = Html::parse_fragment(html);
+    let table_selector = Selector::parse("table").unwrap();
+    let tr_selector = Selector::parse("tr").unwrap();
+    let td_selector = Selector::parse("td").unwrap();
+    let a_selector = Selector::parse("a[href]").unwrap();
+
+    let mut courses = Vec::<CatalogCourse>::new();
+    for table in fragment.select(&table_selector).skip(1) {
+        for tr in table.select(&tr_selector) {
+            let cells: Vec<String> = tr.select(&td_selector).map(|td| element_text(&td)).collect();
+            if cells.len() < 4 {
+                continue;
+            }
+            let credits = match cells[2].parse::<f32>() {
+                Ok(credits) => credits,
+                Err(_) => continue
+            };
+            courses.push(CatalogCourse {
+                course_id: cells[0].clone(),
+                course_name: cells[1].clone(),
+                credits,
+                hours: if cells.len() > 4 { leading_number(&cells[3]) } else { None },
+                department: cells[cells.len() - 1].clone(),
+                detail_url: tr.select(&a_selector).next().and_then(|a| a.value().attr("href")).map(|href| href.to_owned()),
+                ..Default::default()
+            });
+        }
+    }
+    courses
+}
+
+/// The number a text such as `64学时` starts with.
+fn leading_number(text: &str) -> Option<f32> {
+    let number: String = text.trim().chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+    number.parse::<f32>().ok()
+}
+
+/// Labels of the detail pages of TAO, lowercased and without spaces.
+const DETAIL_ENGLISH_NAME: [&str; 4] = ["课程英文名称", "英文名称", "courseenglishname", "englishname"];
+const DETAIL_HOURS: [&str; 5] = ["学时", "总学时", "hours", "totalhours", "classhours"];
+const DETAIL_DESCRIPTION: [&str; 6] = ["课程简介", "课程描述", "简介", "coursedescription", "courseintroduction", "description"];
+const DETAIL_PREREQUISITES: [&str; 6] = ["先修课程", "先修要求", "预修课程", "prerequisites", "prerequisite", "pre-requisites"];
+
+/// Read the detail page of a course on TAO, laid out as rows of labels each
+/// followed by its value.
+pub fn parse_course_detail(html: &str) -> CourseDetail {
+    let fragment = Html::parse_fragment(html);
+    let tr_selector = Selector::parse("tr").unwrap();
+    let cell_selector = Selector::parse("th, td").unwrap();
+
+    let mut detail = CourseDetail::default();
+    for tr in fragment.select(&tr_selector) {
+        let cells: Vec<ElementRef> = tr.select(&cell_selector).collect();
+        for pair in cells.chunks(2) {
+            let (label, value) = match pair {
+                [label, value] => (label, value),
+                _ => continue
+            };
+            let label: String = element_text(label)
+                                    .chars()
+                                    .filter(|c| !c.is_whitespace() && *c != ':' && *c != '：')
+                                    .collect::<String>()
+                                    .to_lowercase();
+            let value = convert(&value.inner_html(), MarkupFormat::Text);
+            if value.is_empty() {
+                continue;
+            }
+            let label = label.as_str();
+            if DETAIL_ENGLISH_NAME.contains(&label) {
+                detail.english_name.get_or_insert(value);
+            } else if DETAIL_HOURS.contains(&label) {
+                detail.hours = detail.hours.or_else(|| leading_number(&value));
+            } else if DETAIL_DESCRIPTION.contains(&label) {
+                detail.description.get_or_insert(value);
+            } else if DETAIL_PREREQUISITES.contains(&label) {
+                detail.prerequisites.get_or_insert(value);
+            }
+        }
+    }
+    detail
+}
+
+/// Keys of `kcdgbentity` read into the fields of a course outline, the
+/// first one set wins. TIS does not fill all of them for every course.
+const OUTLINE_COURSE_ID: [&str; 1] = ["kcdm"];
+const OUTLINE_COURSE_NAME: [&str; 2] = ["kcmc", "kczwmc"];
+const OUTLINE_ENGLISH_NAME: [&str; 2] = ["kcywmc", "ywmc"];
+const OUTLINE_CREDITS: [&str; 1] = ["xf"];
+const OUTLINE_TOTAL_HOURS: [&str; 2] = ["zxs", "xs"];
+const OUTLINE_LECTURE_HOURS: [&str; 2] = ["llxs", "jkxs"];
+const OUTLINE_LAB_HOURS: [&str; 1] = ["syxs"];
+const OUTLINE_PRACTICE_HOURS: [&str; 1] = ["sjxs"];
+const OUTLINE_DESCRIPTION: [&str; 1] = ["kczwjj"];
+const OUTLINE_ENGLISH_DESCRIPTION: [&str; 1] = ["kcywjj"];
+const OUTLINE_OBJECTIVES: [&str; 2] = ["jxmb", "kcmb"];
+const OUTLINE_ASSESSMENT: [&str; 3] = ["khfs", "cjpd", "kcpj"];
+const OUTLINE_TEXTBOOKS: [&str; 3] = ["jc", "jcmc", "jyjc"];
+const OUTLINE_REFERENCES: [&str; 3] = ["cksm", "cks", "ckzl"];
+const OUTLINE_PREREQUISITES: [&str; 3] = ["xxkc", "xxyq", "yxkc"];
+
+/// The string or number under the first of `keys` which is set.
+fn outline_field(entity: &serde_json::Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .filter_map(|key| match &entity[*key] {
+            serde_json::Value::String(value) => Some(value.trim().to_owned()),
+            serde_json::Value::Number(value) => Some(value.to_string()),
+            _ => None
+        })
+        .find(|value| !value.is_empty())
+}
+
+/// Items such as `平时作业 20%` or `Final exam: 50%` of an assessment text.
+pub fn parse_assessment(text: &str) -> Vec<AssessmentItem> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = REGEX.get_or_init(|| {
+        Regex::new(r"([^\d%％,，;；、:：()（）\n]+?)\s*[:：]?\s*[（(]?\s*(\d+(?:\.\d+)?)\s*[%％]").unwrap()
+    });
+    regex.captures_iter(text)
+        .filter_map(|captures| {
+            let name = captures[1].trim().trim_end_matches(|c: char| "占约为 ".contains(c)).trim();
+            let weight = captures[2].parse::<f32>().ok()?;
+            if name.is_empty() { None } else { Some(AssessmentItem { name: name.to_owned(), weight }) }
+        })
+        .collect()
+}
+
+/// The entries of a list of books, one per line, without their numbering.
+fn book_list(text: &str) -> Vec<String> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = REGEX.get_or_init(|| Regex::new(r"^\s*(?:[\[【(（]?\d+[\]】)）.、]|[-•·])\s*").unwrap());
+    text.lines()
+        .map(|line| regex.replace(line, "").trim().to_owned())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Read the `kcdgbentity` of TIS into a course outline, converting its HTML
+/// fields into `format`. The fields this does not know about are kept in
+/// `other_fields`.
+pub fn parse_course_outline(outline_id: &str, entity: &serde_json::Value, format: MarkupFormat) -> CourseOutline {
+    let text = |keys: &[&str]| outline_field(entity, keys).map(|html| convert(&html, MarkupFormat::Text));
+    let rich = |keys: &[&str]| outline_field(entity, keys).map(|html| convert(&html, format)).filter(|v| !v.is_empty());
+    let number = |keys: &[&str]| outline_field(entity, keys).and_then(|value| value.parse::<f32>().ok());
+
+    let known: Vec<&str> = [
+        &OUTLINE_COURSE_ID[..], &OUTLINE_COURSE_NAME, &OUTLINE_ENGLISH_NAME, &OUTLINE_CREDITS,
+        &OUTLINE_TOTAL_HOURS, &OUTLINE_LECTURE_HOURS, &OUTLINE_LAB_HOURS, &OUTLINE_PRACTICE_HOURS,
+        &OUTLINE_DESCRIPTION, &OUTLINE_ENGLISH_DESCRIPTION, &OUTLINE_OBJECTIVES, &OUTLINE_ASSESSMENT,
+        &OUTLINE_TEXTBOOKS, &OUTLINE_REFERENCES, &OUTLINE_PREREQUISITES,
+    ].concat();
+    let other_fields = entity.as_object()
+                            .map(|object| {
+                                object.keys()
+                                    .filter(|key| !known.contains(&key.as_str()))
+                                    .filter_map(|key| Some((key.clone(), rich(&[key.as_str()])?)))
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+
+    CourseOutline {
+        outline_id: outline_id.to_owned(),
+        course_id: outline_field(entity, &OUTLINE_COURSE_ID),
+        course_name: text(&OUTLINE_COURSE_NAME),
+        english_name: text(&OUTLINE_ENGLISH_NAME),
+        credits: number(&OUTLINE_CREDITS),
+        hours: CourseHours {
+            total: number(&OUTLINE_TOTAL_HOURS),
+            lecture: number(&OUTLINE_LECTURE_HOURS),
+            lab: number(&OUTLINE_LAB_HOURS),
+            practice: number(&OUTLINE_PRACTICE_HOURS),
+        },
+        description: rich(&OUTLINE_DESCRIPTION),
+        english_description: rich(&OUTLINE_ENGLISH_DESCRIPTION),
+        objectives: rich(&OUTLINE_OBJECTIVES),
+        assessment: rich(&OUTLINE_ASSESSMENT),
+        assessment_items: text(&OUTLINE_ASSESSMENT).map(|t| parse_assessment(&t)).unwrap_or_default(),
+        textbooks: text(&OUTLINE_TEXTBOOKS).map(|t| book_list(&t)).unwrap_or_default(),
+        references: text(&OUTLINE_REFERENCES).map(|t| book_list(&t)).unwrap_or_default(),
+        prerequisites: text(&OUTLINE_PREREQUISITES).filter(|t| !t.is_empty()),
+        other_fields,
+    }
+}
+
+/// The section being filled. With `new`, a section which already has
+/// teachers or time slots is closed first, while one only holding a label
+/// is kept for what follows it.
+fn section<'a>(
+    sections: &mut Vec<TeachingSection>,
+    current: &'a mut Option<TeachingSection>,
+    new: bool
+) -> &'a mut TeachingSection {
+    let started = current.as_ref().is_some_and(|s| !s.teachers.is_empty() || !s.time_slots.is_empty());
+    if new && started {
+        sections.extend(current.take());
+    }
+    current.get_or_insert_with(|| TeachingSection {
+        kind: SectionKind::Unknown,
+        label: None,
+        teachers: Vec::new(),
+        time_slots: Vec::new(),
+    })
+}
+
+/// Parse the `kcxx` HTML of a class into its teaching sections.
+///
+/// TIS does not use a single layout for it, so the paragraphs are read in
+/// order instead of by position: a paragraph with teacher links, a plain
+/// list of teachers or a label such as `[实验]` starts a new section, and
+/// the paragraphs of a `div` or mentioning weeks and periods are the time
+/// slots of the current one. Anything else is ignored rather than failing.
+pub fn parse_course_info(course_info_html: &str) -> Vec<TeachingSection> {
+    let fragment = Html::parse_fragment(course_info_html);
+    let p_selector = Selector::parse("p").unwrap();
+    let a_selector = Selector::parse("a").unwrap();
+
+    let mut sections = Vec::<TeachingSection>::new();
+    let mut current: Option<TeachingSection> = None;
+
+    for p in fragment.select(&p_selector) {
+        let text = element_text(&p);
+        if text.is_empty() {
+            continue;
+        }
+        let teachers: Vec<String> = p.select(&a_selector)
+                                        .map(|a| element_text(&a))
+                                        .filter(|t| !t.is_empty())
+                                        .collect();
+        if !teachers.is_empty() {
+            let mut rest = text.clone();
+            for teacher in &teachers {
+                rest = rest.replacen(teacher, "", 1);
+            }
+            let section = section(&mut sections, &mut current, true);
+            if let Some(kind) = section_kind(&rest) {
+                section.kind = kind;
+            }
+            section.teachers = teachers;
+        } else if let Some((kind, label)) = section_label(&text) {
+            let section = section(&mut sections, &mut current, true);
+            section.kind = kind;
+            section.label = Some(label);
+        } else if in_div(&p) || looks_like_time(&text) {
+            section(&mut sections, &mut current, false).time_slots.push(parse_time_slot(&text));
+        } else {
+            section(&mut sections, &mut current, true).teachers = text.split(|c| ",，、;；".contains(c))
+                                                                        .map(|t| t.trim().to_owned())
+                                                                        .filter(|t| !t.is_empty())
+                                                                        .collect();
+        }
+    }
+    sections.extend(current);
+    sections.retain(|s| !s.teachers.is_empty() || !s.time_slots.is_empty());
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::structures::SectionKind;
+    use super::super::markup::MarkupFormat;
+    use super::{parse_assessment, parse_catalog_list, parse_conflict_courses, parse_course_detail, parse_course_info, parse_course_outline, parse_course_table_info, parse_table_key, parse_time_slot, parse_weeks};
+
+    #[test]
+    fn test_single_section() {
+        let sections = parse_course_info(include_str!("../tests/fixtures/course_info_single.html"));
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].teachers, vec!["张三", "李四"]);
+        let raw: Vec<&str> = sections[0].time_slots.iter().map(|s| s.raw.as_str()).collect();
+        assert_eq!(raw, vec!["1-16周 星期一 第3-4节 一教101", "1-16周 星期三 第3-4节 一教101"]);
+    }
+
+    #[test]
+    fn test_lecture_and_lab() {
+        let sections = parse_course_info(include_str!("../tests/fixtures/course_info_pair.html"));
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].kind, SectionKind::Lecture);
+        assert_eq!(sections[0].teachers, vec!["王五"]);
+        assert_eq!(sections[0].time_slots.len(), 2);
+        assert_eq!(sections[1].kind, SectionKind::Lab);
+        assert_eq!(sections[1].label.as_deref(), Some("实验"));
+        assert_eq!(sections[1].teachers, vec!["赵六"]);
+        assert_eq!(sections[1].time_slots[0].weeks.weeks(), vec![2, 4, 6, 8, 10, 12, 14, 16]);
+    }
+
+    #[test]
+    fn test_three_sections() {
+        let sections = parse_course_info(include_str!("../tests/fixtures/course_info_three.html"));
+        let kinds: Vec<SectionKind> = sections.iter().map(|s| s.kind).collect();
+        assert_eq!(kinds, vec![SectionKind::Lecture, SectionKind::Lab, SectionKind::Tutorial]);
+        assert!(sections.iter().all(|s| s.time_slots.len() == 1));
+    }
+
+    #[test]
+    fn test_no_teacher_links() {
+        let sections = parse_course_info(include_str!("../tests/fixtures/course_info_plain.html"));
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].teachers, vec!["钱七", "孙八"]);
+        assert_eq!(sections[0].time_slots[0].building.as_deref(), Some("荔园1栋"));
+    }
+
+    #[test]
+    fn test_unknown_layout() {
+        assert!(parse_course_info("").is_empty());
+        assert!(parse_course_info("<span>暂无</span>").is_empty());
+        let sections = parse_course_info("<div><div><p>1-16周 星期四 第7-8节</p></div></div>");
+        assert_eq!(sections.len(), 1);
+        assert!(sections[0].teachers.is_empty());
+    }
+
+    #[test]
+    fn test_parse_weeks() {
+        assert_eq!(parse_weeks("1-4,7", None).weeks(), vec![1, 2, 3, 4, 7]);
+        assert_eq!(parse_weeks("1-9", Some("单")).weeks(), vec![1, 3, 5, 7, 9]);
+        assert_eq!(parse_weeks("3-6", Some("双")).weeks(), vec![4, 6]);
+        assert!(parse_weeks("x-y", None).is_empty());
+    }
+
+    #[test]
+    fn test_parse_time_slot() {
+        let slot = parse_time_slot("1-16周 星期一 第3-4节 一教101");
+        assert_eq!(slot.weeks.weeks(), (1..=16).collect::<Vec<u32>>());
+        assert_eq!((slot.weekday, slot.start_period, slot.end_period), (Some(1), Some(3), Some(4)));
+        assert_eq!((slot.building.as_deref(), slot.room.as_deref()), (Some("一教"), Some("101")));
+
+        let slot = parse_time_slot("星期日 第11节 1-7,9-15单周 荔园1栋 B101");
+        assert_eq!(slot.weeks.weeks(), vec![1, 3, 5, 7, 9, 11, 13, 15]);
+        assert_eq!((slot.weekday, slot.start_period, slot.end_period), (Some(7), Some(11), Some(11)));
+        assert_eq!((slot.building.as_deref(), slot.room.as_deref()), (Some("荔园1栋"), Some("B101")));
+
+        let slot = parse_time_slot("3-15周(单) 周三 第7-8节 实验楼105");
+        assert_eq!(slot.weeks.weeks(), vec![3, 5, 7, 9, 11, 13, 15]);
+        assert_eq!(slot.weekday, Some(3));
+        assert_eq!(slot.building.as_deref(), Some("实验楼"));
+
+        let slot = parse_time_slot("待定");
+        assert!(slot.weeks.is_empty());
+        assert_eq!((slot.weekday, slot.building.as_deref(), slot.room), (None, Some("待定"), None));
+        assert_eq!(slot.raw, "待定");
+    }
+
+    #[test]
+    fn test_parse_table_key() {
+        assert_eq!(parse_table_key("xq1_jc3"), Some((1, 3)));
+        assert_eq!(parse_table_key("xq7_jc11"), Some((7, 11)));
+        assert_eq!(parse_table_key("day2-time10"), Some((2, 10)));
+        assert_eq!(parse_table_key("xq"), None);
+    }
+
+    #[test]
+    fn test_parse_course_table_info() {
+        let entries = parse_course_table_info(include_str!("../tests/fixtures/course_table_cell.html"));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].course_name, "高等数学(上)");
+        assert_eq!(entries[0].course_class.as_deref(), Some("01班-英文"));
+        assert_eq!(entries[0].teachers, vec!["张三", "李四"]);
+        assert_eq!((entries[0].building.as_deref(), entries[0].room.as_deref()), (Some("一教"), Some("101")));
+        assert_eq!(entries[0].weeks.weeks(), (1..=16).collect::<Vec<u32>>());
+        assert_eq!(entries[1].course_name, "大学物理实验");
+        assert_eq!(entries[1].weeks.weeks(), vec![2, 4, 6, 8]);
+        assert_eq!(entries[1].room.as_deref(), Some("305"));
+
+        let entries = parse_course_table_info("体育\n王五");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].course_class, None);
+        assert_eq!(entries[0].teachers, vec!["王五"]);
+        assert!(parse_course_table_info("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_conflict_courses() {
+        let references = parse_conflict_courses("与[CS101]计算机导论[01班]、高等数学(上)[02班,英文]冲突");
+        assert_eq!(references.len(), 2);
+        assert_eq!(references[0].course_id.as_deref(), Some("CS101"));
+        assert_eq!(references[0].course_name, "计算机导论");
+        assert_eq!(references[0].course_class.as_deref(), Some("01班"));
+        assert_eq!(references[1].course_id, None);
+        assert_eq!(references[1].course_name, "高等数学(上)");
+        assert_eq!(references[1].course_class.as_deref(), Some("02班,英文"));
+        assert_eq!(references[1].raw, "高等数学(上)[02班,英文]冲突");
+
+        let references = parse_conflict_courses("上课时间冲突：MA101A 线性代数<br>PHY105B大学物理");
+        let names: Vec<(Option<&str>, &str)> = references.iter()
+                                                .map(|r| (r.course_id.as_deref(), r.course_name.as_str()))
+                                                .collect();
+        assert_eq!(names, vec![(Some("MA101A"), "线性代数"), (Some("PHY105B"), "大学物理")]);
+        assert!(parse_conflict_courses("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_course_list() {
+        let courses = parse_catalog_list(include_str!("../tests/fixtures/course_list.html"));
+        assert_eq!(courses.len(), 3);
+        assert_eq!(courses[0].course_id, "MA101B");
+        assert_eq!(courses[0].course_name, "高等数学(上)A");
+        assert_eq!(courses[0].credits, 4.0);
+        assert_eq!(courses[0].department, "数学系");
+        assert_eq!(courses[2].course_name, "R&D Lab");
+        assert_eq!(courses[2].credits, 1.5);
+        assert_eq!(courses[0].hours, Some(64.0));
+        assert_eq!(courses[0].detail_url.as_deref(), Some("/kcxxweb/KcxxwebChinesePC?kcdm=MA101B"));
+        assert_eq!(courses[2].hours, Some(48.0));
+    }
+
+    #[test]
+    fn test_parse_course_detail() {
+        let detail = parse_course_detail(include_str!("../tests/fixtures/course_detail.html"));
+        assert_eq!(detail.english_name.as_deref(), Some("Calculus I A"));
+        assert_eq!(detail.hours, Some(64.0));
+        assert_eq!(detail.description.as_deref(), Some("本课程介绍一元函数微积分。\n\n内容包括极限、导数与积分。"));
+        assert_eq!(detail.prerequisites.as_deref(), Some("无"));
+        let detail = parse_course_detail(include_str!("../tests/fixtures/course_detail_en.html"));
+        assert_eq!(detail.description.as_deref(), Some("Limits, derivatives and integrals of functions of one variable."));
+        assert_eq!(detail.prerequisites.as_deref(), Some("None"));
+        assert_eq!(detail.english_name, None);
+    }
+
+    #[test]
+    fn test_parse_assessment() {
+        let items = parse_assessment("考核方式：平时作业 20%，期中考试（30%），期末考试占50%");
+        let items: Vec<(&str, f32)> = items.iter().map(|i| (i.name.as_str(), i.weight)).collect();
+        assert_eq!(items, vec![("平时作业", 20.0), ("期中考试", 30.0), ("期末考试", 50.0)]);
+        let items = parse_assessment("Homework: 25%; Final exam 75%");
+        assert_eq!(items[1].name, "Final exam");
+    }
+
+    #[test]
+    fn test_parse_course_outline() {
+        let entity: serde_json::Value = serde_json::from_str(include_str!("../tests/fixtures/course_outline.json")).unwrap();
+        let outline = parse_course_outline("123", &entity, MarkupFormat::Markdown);
+        assert_eq!(outline.course_id.as_deref(), Some("CS101"));
+        assert_eq!(outline.english_name.as_deref(), Some("Introduction to Computer Science"));
+        assert_eq!(outline.credits, Some(3.0));
+        assert_eq!((outline.hours.total, outline.hours.lab), (Some(64.0), Some(16.0)));
+        assert_eq!(outline.description.as_deref(), Some("介绍**计算机科学**的基本概念。\n\n- 程序设计\n- 算法"));
+        assert_eq!(outline.assessment_items.len(), 3);
+        assert_eq!(outline.textbooks, vec!["《计算机科学导论》，清华大学出版社", "Computer Science: An Overview"]);
+        assert_eq!(outline.prerequisites.as_deref(), Some("无"));
+        assert_eq!(outline.other_fields.get("skdd").map(|s| s.as_str()), Some("一教101"));
+        assert!(!outline.other_fields.contains_key("kcywjj"));
+    }
+}
@@ -0,0 +1,332 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::NaiveTime;
+use rocket::serde::Deserialize;
+
+use super::structures::*;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PlannerConfig {
+    /// Most schedules returned by one request.
+    pub max_schedules: usize,
+    /// Partial combinations tried before the search gives up.
+    pub search_limit: usize,
+    /// Penalty of each free period between two classes of a day.
+    pub gap_weight: f64,
+    /// Penalty of each change of building between two classes of a day.
+    pub building_change_weight: f64,
+    /// Penalty of each day with classes.
+    pub class_day_weight: f64,
+}
+
+impl Default for PlannerConfig {
+    fn default() -> Self {
+        PlannerConfig {
+            max_schedules: 20,
+            search_limit: 200_000,
+            gap_weight: 1.0,
+            building_change_weight: 2.0,
+            class_day_weight: 0.5,
+        }
+    }
+}
+
+/// Hard constraints every class of a schedule has to meet.
+#[derive(Default)]
+pub struct PlanConstraints {
+    /// No class starting before.
+    pub earliest: Option<NaiveTime>,
+    /// No class ending after.
+    pub latest: Option<NaiveTime>,
+    /// Weekdays kept free, 1 for Monday to 7 for Sunday.
+    pub free_weekdays: Vec<u32>,
+    /// Only classes with undergraduate seats left.
+    pub only_open: bool,
+}
+
+fn time_slots(course: &AdvancedCourse) -> impl Iterator<Item = &TimeSlot> {
+    course.sections.iter().flat_map(|section| section.time_slots.iter())
+}
+
+/// Whether two slots meet on the same weekday, periods and weeks. The slots
+/// which could not be parsed never clash, and a slot without weeks is taken
+/// as held every week.
+pub fn slots_clash(a: &TimeSlot, b: &TimeSlot) -> bool {
+    let (a_day, a_start, b_day, b_start) = match (a.weekday, a.start_period, b.weekday, b.start_period) {
+        (Some(a_day), Some(a_start), Some(b_day), Some(b_start)) => (a_day, a_start, b_day, b_start),
+        _ => return false
+    };
+    let a_end = a.end_period.unwrap_or(a_start);
+    let b_end = b.end_period.unwrap_or(b_start);
+    a_day == b_day
+        && a_start <= b_end
+        && b_start <= a_end
+        && (a.weeks.is_empty() || b.weeks.is_empty() || a.weeks.intersects(&b.weeks))
+}
+
+/// The pairs of slots of `a` and `b` which clash.
+pub fn clashing_slots<'a>(a: &'a AdvancedCourse, b: &'a AdvancedCourse) -> Vec<(&'a TimeSlot, &'a TimeSlot)> {
+    time_slots(a).flat_map(|x| time_slots(b).filter(move |y| slots_clash(x, y)).map(move |y| (x, y)))
+                .collect()
+}
+
+fn allowed(
+    course: &AdvancedCourse,
+    constraints: &PlanConstraints,
+    bell_schedule: &[(NaiveTime, NaiveTime)],
+) -> bool {
+    if constraints.only_open && course.undergraduated_selected >= course.undergraduated_available {
+        return false;
+    }
+    let time = |period: Option<u32>| period.and_then(|p| (p as usize).checked_sub(1)).and_then(|i| bell_schedule.get(i));
+    time_slots(course).all(|slot| {
+        let free_day = slot.weekday.is_some_and(|day| constraints.free_weekdays.contains(&day));
+        let too_early = match (constraints.earliest, time(slot.start_period)) {
+            (Some(earliest), Some((start, _))) => *start < earliest,
+            _ => false
+        };
+        let too_late = match (constraints.latest, time(slot.end_period.or(slot.start_period))) {
+            (Some(latest), Some((_, end))) => *end > latest,
+            _ => false
+        };
+        !free_day && !too_early && !too_late
+    })
+}
+
+struct Metrics {
+    gap_periods: u32,
+    building_changes: u32,
+    class_days: u32,
+}
+
+fn metrics(courses: &[&AdvancedCourse]) -> Metrics {
+    let mut days = HashMap::<u32, Vec<(u32, u32, Option<&str>)>>::new();
+    for slot in courses.iter().flat_map(|course| time_slots(course)) {
+        if let (Some(day), Some(start)) = (slot.weekday, slot.start_period) {
+            days.entry(day).or_default().push((start, slot.end_period.unwrap_or(start), slot.building.as_deref()));
+        }
+    }
+    let mut metrics = Metrics { gap_periods: 0, building_changes: 0, class_days: days.len() as u32 };
+    for slots in days.values_mut() {
+        slots.sort_unstable();
+        for pair in slots.windows(2) {
+            let ((_, end, from), (start, _, to)) = (pair[0], pair[1]);
+            metrics.gap_periods += start.saturating_sub(end + 1);
+            if from.is_some() && to.is_some() && from != to {
+                metrics.building_changes += 1;
+            }
+        }
+    }
+    metrics
+}
+
+/// Depth first search over one candidate of each group, keeping the best
+/// schedules found as the chosen index in each group.
+struct Search<'a> {
+    groups: &'a [Vec<(&'a str, &'a AvailableCourse)>],
+    config: &'a PlannerConfig,
+    top: usize,
+    chosen: Vec<usize>,
+    found: Vec<(f64, Vec<usize>)>,
+    visited: usize,
+    complete: bool,
+}
+
+impl<'a> Search<'a> {
+    fn score(&self, courses: &[&AdvancedCourse]) -> (f64, Metrics) {
+        let metrics = metrics(courses);
+        let penalty = self.config.gap_weight * metrics.gap_periods as f64
+                        + self.config.building_change_weight * metrics.building_changes as f64
+                        + self.config.class_day_weight * metrics.class_days as f64;
+        (100.0 / (1.0 + penalty), metrics)
+    }
+
+    fn courses(&self, chosen: &[usize]) -> Vec<&'a AdvancedCourse> {
+        chosen.iter().enumerate().map(|(group, i)| &self.groups[group][*i].1.advanced_course).collect()
+    }
+
+    fn keep_top(&mut self) {
+        self.found.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.partial_cmp(a_score).unwrap_or(Ordering::Equal).then_with(|| a.cmp(b))
+        });
+        self.found.truncate(self.top);
+    }
+
+    fn run(&mut self) {
+        if self.visited >= self.config.search_limit {
+            self.complete = false;
+            return;
+        }
+        self.visited += 1;
+
+        let group = self.chosen.len();
+        if group == self.groups.len() {
+            let (score, _) = self.score(&self.courses(&self.chosen));
+            self.found.push((score, self.chosen.clone()));
+            if self.found.len() >= self.top * 2 {
+                self.keep_top();
+            }
+            return;
+        }
+        for i in 0..self.groups[group].len() {
+            let candidate = &self.groups[group][i].1.advanced_course;
+            let clashes = self.courses(&self.chosen)
+                            .iter()
+                            .any(|course| !clashing_slots(course, candidate).is_empty());
+            if !clashes {
+                self.chosen.push(i);
+                self.run();
+                self.chosen.pop();
+            }
+        }
+    }
+}
+
+/// Search the combinations of one class per wanted course without clashes,
+/// under the hard constraints, and rank them by their free periods between
+/// classes, their changes of building and their days with classes.
+///
+/// `courses` are the available classes with the `courses_type` tag they
+/// were found under. The courses without any class meeting the constraints
+/// are left out of the schedules and listed in `unplaced_courses`.
+pub fn plan_timetable(
+    courses: &[(&str, AvailableCourse)],
+    course_ids: &[&str],
+    constraints: &PlanConstraints,
+    bell_schedule: &[(NaiveTime, NaiveTime)],
+    top: usize,
+    config: &PlannerConfig,
+) -> TimetablePlan {
+    let mut groups = Vec::<Vec<(&str, &AvailableCourse)>>::new();
+    let mut unplaced_courses = Vec::<String>::new();
+    for course_id in course_ids {
+        let candidates: Vec<(&str, &AvailableCourse)> = courses.iter()
+                                                        .filter(|(_, c)| c.advanced_course.basic_course.course_id == *course_id)
+                                                        .filter(|(_, c)| allowed(&c.advanced_course, constraints, bell_schedule))
+                                                        .map(|(courses_type, c)| (*courses_type, c))
+                                                        .collect();
+        if candidates.is_empty() {
+            unplaced_courses.push(course_id.to_string());
+        } else {
+            groups.push(candidates);
+        }
+    }
+    // The courses with the fewest classes first prune the search sooner.
+    groups.sort_by_key(|candidates| candidates.len());
+
+    let mut search = Search {
+        groups: &groups,
+        config,
+        top: top.clamp(1, config.max_schedules.max(1)),
+        chosen: Vec::new(),
+        found: Vec::new(),
+        visited: 0,
+        complete: true,
+    };
+    if !groups.is_empty() {
+        search.run();
+    }
+    search.keep_top();
+
+    let schedules = search.found
+                        .iter()
+                        .map(|(score, chosen)| {
+                            let (_, metrics) = search.score(&search.courses(chosen));
+                            let classes = chosen.iter()
+                                            .enumerate()
+                                            .map(|(group, i)| {
+                                                let (courses_type, course) = groups[group][*i];
+                                                let course = &course.advanced_course;
+                                                PlannedClass {
+                                                    course_id: course.basic_course.course_id.clone(),
+                                                    course_name: course.basic_course.course_name.clone(),
+                                                    class_id: course.id.clone(),
+                                                    course_class: course.course_class.clone(),
+                                                    courses_type: courses_type.to_owned(),
+                                                    time_slots: time_slots(course).cloned().collect(),
+                                                }
+                                            })
+                                            .collect();
+                            PlannedSchedule {
+                                score: *score,
+                                gap_periods: metrics.gap_periods,
+                                building_changes: metrics.building_changes,
+                                class_days: metrics.class_days,
+                                classes,
+                            }
+                        })
+                        .collect();
+    TimetablePlan {
+        schedules,
+        unplaced_courses,
+        searched: search.visited,
+        complete: search.complete,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::super::ical::TimetableConfig;
+    use super::super::parser::parse_time_slot;
+    use super::super::structures::*;
+    use super::super::test_util::advanced_course;
+    use super::{plan_timetable, slots_clash, PlanConstraints, PlannerConfig};
+
+    fn class(course_id: &str, id: &str, slots: &[&str]) -> (&'static str, AvailableCourse) {
+        ("TP", AvailableCourse {
+            advanced_course: advanced_course(course_id, course_id, id, id, slots),
+            outline_id: String::new(),
+            conflict_courses: None,
+            conflicts: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_slots_clash() {
+        let a = parse_time_slot("1-16周 星期一 第3-4节 一教101");
+        assert!(slots_clash(&a, &parse_time_slot("1-8周 星期一 第4-5节 一教102")));
+        assert!(!slots_clash(&a, &parse_time_slot("1-16周 星期二 第3-4节 一教101")));
+        assert!(!slots_clash(&a, &parse_time_slot("1-16周 星期一 第5-6节 一教101")));
+        assert!(!slots_clash(&parse_time_slot("1-16周(单) 星期一 第3-4节 一教101"), &parse_time_slot("1-16周(双) 星期一 第3-4节 一教101")));
+        assert!(!slots_clash(&a, &parse_time_slot("待定")));
+    }
+
+    #[test]
+    fn test_plan_timetable() {
+        let bell_schedule = TimetableConfig::default().bell_schedule().unwrap();
+        let courses = vec![
+            class("MA101", "ma-1", &["1-16周 星期一 第1-2节 一教101"]),
+            class("MA101", "ma-2", &["1-16周 星期一 第7-8节 一教101"]),
+            class("CS102", "cs-1", &["1-16周 星期一 第3-4节 一教201"]),
+            class("CS102", "cs-2", &["1-16周 星期五 第3-4节 一教201"]),
+            class("PH103", "ph-1", &["1-16周 星期一 第3-4节 二教101"]),
+            class("EN104", "en-1", &["1-16周 星期五 第5-6节 三教101"]),
+        ];
+        let config = PlannerConfig::default();
+        let plan = plan_timetable(&courses, &["MA101", "CS102", "PH103"], &PlanConstraints::default(), &bell_schedule, 10, &config);
+        assert!(plan.complete);
+        assert!(plan.unplaced_courses.is_empty());
+        let ids: Vec<Vec<&str>> = plan.schedules.iter()
+                                    .map(|s| s.classes.iter().map(|c| c.class_id.as_str()).collect())
+                                    .collect();
+        // The best schedule has no free period between the classes of Monday.
+        assert_eq!(ids, vec![vec!["ph-1", "ma-1", "cs-2"], vec!["ph-1", "ma-2", "cs-2"]]);
+        assert_eq!((plan.schedules[0].gap_periods, plan.schedules[0].class_days), (0, 2));
+
+        let constraints = PlanConstraints {
+            earliest: NaiveTime::from_hms_opt(10, 0, 0),
+            free_weekdays: vec![5],
+            ..PlanConstraints::default()
+        };
+        let plan = plan_timetable(&courses, &["MA101", "CS102", "EN104"], &constraints, &bell_schedule, 10, &config);
+        assert_eq!(plan.unplaced_courses, vec!["EN104"]);
+        let ids: Vec<Vec<&str>> = plan.schedules.iter()
+                                    .map(|s| s.classes.iter().map(|c| c.class_id.as_str()).collect())
+                                    .collect();
+        assert_eq!(ids, vec![vec!["ma-2", "cs-1"]]);
+    }
+}
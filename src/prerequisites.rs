@@ -0,0 +1,408 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use super::structures::*;
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Code(String),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+/// Whether a word is a course code such as `MA101B` or `PHY105`.
+fn is_course_code(word: &str) -> bool {
+    let letters = word.chars().take_while(|c| c.is_ascii_alphabetic()).count();
+    let digits = word[letters..].chars().take_while(|c| c.is_ascii_digit()).count();
+    let rest = word.len() - letters - digits;
+    (2..=4).contains(&letters) && digits == 3 && rest <= 1
+        && word[letters + digits..].chars().all(|c| c.is_ascii_alphabetic())
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() {
+            let mut word = c.to_string();
+            while let Some(next) = chars.peek().filter(|next| next.is_ascii_alphanumeric()) {
+                word.push(*next);
+                chars.next();
+            }
+            if is_course_code(&word) {
+                tokens.push(Token::Code(word.to_uppercase()));
+            } else if word.eq_ignore_ascii_case("and") {
+                tokens.push(Token::And);
+            } else if word.eq_ignore_ascii_case("or") {
+                tokens.push(Token::Or);
+            }
+            continue;
+        }
+        let token = match c {
+            '或' | '/' | '|' => Token::Or,
+            '和' | '与' | '及' | '且' | '并' | ',' | '，' | ';' | '；' | '、' | '&' | '+' => Token::And,
+            '(' | '（' | '[' | '【' => Token::Open,
+            ')' | '）' | ']' | '】' => Token::Close,
+            _ => continue
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Merge requirements into one, flattening the nested ones of the same kind.
+fn combine(items: Vec<Requirement>, any: bool) -> Option<Requirement> {
+    let mut merged = Vec::<Requirement>::new();
+    for item in items {
+        let inner = match item {
+            Requirement::Any(inner) if any => inner,
+            Requirement::All(inner) if !any => inner,
+            item => vec![item]
+        };
+        for item in inner {
+            if !merged.contains(&item) {
+                merged.push(item);
+            }
+        }
+    }
+    match merged.len() {
+        0 => None,
+        1 => merged.pop(),
+        _ if any => Some(Requirement::Any(merged)),
+        _ => Some(Requirement::All(merged))
+    }
+}
+
+fn parse_all(tokens: &[Token], pos: &mut usize) -> Option<Requirement> {
+    let mut items = Vec::new();
+    loop {
+        match tokens.get(*pos) {
+            None | Some(Token::Close) => break,
+            Some(Token::And) | Some(Token::Or) => *pos += 1,
+            _ => items.extend(parse_any(tokens, pos))
+        }
+    }
+    combine(items, false)
+}
+
+fn parse_any(tokens: &[Token], pos: &mut usize) -> Option<Requirement> {
+    let mut items = Vec::new();
+    loop {
+        items.extend(parse_atom(tokens, pos));
+        if tokens.get(*pos) != Some(&Token::Or) {
+            break;
+        }
+        *pos += 1;
+    }
+    combine(items, true)
+}
+
+fn parse_atom(tokens: &[Token], pos: &mut usize) -> Option<Requirement> {
+    match tokens.get(*pos) {
+        Some(Token::Code(code)) => {
+            *pos += 1;
+            Some(Requirement::Course(code.clone()))
+        },
+        Some(Token::Open) => {
+            *pos += 1;
+            let requirement = parse_all(tokens, pos);
+            if tokens.get(*pos) == Some(&Token::Close) {
+                *pos += 1;
+            }
+            requirement
+        },
+        _ => None
+    }
+}
+
+/// Read the course codes of a prerequisite text such as
+/// `MA101B或MA101A，CS102A` into a requirement. `或`, `or` and `/` bind
+/// tighter than `和`, `and`, commas and semicolons, and brackets group.
+/// Anything but course codes, such as course names, is left out, so `None`
+/// means no course is required as far as the text tells.
+pub fn parse_prerequisites(text: &str) -> Option<Requirement> {
+    let tokens = tokenize(text);
+    let mut pos = 0;
+    let mut items = Vec::new();
+    while pos < tokens.len() {
+        items.extend(parse_all(&tokens, &mut pos));
+        // A closing bracket without its opening one.
+        pos += 1;
+    }
+    combine(items, false)
+}
+
+/// The course codes of a requirement, in order.
+pub fn requirement_courses(requirement: &Requirement) -> Vec<String> {
+    match requirement {
+        Requirement::Course(code) => vec![code.clone()],
+        Requirement::All(items) | Requirement::Any(items) => {
+            let mut codes = Vec::<String>::new();
+            for code in items.iter().flat_map(requirement_courses) {
+                if !codes.contains(&code) {
+                    codes.push(code);
+                }
+            }
+            codes
+        }
+    }
+}
+
+pub fn is_satisfied(requirement: &Requirement, passed: &HashSet<String>) -> bool {
+    match requirement {
+        Requirement::Course(code) => passed.contains(code),
+        Requirement::All(items) => items.iter().all(|item| is_satisfied(item, passed)),
+        Requirement::Any(items) => items.iter().any(|item| is_satisfied(item, passed)),
+    }
+}
+
+/// A requirement as it reads, such as `MA101B and (CS102A or CS109)`.
+pub fn describe(requirement: &Requirement) -> String {
+    let describe_items = |items: &[Requirement], separator: &str| {
+        items.iter()
+            .map(|item| match item {
+                Requirement::Course(code) => code.clone(),
+                item => format!("({})", describe(item))
+            })
+            .collect::<Vec<String>>()
+            .join(separator)
+    };
+    match requirement {
+        Requirement::Course(code) => code.clone(),
+        Requirement::All(items) => describe_items(items, " and "),
+        Requirement::Any(items) => describe_items(items, " or "),
+    }
+}
+
+/// Why a requirement is not satisfied, one sentence for each missing part.
+fn explain(requirement: &Requirement, passed: &HashSet<String>, reasons: &mut Vec<String>) {
+    if is_satisfied(requirement, passed) {
+        return;
+    }
+    match requirement {
+        Requirement::Course(code) => reasons.push(format!("{} has not been passed.", code)),
+        Requirement::All(items) => items.iter().for_each(|item| explain(item, passed, reasons)),
+        Requirement::Any(_) => reasons.push(format!("One of {} is needed, none has been passed.", describe(requirement))),
+    }
+}
+
+/// Whether a grade passes, from 60 or with a letter from A to D, `P` or its
+/// Chinese equivalents.
+pub fn is_passed(grade: &CourseGrade) -> bool {
+    const PASSING_LEVELS: [&str; 7] = ["P", "通过", "合格", "优秀", "良好", "中等", "及格"];
+    let level = grade.final_level.trim();
+    match grade.final_grade.trim().parse::<f64>() {
+        Ok(score) => score >= 60.0,
+        Err(_) => PASSING_LEVELS.contains(&level)
+                    || (level.len() <= 2 && level.starts_with(|c| ('A'..='D').contains(&c))
+                        && level[1..].chars().all(|c| c == '+' || c == '-'))
+    }
+}
+
+/// The codes of the courses which have been passed.
+pub fn passed_courses(grades: &[CourseGrade]) -> HashSet<String> {
+    grades.iter()
+        .filter(|grade| is_passed(grade))
+        .map(|grade| grade.code.trim().to_uppercase())
+        .collect()
+}
+
+struct Node {
+    course_name: String,
+    text: Option<String>,
+    requirement: Option<Requirement>,
+    /// Whether the detail pages of the course have been read, without which
+    /// its prerequisites are not known.
+    known: bool,
+}
+
+/// The prerequisites of the courses of the catalogue, linking each course
+/// to those it needs and to those it unlocks.
+pub struct PrerequisiteGraph {
+    nodes: BTreeMap<String, Node>,
+    /// The courses needing each course, directly.
+    dependents: BTreeMap<String, Vec<String>>,
+}
+
+impl PrerequisiteGraph {
+    pub fn new(courses: &[CatalogCourse]) -> Self {
+        let mut nodes = BTreeMap::new();
+        let mut dependents = BTreeMap::<String, Vec<String>>::new();
+        for course in courses {
+            let course_id = course.course_id.to_uppercase();
+            let text = course.prerequisites.clone().or_else(|| course.english_prerequisites.clone());
+            let requirement = text.as_deref().and_then(parse_prerequisites);
+            for code in requirement.iter().flat_map(requirement_courses) {
+                dependents.entry(code).or_default().push(course_id.clone());
+            }
+            nodes.insert(course_id, Node {
+                course_name: course.course_name.clone(),
+                text,
+                requirement,
+                known: course.details_fetched_at.is_some(),
+            });
+        }
+        PrerequisiteGraph { nodes, dependents }
+    }
+
+    /// The courses reached from `course_id` in breadth first order, without
+    /// it, going to the neighbours `next` gives.
+    fn reach(&self, course_id: &str, next: impl Fn(&str) -> Vec<String>) -> Vec<String> {
+        let mut seen = HashSet::new();
+        seen.insert(course_id.to_owned());
+        let mut queue: VecDeque<String> = next(course_id).into();
+        let mut reached = Vec::new();
+        while let Some(code) = queue.pop_front() {
+            if !seen.insert(code.clone()) {
+                continue;
+            }
+            queue.extend(next(&code));
+            reached.push(code);
+        }
+        reached
+    }
+
+    pub fn requirement(&self, course_id: &str) -> Option<&Requirement> {
+        self.nodes.get(course_id)?.requirement.as_ref()
+    }
+
+    pub fn prerequisites(&self, course_id: &str) -> Vec<String> {
+        self.nodes
+            .get(course_id)
+            .and_then(|node| node.requirement.as_ref())
+            .map(requirement_courses)
+            .unwrap_or_default()
+    }
+
+    pub fn all_prerequisites(&self, course_id: &str) -> Vec<String> {
+        self.reach(course_id, |code| self.prerequisites(code))
+    }
+
+    /// The courses which need `course_id` as one of their prerequisites.
+    pub fn unlocks(&self, course_id: &str) -> Vec<String> {
+        self.dependents.get(course_id).cloned().unwrap_or_default()
+    }
+
+    pub fn all_unlocks(&self, course_id: &str) -> Vec<String> {
+        self.reach(course_id, |code| self.unlocks(code))
+    }
+
+    pub fn info(&self, course_id: &str) -> Option<PrerequisiteInfo> {
+        let course_id = course_id.trim().to_uppercase();
+        let node = self.nodes.get(&course_id)?;
+        let all_prerequisites = self.all_prerequisites(&course_id);
+        let unknown = std::iter::once(&course_id)
+                        .chain(all_prerequisites.iter())
+                        .filter(|code| self.nodes.get(*code).is_some_and(|node| !node.known))
+                        .cloned()
+                        .collect();
+        Some(PrerequisiteInfo {
+            course_name: node.course_name.clone(),
+            text: node.text.clone(),
+            requirement: node.requirement.clone(),
+            prerequisites: self.prerequisites(&course_id),
+            all_prerequisites,
+            unlocks: self.unlocks(&course_id),
+            all_unlocks: self.all_unlocks(&course_id),
+            unknown,
+            course_id,
+        })
+    }
+
+    /// Whether the passed courses satisfy the prerequisites of `course_id`,
+    /// with the reasons when they do not.
+    pub fn check(&self, course_id: &str, passed: &HashSet<String>) -> Eligibility {
+        let course_id = course_id.trim().to_uppercase();
+        let node = match self.nodes.get(&course_id) {
+            Some(node) => node,
+            None => return Eligibility {
+                explanation: format!("{} is not in the catalogue, so its prerequisites are not known.", course_id),
+                course_id,
+                course_name: None,
+                eligible: true,
+                requirement: None,
+                missing: Vec::new(),
+            }
+        };
+        let (eligible, missing, explanation) = match (&node.requirement, &node.text) {
+            (Some(requirement), _) => {
+                let mut reasons = Vec::new();
+                explain(requirement, passed, &mut reasons);
+                let missing: Vec<String> = requirement_courses(requirement)
+                                            .into_iter()
+                                            .filter(|code| !passed.contains(code))
+                                            .collect();
+                let needs = format!("{} needs {}", course_id, describe(requirement));
+                if reasons.is_empty() {
+                    (true, Vec::new(), format!("{}, which has been satisfied.", needs))
+                } else {
+                    (false, missing, format!("{}. {}", needs, reasons.join(" ")))
+                }
+            },
+            (None, Some(text)) => (true, Vec::new(), format!("The prerequisites of {} name no course: {}", course_id, text)),
+            (None, None) if node.known => (true, Vec::new(), format!("{} has no prerequisites.", course_id)),
+            (None, None) => (true, Vec::new(), format!("The prerequisites of {} are not known yet.", course_id)),
+        };
+        Eligibility {
+            course_id,
+            course_name: Some(node.course_name.clone()),
+            eligible,
+            requirement: node.requirement.clone(),
+            missing,
+            explanation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::super::structures::Requirement;
+    use super::super::test_util::catalog_course;
+    use super::{parse_prerequisites, PrerequisiteGraph};
+
+    #[test]
+    fn test_parse_prerequisites() {
+        let c = |code: &str| Requirement::Course(code.to_owned());
+        assert_eq!(parse_prerequisites("无"), None);
+        assert_eq!(parse_prerequisites("MA101B"), Some(c("MA101B")));
+        assert_eq!(
+            parse_prerequisites("高等数学(上)A MA101B或MA101A，CS102A"),
+            Some(Requirement::All(vec![Requirement::Any(vec![c("MA101B"), c("MA101A")]), c("CS102A")]))
+        );
+        assert_eq!(
+            parse_prerequisites("cs203 and (MA103A or MA107A/MA107B)"),
+            Some(Requirement::All(vec![c("CS203"), Requirement::Any(vec![c("MA103A"), c("MA107A"), c("MA107B")])]))
+        );
+        assert_eq!(parse_prerequisites("MA101B) 和 MA101B"), Some(c("MA101B")));
+    }
+
+    #[test]
+    fn test_graph() {
+        let graph = PrerequisiteGraph::new(&[
+            catalog_course("CS102A", None),
+            catalog_course("MA101B", Some("无")),
+            catalog_course("CS203", Some("CS102A")),
+            catalog_course("CS303", Some("CS203和(MA101B或MA101A)")),
+            catalog_course("CS401", None),
+        ]);
+        assert_eq!(graph.prerequisites("CS303"), vec!["CS203", "MA101B", "MA101A"]);
+        assert_eq!(graph.all_prerequisites("CS303"), vec!["CS203", "MA101B", "MA101A", "CS102A"]);
+        assert_eq!(graph.unlocks("CS102A"), vec!["CS203"]);
+        assert_eq!(graph.all_unlocks("CS102A"), vec!["CS203", "CS303"]);
+
+        let passed: HashSet<String> = vec!["CS102A".to_owned(), "CS203".to_owned()].into_iter().collect();
+        let eligibility = graph.check("cs303", &passed);
+        assert!(!eligibility.eligible);
+        assert_eq!(eligibility.missing, vec!["MA101B", "MA101A"]);
+        assert_eq!(
+            eligibility.explanation,
+            "CS303 needs CS203 and (MA101B or MA101A). One of MA101B or MA101A is needed, none has been passed."
+        );
+        assert!(graph.check("CS203", &passed).eligible);
+        assert_eq!(graph.check("MA101B", &passed).explanation, "The prerequisites of MA101B name no course: 无");
+        assert_eq!(graph.check("CS401", &passed).explanation, "CS401 has no prerequisites.");
+    }
+}
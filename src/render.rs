@@ -0,0 +1,291 @@
+use chrono::NaiveTime;
+use rocket::serde::Deserialize;
+
+use super::agenda::weekday_classes;
+use super::structures::*;
+
+const PADDING: f64 = 16.0;
+const TITLE_HEIGHT: f64 = 40.0;
+const HEADER_HEIGHT: f64 = 32.0;
+const PERIOD_WIDTH: f64 = 72.0;
+const DAY_WIDTH: f64 = 132.0;
+const PERIOD_HEIGHT: f64 = 52.0;
+const FONT_FAMILY: &str = "PingFang SC, Noto Sans CJK SC, Source Han Sans SC, Microsoft YaHei, sans-serif";
+const WEEKDAYS: [&str; 7] = ["Mon 周一", "Tue 周二", "Wed 周三", "Thu 周四", "Fri 周五", "Sat 周六", "Sun 周日"];
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RenderConfig {
+    /// Font files loaded for PNG output on top of the system fonts, which
+    /// should cover Chinese.
+    pub font_files: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+struct Palette {
+    background: &'static str,
+    grid: &'static str,
+    header: &'static str,
+    muted: &'static str,
+    text: &'static str,
+    blocks: [&'static str; 10],
+}
+
+impl Theme {
+    pub fn parse(theme: &str) -> Option<Theme> {
+        match theme {
+            "light" => Some(Theme::Light),
+            "dark" => Some(Theme::Dark),
+            _ => None
+        }
+    }
+
+    fn palette(&self) -> Palette {
+        match self {
+            Theme::Light => Palette {
+                background: "#ffffff",
+                grid: "#e5e7eb",
+                header: "#374151",
+                muted: "#6b7280",
+                text: "#111827",
+                blocks: ["#dbeafe", "#dcfce7", "#fef3c7", "#fce7f3", "#ede9fe", "#cffafe", "#fee2e2", "#e0e7ff", "#ecfccb", "#ffedd5"],
+            },
+            Theme::Dark => Palette {
+                background: "#111827",
+                grid: "#374151",
+                header: "#e5e7eb",
+                muted: "#9ca3af",
+                text: "#f9fafb",
+                blocks: ["#1e3a8a", "#14532d", "#78350f", "#831843", "#4c1d95", "#164e63", "#7f1d1d", "#312e81", "#365314", "#7c2d12"],
+            },
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Rough width of a character, Chinese ones being a full em wide.
+fn char_width(c: char, font_size: f64) -> f64 {
+    if c.is_ascii() { font_size * 0.6 } else { font_size }
+}
+
+/// Break `text` into at most `max_lines` lines of `width`, with an ellipsis
+/// when it does not fit.
+fn wrap(text: &str, width: f64, font_size: f64, max_lines: usize) -> Vec<String> {
+    let mut lines = Vec::<String>::new();
+    let mut line = String::new();
+    let mut line_width = 0.0;
+    for c in text.chars() {
+        let w = char_width(c, font_size);
+        if line_width + w > width && !line.is_empty() {
+            if lines.len() + 1 == max_lines {
+                line.pop();
+                line.push('…');
+                lines.push(line);
+                return lines;
+            }
+            lines.push(std::mem::take(&mut line));
+            line_width = 0.0;
+        }
+        line.push(c);
+        line_width += w;
+    }
+    if !line.is_empty() && lines.len() < max_lines {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Render the weekly grid of the course table as SVG, with a block per
+/// class coloured by course. Only the classes of `week` are shown if given.
+/// The weekend columns are only drawn when they have classes.
+pub fn render_timetable(
+    title: &str,
+    table: &[CourseTableItem],
+    week: Option<u32>,
+    bell_schedule: &[(NaiveTime, NaiveTime)],
+    theme: Theme,
+) -> String {
+    let palette = theme.palette();
+    let days: Vec<Vec<ScheduledClass>> = (1..=7).map(|day| weekday_classes(table, day, week, bell_schedule)).collect();
+    let day_count = if days[5].is_empty() && days[6].is_empty() { 5 } else { 7 };
+    let periods = days.iter()
+                    .flatten()
+                    .map(|class| class.end_period as usize)
+                    .chain(std::iter::once(bell_schedule.len()))
+                    .max()
+                    .unwrap_or_default();
+
+    let mut course_names: Vec<&str> = days.iter().flatten().map(|class| class.course_name.as_str()).collect();
+    course_names.sort_unstable();
+    course_names.dedup();
+    let colour = |name: &str| {
+        let index = course_names.iter().position(|n| *n == name).unwrap_or_default();
+        palette.blocks[index % palette.blocks.len()]
+    };
+
+    let grid_x = PADDING + PERIOD_WIDTH;
+    let grid_y = PADDING + TITLE_HEIGHT + HEADER_HEIGHT;
+    let width = grid_x + DAY_WIDTH * day_count as f64 + PADDING;
+    let height = grid_y + PERIOD_HEIGHT * periods as f64 + PADDING;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"{f}\">\n",
+        w = width, h = height, f = FONT_FAMILY
+    );
+    svg.push_str(&format!("<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n", width, height, palette.background));
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" font-size=\"20\" font-weight=\"bold\" fill=\"{}\">{}</text>\n",
+        PADDING, PADDING + 24.0, palette.header, escape(title)
+    ));
+
+    for (day, name) in WEEKDAYS.iter().take(day_count).enumerate() {
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" font-size=\"13\" text-anchor=\"middle\" fill=\"{}\">{}</text>\n",
+            grid_x + DAY_WIDTH * (day as f64 + 0.5), grid_y - 11.0, palette.header, name
+        ));
+    }
+    for period in 0..periods {
+        let y = grid_y + PERIOD_HEIGHT * period as f64;
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" font-size=\"13\" text-anchor=\"middle\" fill=\"{}\">{}</text>\n",
+            PADDING + PERIOD_WIDTH / 2.0, y + 22.0, palette.header, period + 1
+        ));
+        if let Some((start, end)) = bell_schedule.get(period) {
+            svg.push_str(&format!(
+                "<text x=\"{}\" y=\"{}\" font-size=\"10\" text-anchor=\"middle\" fill=\"{}\">{}-{}</text>\n",
+                PADDING + PERIOD_WIDTH / 2.0, y + 38.0, palette.muted, start.format("%H:%M"), end.format("%H:%M")
+            ));
+        }
+    }
+    for period in 0..=periods {
+        let y = grid_y + PERIOD_HEIGHT * period as f64;
+        svg.push_str(&format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"/>\n",
+            PADDING, y, width - PADDING, y, palette.grid
+        ));
+    }
+    for day in 0..=day_count {
+        let x = grid_x + DAY_WIDTH * day as f64;
+        svg.push_str(&format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"/>\n",
+            x, grid_y - HEADER_HEIGHT, x, height - PADDING, palette.grid
+        ));
+    }
+
+    for (day, classes) in days.iter().take(day_count).enumerate() {
+        // Classes overlapping in time share the column in lanes.
+        let mut lanes = Vec::<u32>::new();
+        let mut lane_of = Vec::<usize>::new();
+        for class in classes {
+            let lane = match lanes.iter().position(|end| *end < class.start_period) {
+                Some(lane) => lane,
+                None => {
+                    lanes.push(0);
+                    lanes.len() - 1
+                }
+            };
+            lanes[lane] = class.end_period;
+            lane_of.push(lane);
+        }
+        let lane_width = DAY_WIDTH / lanes.len().max(1) as f64;
+
+        for (class, lane) in classes.iter().zip(lane_of) {
+            let x = grid_x + DAY_WIDTH * day as f64 + lane_width * lane as f64 + 2.0;
+            let y = grid_y + PERIOD_HEIGHT * (class.start_period.max(1) - 1) as f64 + 2.0;
+            let w = lane_width - 4.0;
+            let h = PERIOD_HEIGHT * (class.end_period + 1 - class.start_period.max(1)) as f64 - 4.0;
+            svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"6\" fill=\"{}\"/>\n",
+                x, y, w, h, colour(&class.course_name)
+            ));
+
+            let location = format!("{}{}", class.building.clone().unwrap_or_default(), class.room.clone().unwrap_or_default());
+            let max_lines = ((h - 8.0) / 15.0).floor().max(1.0) as usize;
+            let mut lines: Vec<(String, bool)> = wrap(&class.course_name, w - 10.0, 12.0, 2.min(max_lines))
+                                                    .into_iter()
+                                                    .map(|line| (line, true))
+                                                    .collect();
+            for detail in [location, class.teachers.join(", ")] {
+                if !detail.is_empty() && lines.len() < max_lines {
+                    lines.extend(wrap(&detail, w - 10.0, 11.0, 1).into_iter().map(|line| (line, false)));
+                }
+            }
+            for (i, (line, bold)) in lines.iter().enumerate() {
+                svg.push_str(&format!(
+                    "<text x=\"{}\" y=\"{}\" font-size=\"{}\"{} fill=\"{}\">{}</text>\n",
+                    x + 5.0,
+                    y + 16.0 + 15.0 * i as f64,
+                    if *bold { 12 } else { 11 },
+                    if *bold { " font-weight=\"bold\"" } else { "" },
+                    palette.text,
+                    escape(line)
+                ));
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Rasterise an SVG into PNG.
+#[cfg(feature = "png")]
+pub fn rasterize(svg: &str, config: &RenderConfig) -> Result<Vec<u8>, String> {
+    use resvg::{tiny_skia, usvg};
+
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    for file in &config.font_files {
+        options.fontdb_mut().load_font_file(file).map_err(|e| format!("Unable to load the font {}: {}", file, e))?;
+    }
+    let tree = usvg::Tree::from_str(svg, &options).map_err(|e| e.to_string())?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+                        .ok_or_else(|| "The timetable is too large to render".to_owned())?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.encode_png().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ical::TimetableConfig;
+    use super::super::test_util::table_item;
+    use super::{render_timetable, wrap, Theme};
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("高等数学", 100.0, 12.0, 2), vec!["高等数学"]);
+        assert_eq!(wrap("计算机程序设计基础A", 50.0, 12.0, 2), vec!["计算机程", "序设计…"]);
+    }
+
+    #[test]
+    fn test_render_timetable() {
+        let bell_schedule = TimetableConfig::default().bell_schedule().unwrap();
+        let table = vec![
+            table_item(1, 1, "高等数学(上)[01班]\n张三\n1-16周\n一教101"),
+            table_item(1, 2, "高等数学(上)[01班]\n张三\n1-16周\n一教101"),
+            table_item(2, 3, "R&amp;D &lt;Lab&gt;[3班]\n2-16周(双)\n实验楼305"),
+        ];
+        let svg = render_timetable("Timetable", &table, Some(2), &bell_schedule, Theme::Light);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(">高等数学(上)</text>"));
+        assert!(svg.contains("R&amp;D &lt;Lab&gt;"));
+        assert!(svg.contains("height=\"100\" rx=\"6\""));
+        assert!(!svg.contains("Sat"));
+
+        let svg = render_timetable("Timetable", &table, Some(1), &bell_schedule, Theme::Dark);
+        assert!(svg.contains("fill=\"#111827\""));
+        assert!(!svg.contains("Lab"));
+    }
+}
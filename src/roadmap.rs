@@ -0,0 +1,376 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use rocket::serde::Deserialize;
+
+use super::prerequisites::{describe, is_satisfied, requirement_courses, PrerequisiteGraph};
+use super::structures::*;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RoadmapConfig {
+    /// Credits of a semester unless told otherwise.
+    pub max_credits: f32,
+    /// Semesters planned at most, summer terms included.
+    pub max_semesters: usize,
+}
+
+impl Default for RoadmapConfig {
+    fn default() -> Self {
+        RoadmapConfig {
+            max_credits: 25.0,
+            max_semesters: 12,
+        }
+    }
+}
+
+/// A semester of the plan, before courses are put in it.
+pub struct Term {
+    pub semester_year: String,
+    pub semester_no: String,
+    pub max_credits: f32,
+    /// A semester without courses at SUSTech, such as one abroad.
+    pub skipped: bool,
+}
+
+/// A course left to take.
+pub struct RemainingCourse {
+    pub course_id: String,
+    pub course_name: Option<String>,
+    pub credits: f32,
+    /// Index of the term the student wants it in.
+    pub pinned: Option<usize>,
+}
+
+/// Read a semester such as `2023-2024-1` into its first year and number.
+pub fn parse_term(term: &str) -> Option<(i32, u32)> {
+    let mut parts = term.trim().split('-');
+    let year = parts.next()?.parse::<i32>().ok()?;
+    let next_year = parts.next()?.parse::<i32>().ok()?;
+    let no = parts.next()?.parse::<u32>().ok()?;
+    if next_year != year + 1 || !(1..=3).contains(&no) || parts.next().is_some() {
+        return None;
+    }
+    Some((year, no))
+}
+
+/// The semester after one, through the summer term when `summer` is set.
+pub fn next_term((year, no): (i32, u32), summer: bool) -> (i32, u32) {
+    match no {
+        1 => (year, 2),
+        2 if summer => (year, 3),
+        _ => (year + 1, 1)
+    }
+}
+
+/// `count` semesters from `first`, as `semester_year` and `semester_no`.
+pub fn terms_from(first: (i32, u32), count: usize, summer: bool) -> Vec<(String, String)> {
+    let mut term = first;
+    let mut terms = Vec::new();
+    while terms.len() < count {
+        terms.push((format!("{}-{}", term.0, term.0 + 1), term.1.to_string()));
+        term = next_term(term, summer);
+    }
+    terms
+}
+
+/// The option of a required course of a training plan, such as
+/// `MA101A/MA101B`, which has been offered, or else the first one.
+pub fn choose_alternative(entry: &str, offerings: &HashMap<String, BTreeSet<String>>) -> String {
+    let options: Vec<&str> = entry.split('/').map(|code| code.trim()).filter(|code| !code.is_empty()).collect();
+    options.iter()
+        .find(|code| offerings.contains_key(**code))
+        .or_else(|| options.first())
+        .map(|code| code.to_string())
+        .unwrap_or_default()
+}
+
+/// Whether a course may be in a semester with this number. A course never
+/// seen is taken to be offered in the autumn and spring semesters.
+fn offered(course_id: &str, semester_no: &str, offerings: &HashMap<String, BTreeSet<String>>) -> bool {
+    match offerings.get(course_id) {
+        Some(seen) => seen.contains(semester_no),
+        None => semester_no != "3"
+    }
+}
+
+/// How many semesters the courses unlocked by a course need after it, in
+/// the longest chain of courses left to take.
+fn chain_length(
+    course_id: &str,
+    graph: &PrerequisiteGraph,
+    remaining: &HashSet<&str>,
+    lengths: &mut HashMap<String, usize>,
+    visiting: &mut HashSet<String>,
+) -> usize {
+    if let Some(length) = lengths.get(course_id) {
+        return *length;
+    }
+    if !visiting.insert(course_id.to_owned()) {
+        // A cycle of prerequisites, which no order satisfies anyway.
+        return 0;
+    }
+    let length = graph.unlocks(course_id)
+                    .iter()
+                    .filter(|code| remaining.contains(code.as_str()))
+                    .map(|code| 1 + chain_length(code, graph, remaining, lengths, visiting))
+                    .max()
+                    .unwrap_or(0);
+    visiting.remove(course_id);
+    lengths.insert(course_id.to_owned(), length);
+    length
+}
+
+/// Spread the courses left to take over the semesters to come. Each one
+/// goes, as early as its prerequisites allow, in the first semester where
+/// it is usually offered and which has room for it, starting with the
+/// courses at the head of the longest chains of prerequisites. Pinned
+/// courses stay where they are. The elective credits then fill the room
+/// left, in the order given.
+pub fn plan_semesters(
+    courses: &[RemainingCourse],
+    electives: &[(String, f32)],
+    passed: &HashSet<String>,
+    terms: &[Term],
+    graph: &PrerequisiteGraph,
+    offerings: &HashMap<String, BTreeSet<String>>,
+) -> StudyPlan {
+    let remaining: HashSet<&str> = courses.iter().map(|course| course.course_id.as_str()).collect();
+    let mut warnings = Vec::<String>::new();
+
+    // Prerequisites neither passed nor planned are taken elsewhere.
+    let mut outside = HashSet::<String>::new();
+    for course in courses {
+        let requirement = match graph.requirement(&course.course_id) {
+            Some(requirement) if !is_satisfied(requirement, passed) => requirement,
+            _ => continue
+        };
+        let missing: Vec<String> = requirement_courses(requirement)
+                                    .into_iter()
+                                    .filter(|code| !passed.contains(code) && !remaining.contains(code.as_str()))
+                                    .collect();
+        if !missing.is_empty() {
+            warnings.push(format!(
+                "{} needs {}, of which {} is neither passed nor planned and is taken to be done elsewhere.",
+                course.course_id, describe(requirement), missing.join(", ")
+            ));
+        }
+        outside.extend(missing);
+    }
+
+    let mut lengths = HashMap::new();
+    for course in courses {
+        chain_length(&course.course_id, graph, &remaining, &mut lengths, &mut HashSet::new());
+    }
+    let ready = |course: &RemainingCourse, done: &HashSet<String>| {
+        graph.requirement(&course.course_id).is_none_or(|requirement| is_satisfied(requirement, done))
+    };
+    // The courses done before a term, planned in an earlier one or not.
+    let done_before = |placed: &[Option<usize>], t: usize| {
+        let mut done: HashSet<String> = passed.union(&outside).cloned().collect();
+        done.extend(courses.iter().zip(placed).filter(|(_, p)| p.is_some_and(|p| p < t)).map(|(c, _)| c.course_id.clone()));
+        done
+    };
+
+    let mut placed = vec![None::<usize>; courses.len()];
+    let mut load = vec![0.0f32; terms.len()];
+    for (i, course) in courses.iter().enumerate() {
+        if let Some(t) = course.pinned.filter(|t| *t < terms.len()) {
+            placed[i] = Some(t);
+            load[t] += course.credits;
+        }
+    }
+    for (t, term) in terms.iter().enumerate() {
+        if term.skipped {
+            continue;
+        }
+        let done = done_before(&placed, t);
+        let mut candidates: Vec<usize> = (0..courses.len())
+                                            .filter(|i| placed[*i].is_none())
+                                            .filter(|i| offered(&courses[*i].course_id, &term.semester_no, offerings))
+                                            .filter(|i| ready(&courses[*i], &done))
+                                            .collect();
+        candidates.sort_by_key(|i| {
+            let course_id = &courses[*i].course_id;
+            let terms_offered = offerings.get(course_id).map_or(2, |seen| seen.len());
+            (std::cmp::Reverse(lengths.get(course_id).cloned().unwrap_or(0)), terms_offered, course_id.clone())
+        });
+        for i in candidates {
+            if load[t] + courses[i].credits <= term.max_credits {
+                placed[i] = Some(t);
+                load[t] += courses[i].credits;
+            }
+        }
+    }
+
+    for (course, t) in courses.iter().zip(&placed) {
+        let t = match (course.pinned, t) {
+            (Some(_), Some(t)) => *t,
+            _ => continue
+        };
+        let term = &terms[t];
+        let name = format!("{}-{}", term.semester_year, term.semester_no);
+        if term.skipped {
+            warnings.push(format!("{} is pinned to {}, which is skipped.", course.course_id, name));
+        }
+        if !offered(&course.course_id, &term.semester_no, offerings) {
+            warnings.push(format!("{} is pinned to {}, but it is not usually offered in semester {}.", course.course_id, name, term.semester_no));
+        }
+        if !ready(course, &done_before(&placed, t)) {
+            warnings.push(format!("{} is pinned to {}, before its prerequisites are done.", course.course_id, name));
+        }
+    }
+    for (i, t) in load.iter().enumerate() {
+        if *t > terms[i].max_credits {
+            warnings.push(format!(
+                "{}-{} has {} credits, more than its {}.",
+                terms[i].semester_year, terms[i].semester_no, t, terms[i].max_credits
+            ));
+        }
+    }
+
+    let open_terms: HashSet<&str> = terms.iter().filter(|term| !term.skipped).map(|term| term.semester_no.as_str()).collect();
+    let done = done_before(&placed, usize::MAX);
+    let unplaced: Vec<UnplacedCourse> = courses.iter()
+                                            .zip(&placed)
+                                            .filter(|(_, t)| t.is_none())
+                                            .map(|(course, _)| {
+                                                let reason = if !open_terms.iter().any(|no| offered(&course.course_id, no, offerings)) {
+                                                    let seen: Vec<String> = offerings.get(&course.course_id).into_iter().flatten().cloned().collect();
+                                                    format!("It is only offered in semester {}, which is not planned.", seen.join(", "))
+                                                } else if !ready(course, &done) {
+                                                    let requirement = graph.requirement(&course.course_id).map(describe).unwrap_or_default();
+                                                    format!("Its prerequisites {} are not done within the plan.", requirement)
+                                                } else {
+                                                    "There is no room for it within the credits of the semesters.".to_owned()
+                                                };
+                                                UnplacedCourse {
+                                                    course_id: course.course_id.clone(),
+                                                    course_name: course.course_name.clone(),
+                                                    reason,
+                                                }
+                                            })
+                                            .collect();
+
+    let mut placeholders: Vec<Vec<ElectivePlaceholder>> = terms.iter().map(|_| Vec::new()).collect();
+    for (category, credits) in electives {
+        let mut left = *credits;
+        for (t, term) in terms.iter().enumerate() {
+            let room = (term.max_credits - load[t]).max(0.0);
+            if term.skipped || left <= 0.0 || room <= 0.0 {
+                continue;
+            }
+            let taken = room.min(left);
+            placeholders[t].push(ElectivePlaceholder { category: category.clone(), credits: taken });
+            load[t] += taken;
+            left -= taken;
+        }
+        if left > 0.0 {
+            warnings.push(format!("{} credits of {} do not fit in the semesters planned.", left, category));
+        }
+    }
+
+    let mut planned: Vec<RoadmapTerm> = terms.iter()
+                                            .zip(placeholders)
+                                            .enumerate()
+                                            .map(|(t, (term, electives))| RoadmapTerm {
+                                                semester_year: term.semester_year.clone(),
+                                                semester_no: term.semester_no.clone(),
+                                                skipped: term.skipped,
+                                                max_credits: term.max_credits,
+                                                credits: load[t],
+                                                courses: courses.iter()
+                                                            .zip(&placed)
+                                                            .filter(|(_, p)| **p == Some(t))
+                                                            .map(|(course, _)| RoadmapCourse {
+                                                                course_id: course.course_id.clone(),
+                                                                course_name: course.course_name.clone(),
+                                                                credits: course.credits,
+                                                                pinned: course.pinned.is_some(),
+                                                                offered_in: offerings.get(&course.course_id).into_iter().flatten().cloned().collect(),
+                                                            })
+                                                            .collect(),
+                                                electives,
+                                            })
+                                            .collect();
+    while planned.last().is_some_and(|term| term.courses.is_empty() && term.electives.is_empty()) {
+        planned.pop();
+    }
+    StudyPlan {
+        terms: planned,
+        unplaced,
+        warnings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap, HashSet};
+
+    use super::super::prerequisites::PrerequisiteGraph;
+    use super::super::test_util::catalog_course;
+    use super::{parse_term, plan_semesters, terms_from, RemainingCourse, Term};
+
+    fn course(course_id: &str, credits: f32, pinned: Option<usize>) -> RemainingCourse {
+        RemainingCourse {
+            course_id: course_id.to_owned(),
+            course_name: None,
+            credits,
+            pinned,
+        }
+    }
+
+    #[test]
+    fn test_terms() {
+        assert_eq!(parse_term("2023-2024-2"), Some((2023, 2)));
+        assert_eq!(parse_term("2023-2025-1"), None);
+        let terms = terms_from((2023, 2), 3, true);
+        assert_eq!(terms[1], ("2023-2024".to_owned(), "3".to_owned()));
+        assert_eq!(terms[2], ("2024-2025".to_owned(), "1".to_owned()));
+        assert_eq!(terms_from((2023, 2), 2, false)[1], ("2024-2025".to_owned(), "1".to_owned()));
+    }
+
+    #[test]
+    fn test_plan_semesters() {
+        let graph = PrerequisiteGraph::new(&[
+            catalog_course("CS203", Some("CS102A")),
+            catalog_course("CS303", Some("CS203")),
+            catalog_course("CS305", Some("CS203")),
+            catalog_course("CS401", Some("CS303")),
+            catalog_course("MA212", None),
+            catalog_course("PHY105", Some("PHY104")),
+        ]);
+        let offerings: HashMap<String, BTreeSet<String>> = vec![
+            ("CS303".to_owned(), vec!["1".to_owned()].into_iter().collect()),
+            ("CS401".to_owned(), vec!["2".to_owned()].into_iter().collect()),
+        ].into_iter().collect();
+        let passed: HashSet<String> = vec!["CS102A".to_owned()].into_iter().collect();
+        let terms: Vec<Term> = terms_from((2023, 2), 5, false)
+                                .into_iter()
+                                .map(|(semester_year, semester_no)| Term {
+                                    skipped: semester_year == "2024-2025" && semester_no == "2",
+                                    semester_year,
+                                    semester_no,
+                                    max_credits: 6.0,
+                                })
+                                .collect();
+        let courses = vec![
+            course("CS203", 3.0, None),
+            course("CS303", 3.0, None),
+            course("CS305", 3.0, None),
+            course("CS401", 3.0, None),
+            course("MA212", 3.0, Some(0)),
+            course("PHY105", 4.0, None),
+        ];
+        let plan = plan_semesters(&courses, &[("通识选修".to_owned(), 4.0)], &passed, &terms, &graph, &offerings);
+        let ids = |t: usize| -> Vec<&str> { plan.terms[t].courses.iter().map(|c| c.course_id.as_str()).collect() };
+        assert_eq!(ids(0), vec!["CS203", "MA212"]);
+        assert_eq!(ids(1), vec!["CS303", "CS305"]);
+        assert!(plan.terms[2].skipped);
+        assert_eq!(ids(3), vec!["PHY105"]);
+        assert_eq!(ids(4), vec!["CS401"]);
+        assert_eq!(plan.terms[3].electives[0].credits, 2.0);
+        assert_eq!(plan.terms[4].electives[0].credits, 2.0);
+        assert!(plan.unplaced.is_empty());
+        assert!(plan.warnings[0].starts_with("PHY105 needs PHY104"));
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::lock::Mutex;
+use rocket::response::status::Unauthorized;
+use rocket::serde::Deserialize;
+use rocket::tokio;
+
+use super::courses::FetchError;
+use super::login::{refresh_tis_session, tis_login};
+use super::selection::{is_accepted, response_message, submit_select_course, validate_batch};
+use super::structures::*;
+use super::throttle::Throttle;
+use super::urls::*;
+
+/// No config can raise the number of attempts per course above this.
+const HARD_MAX_ATTEMPTS: u32 = 10;
+/// No config can make the retries of a course closer than this.
+const MIN_RETRY_INTERVAL_MS: u64 = 200;
+/// Submissions cannot be queued further ahead than this.
+const MAX_SCHEDULE_AHEAD_DAYS: i64 = 14;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    /// Seconds before the opening instant when the session is warmed up.
+    pub warm_up: u64,
+    /// Default and maximum number of attempts for each course.
+    pub max_attempts: u32,
+    /// Milliseconds between two attempts for the same course.
+    pub retry_interval_ms: u64,
+    /// Number of `Date` headers sampled to estimate the clock offset.
+    pub clock_samples: u32,
+    /// Seconds a finished or cancelled job stays listed before it is removed.
+    pub keep_finished: u64,
+    /// Seconds between two refreshes of the session of a queued job.
+    pub keep_alive: u64,
+    /// Jobs a user can have queued or running at the same time.
+    pub max_jobs_per_user: usize,
+    /// Courses a job can submit.
+    pub max_items: usize,
+    /// Attempts a job can make in total, all courses together.
+    pub max_total_attempts: u32,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            warm_up: 120,
+            max_attempts: 3,
+            retry_interval_ms: 500,
+            clock_samples: 5,
+            keep_finished: 3600,
+            keep_alive: 1800,
+            max_jobs_per_user: 3,
+            max_items: 10,
+            max_total_attempts: 20,
+        }
+    }
+}
+
+/// One request used to estimate the clock offset, all in unix milliseconds:
+/// when it was sent, when the response came back and the `Date` of TIS.
+#[derive(Clone, Copy)]
+pub struct ClockSample {
+    pub sent: i64,
+    pub received: i64,
+    pub server: i64,
+}
+
+/// Estimate how far the clock of TIS is ahead of ours, in milliseconds.
+///
+/// `Date` only has a resolution of one second and is truncated, so the
+/// middle of that second is compared with the middle of the round trip. The
+/// median of the samples is taken to ignore slow responses.
+pub fn estimate_offset(samples: &[ClockSample]) -> Option<i64> {
+    let mut offsets: Vec<i64> = samples.iter()
+                                    .map(|s| s.server + 500 - (s.sent + (s.received - s.sent) / 2))
+                                    .collect();
+    if offsets.is_empty() {
+        return None;
+    }
+    offsets.sort_unstable();
+    Some(offsets[offsets.len() / 2])
+}
+
+struct Job {
+    username: String,
+    /// The session of the job, kept until it is finished.
+    client: Option<reqwest::Client>,
+    fire_at: DateTime<Utc>,
+    selection: ScheduledSelection,
+}
+
+struct SchedulerInner {
+    config: SchedulerConfig,
+    throttle: Arc<Throttle>,
+    jobs: Mutex<HashMap<u64, Job>>,
+    next_id: AtomicU64,
+}
+
+/// Queue of pre-validated `select_course` submissions fired at a given
+/// instant, such as the opening of a selection round.
+///
+/// Each job logs in on its own session when it is queued and keeps it alive
+/// until shortly before the instant, when it estimates the offset between
+/// our clock and the one of TIS. It then submits its courses in order with
+/// a bounded number of attempts.
+#[derive(Clone)]
+pub struct Scheduler {
+    inner: Arc<SchedulerInner>,
+}
+
+fn is_pending(status: ScheduleStatus) -> bool {
+    matches!(status, ScheduleStatus::Queued | ScheduleStatus::WarmingUp | ScheduleStatus::Ready | ScheduleStatus::Firing)
+}
+
+/// Log in on a session of its own for a job, so that nothing else can log it
+/// out right before the submission.
+pub async fn job_session(
+    username: &str,
+    password: &str,
+) -> Result<reqwest::Client, String> {
+    let storage = Mutex::new(HashMap::<String, Account>::new());
+    if !tis_login(username, password, &storage).await.map_err(|Unauthorized(message)| message.unwrap_or_default())? {
+        return Err("Unable to log in to TIS for the scheduled selection".to_owned());
+    }
+    let client = storage.lock().await.get(username).unwrap().client.clone();
+    Ok(client)
+}
+
+async fn sleep_until(at: DateTime<Utc>) {
+    if let Ok(duration) = (at - Utc::now()).to_std() {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig, throttle: Arc<Throttle>) -> Self {
+        Scheduler {
+            inner: Arc::new(SchedulerInner {
+                config,
+                throttle,
+                jobs: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(1),
+            })
+        }
+    }
+
+    /// Check a request before a session is opened for it.
+    pub async fn check(
+        &self,
+        username: &str,
+        request: &ScheduledSelectionRequest,
+    ) -> Result<DateTime<Utc>, String> {
+        let fire_at = DateTime::parse_from_rfc3339(&request.fire_at)
+                            .map_err(|_| format!("Unable to parse {} as an RFC 3339 date time", request.fire_at))?
+                            .with_timezone(&Utc);
+        let now = Utc::now();
+        if fire_at <= now {
+            return Err("The submission instant has already passed".to_owned());
+        }
+        if fire_at - now > chrono::Duration::days(MAX_SCHEDULE_AHEAD_DAYS) {
+            return Err(format!("Submissions cannot be scheduled more than {} days ahead", MAX_SCHEDULE_AHEAD_DAYS));
+        }
+        if request.items.len() > self.inner.config.max_items {
+            return Err(format!("A scheduled selection cannot submit more than {} courses", self.inner.config.max_items));
+        }
+        validate_batch(&BatchSelectionRequest {
+            items: request.items.clone(),
+            points_budget: request.points_budget,
+            all_or_nothing: false,
+        })?;
+        let pending = self.inner.jobs.lock().await
+                            .values()
+                            .filter(|job| job.username == username && is_pending(job.selection.status))
+                            .count();
+        if pending >= self.inner.config.max_jobs_per_user {
+            return Err(format!("You cannot have more than {} scheduled selections at the same time", self.inner.config.max_jobs_per_user));
+        }
+        Ok(fire_at)
+    }
+
+    /// Queue a request with the session opened for it by [`job_session`].
+    pub async fn add(
+        &self,
+        username: &str,
+        client: reqwest::Client,
+        request: &ScheduledSelectionRequest,
+    ) -> Result<ScheduledSelection, String> {
+        let fire_at = self.check(username, request).await?;
+        let attempts_cap = self.inner.config.max_attempts.clamp(1, HARD_MAX_ATTEMPTS);
+        let max_attempts = request.max_attempts
+                                .unwrap_or(attempts_cap)
+                                .clamp(1, attempts_cap);
+
+        let selection = ScheduledSelection {
+            id: self.inner.next_id.fetch_add(1, Ordering::SeqCst),
+            fire_at: request.fire_at.clone(),
+            semester_year: request.semester_year.clone(),
+            semester_no: request.semester_no.clone(),
+            items: request.items.clone(),
+            max_attempts,
+            status: ScheduleStatus::Queued,
+            clock_offset_ms: None,
+            message: None,
+            attempts: Vec::new(),
+        };
+        self.inner.jobs.lock().await.insert(selection.id, Job {
+            username: username.to_owned(),
+            client: Some(client),
+            fire_at,
+            selection: selection.clone(),
+        });
+
+        let scheduler = self.clone();
+        let id = selection.id;
+        tokio::spawn(async move {
+            scheduler.run(id).await;
+        });
+        Ok(selection)
+    }
+
+    pub async fn list(
+        &self,
+        username: &str,
+    ) -> Vec<ScheduledSelection> {
+        let jobs = self.inner.jobs.lock().await;
+        let mut selections: Vec<ScheduledSelection> = jobs.values()
+                                                        .filter(|job| job.username == username)
+                                                        .map(|job| job.selection.clone())
+                                                        .collect();
+        selections.sort_by_key(|s| s.id);
+        selections
+    }
+
+    pub async fn cancel(
+        &self,
+        username: &str,
+        id: u64,
+    ) -> Option<ScheduledSelection> {
+        let mut jobs = self.inner.jobs.lock().await;
+        let job = jobs.get_mut(&id).filter(|job| job.username == username)?;
+        if is_pending(job.selection.status) {
+            job.selection.status = ScheduleStatus::Cancelled;
+            job.client = None;
+            self.forget_later(id);
+        }
+        Some(job.selection.clone())
+    }
+
+    /// Remove a finished or cancelled job once it has been listed for
+    /// `keep_finished` seconds.
+    fn forget_later(&self, id: u64) {
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(scheduler.inner.config.keep_finished)).await;
+            scheduler.inner.jobs.lock().await.remove(&id);
+        });
+    }
+
+    async fn is_cancelled(&self, id: u64) -> bool {
+        self.inner.jobs.lock().await
+                .get(&id)
+                .map(|job| job.selection.status == ScheduleStatus::Cancelled)
+                .unwrap_or(true)
+    }
+
+    /// Move a job to `status`, unless it has been cancelled meanwhile.
+    async fn advance(
+        &self,
+        id: u64,
+        status: ScheduleStatus,
+        message: Option<String>,
+    ) -> bool {
+        let mut jobs = self.inner.jobs.lock().await;
+        match jobs.get_mut(&id) {
+            Some(job) if job.selection.status != ScheduleStatus::Cancelled => {
+                job.selection.status = status;
+                if message.is_some() {
+                    job.selection.message = message;
+                }
+                if matches!(status, ScheduleStatus::Done | ScheduleStatus::Failed) {
+                    job.client = None;
+                    self.forget_later(id);
+                }
+                true
+            },
+            _ => false
+        }
+    }
+
+    async fn run(&self, id: u64) {
+        let (client, fire_at) = match self.inner.jobs.lock().await.get(&id) {
+            Some(Job { client: Some(client), fire_at, .. }) => (client.clone(), *fire_at),
+            _ => return
+        };
+        let warm_up_at = fire_at - chrono::Duration::seconds(self.inner.config.warm_up as i64);
+        let keep_alive = chrono::Duration::seconds(self.inner.config.keep_alive.max(60) as i64);
+        // The session is refreshed while the job waits, so that an expired
+        // one is reported long before the instant.
+        loop {
+            let next = Utc::now() + keep_alive;
+            if next >= warm_up_at {
+                break;
+            }
+            sleep_until(next).await;
+            if self.is_cancelled(id).await {
+                return;
+            }
+            if !self.refresh_session(id, &client).await {
+                return;
+            }
+        }
+
+        sleep_until(warm_up_at).await;
+        if !self.advance(id, ScheduleStatus::WarmingUp, None).await {
+            return;
+        }
+        if !self.refresh_session(id, &client).await {
+            return;
+        }
+
+        let offset = self.clock_offset(&client).await.unwrap_or(0);
+        if let Some(job) = self.inner.jobs.lock().await.get_mut(&id) {
+            job.selection.clock_offset_ms = Some(offset);
+        }
+        if !self.advance(id, ScheduleStatus::Ready, None).await {
+            return;
+        }
+
+        sleep_until(fire_at - chrono::Duration::milliseconds(offset)).await;
+        if !self.advance(id, ScheduleStatus::Firing, None).await {
+            return;
+        }
+        self.fire(id, &client).await;
+    }
+
+    /// Open a new TIS session with the CAS session of the job, failing the
+    /// job if the CAS session has expired.
+    async fn refresh_session(
+        &self,
+        id: u64,
+        client: &reqwest::Client,
+    ) -> bool {
+        self.inner.throttle.wait().await;
+        if refresh_tis_session(client).await.unwrap_or(false) {
+            return true;
+        }
+        let message = "The session of the scheduled selection expired, schedule it again".to_owned();
+        self.advance(id, ScheduleStatus::Failed, Some(message)).await;
+        false
+    }
+
+    async fn clock_offset(
+        &self,
+        client: &reqwest::Client,
+    ) -> Option<i64> {
+        let mut samples = Vec::<ClockSample>::new();
+        for _ in 0..self.inner.config.clock_samples {
+            self.inner.throttle.wait().await;
+            let sent = Utc::now().timestamp_millis();
+            let response = match client.head(TIS_URL).send().await {
+                Ok(response) => response,
+                Err(_) => continue
+            };
+            let received = Utc::now().timestamp_millis();
+            let server = response.headers()
+                                .get(reqwest::header::DATE)
+                                .and_then(|date| date.to_str().ok())
+                                .and_then(|date| DateTime::parse_from_rfc2822(date).ok());
+            if let Some(server) = server {
+                samples.push(ClockSample { sent, received, server: server.timestamp_millis() });
+            }
+        }
+        estimate_offset(&samples)
+    }
+
+    async fn fire(
+        &self,
+        id: u64,
+        client: &reqwest::Client,
+    ) {
+        let selection = match self.inner.jobs.lock().await.get(&id) {
+            Some(job) => job.selection.clone(),
+            None => return
+        };
+        let retry_interval = Duration::from_millis(self.inner.config.retry_interval_ms.max(MIN_RETRY_INTERVAL_MS));
+        let mut budget = self.inner.config.max_total_attempts.max(1);
+        let mut selected = 0;
+        let mut stopped = None;
+        'items: for item in &selection.items {
+            for attempt in 1..=selection.max_attempts {
+                // The job may be cancelled while it is firing.
+                if self.is_cancelled(id).await {
+                    break 'items;
+                }
+                if budget == 0 {
+                    stopped = Some("all the attempts of the job were used");
+                    break 'items;
+                }
+                budget -= 1;
+                self.inner.throttle.wait().await;
+                let result = submit_select_course(
+                    client,
+                    &selection.semester_year,
+                    &selection.semester_no,
+                    &item.course_id,
+                    &item.course_type,
+                    &item.points.to_string()
+                ).await;
+                let session_expired = matches!(result, Err(FetchError::SessionExpired));
+                let (status, message) = match result {
+                    Ok(response) if is_accepted(&response) => (SelectionStatus::Selected, response_message(&response)),
+                    Ok(response) => (SelectionStatus::Rejected, response_message(&response)),
+                    Err(e) => (SelectionStatus::Failed, Some(e.message()))
+                };
+                if let Some(job) = self.inner.jobs.lock().await.get_mut(&id) {
+                    job.selection.attempts.push(ScheduledAttempt {
+                        course_id: item.course_id.clone(),
+                        attempt,
+                        time: chrono::Local::now().to_rfc3339(),
+                        status,
+                        message,
+                    });
+                }
+                if session_expired {
+                    stopped = Some("the session expired");
+                    break 'items;
+                }
+                match status {
+                    SelectionStatus::Selected => {
+                        selected += 1;
+                        break;
+                    },
+                    // TIS answered, trying again would get the same answer.
+                    SelectionStatus::Rejected => break,
+                    _ => {}
+                }
+                if attempt < selection.max_attempts {
+                    tokio::time::sleep(retry_interval).await;
+                }
+            }
+        }
+        let message = match stopped {
+            Some(reason) => format!("{} of {} courses selected, stopped as {}", selected, selection.items.len(), reason),
+            None => format!("{} of {} courses selected", selected, selection.items.len())
+        };
+        self.advance(id, ScheduleStatus::Done, Some(message)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rocket::tokio;
+
+    use super::super::structures::*;
+    use super::super::throttle::{Throttle, ThrottleConfig};
+    use super::{estimate_offset, ClockSample, Scheduler, SchedulerConfig};
+
+    #[test]
+    fn test_estimate_offset() {
+        assert_eq!(estimate_offset(&[]), None);
+        // TIS is 2 seconds ahead, the round trip takes 100ms.
+        let samples = [
+            ClockSample { sent: 10_000, received: 10_100, server: 12_000 },
+            ClockSample { sent: 11_000, received: 11_100, server: 13_000 },
+            ClockSample { sent: 12_000, received: 14_000, server: 13_000 },
+        ];
+        assert_eq!(estimate_offset(&samples), Some(2_450));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_jobs_are_removed() {
+        let config = SchedulerConfig { keep_finished: 0, ..Default::default() };
+        let scheduler = Scheduler::new(config, Arc::new(Throttle::new(&ThrottleConfig::default())));
+        let request = ScheduledSelectionRequest {
+            fire_at: (chrono::Local::now() + chrono::Duration::hours(1)).to_rfc3339(),
+            semester_year: "2020-2021".to_owned(),
+            semester_no: "2".to_owned(),
+            items: vec![BatchSelectionItem { course_id: "A".to_owned(), course_type: "TP".to_owned(), points: 10 }],
+            points_budget: None,
+            max_attempts: None,
+        };
+        let selection = scheduler.add("student", reqwest::Client::new(), &request).await.unwrap();
+        assert!(scheduler.inner.jobs.lock().await[&selection.id].client.is_some());
+
+        let cancelled = scheduler.cancel("student", selection.id).await.unwrap();
+        assert_eq!(cancelled.status, ScheduleStatus::Cancelled);
+        assert!(scheduler.inner.jobs.lock().await[&selection.id].client.is_none());
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(scheduler.list("student").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_caps_jobs_and_items() {
+        let config = SchedulerConfig { max_jobs_per_user: 1, max_items: 2, ..Default::default() };
+        let scheduler = Scheduler::new(config, Arc::new(Throttle::new(&ThrottleConfig::default())));
+        let item = |course_id: &str| BatchSelectionItem { course_id: course_id.to_owned(), course_type: "TP".to_owned(), points: 10 };
+        let mut request = ScheduledSelectionRequest {
+            fire_at: (chrono::Local::now() + chrono::Duration::hours(1)).to_rfc3339(),
+            semester_year: "2020-2021".to_owned(),
+            semester_no: "2".to_owned(),
+            items: vec![item("A"), item("B"), item("C")],
+            points_budget: None,
+            max_attempts: None,
+        };
+        assert!(scheduler.check("student", &request).await.is_err());
+
+        request.items.pop();
+        scheduler.add("student", reqwest::Client::new(), &request).await.unwrap();
+        assert!(scheduler.add("student", reqwest::Client::new(), &request).await.is_err());
+        assert!(scheduler.check("another", &request).await.is_ok());
+    }
+}
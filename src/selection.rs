@@ -0,0 +1,226 @@
+use std::collections::HashSet;
+
+use rocket::response::status::Unauthorized;
+
+use super::courses::{send_json, FetchError};
+use super::structures::*;
+use super::urls::*;
+
+/// Map the course type tag used by our APIs to the `p_xkfsdm` code of TIS.
+pub fn course_type_code(course_type: &str) -> Option<&'static str> {
+    match course_type {
+        "GR" => Some("bxxk"), //  General Required
+        "GE" => Some("xxxk"), //  General Elective
+        "TP" => Some("kzyxk"), //  Courses within the training program
+        "NTP" => Some("zynknjxk"), //  Courses without the training program
+        _ => None
+    }
+}
+
+pub async fn submit_select_course(
+    client: &reqwest::Client,
+    semester_year: &str,
+    semester_no: &str,
+    course_id: &str,
+    course_type: &str,
+    points: &str,
+) -> Result<serde_json::Value, FetchError> {
+    let code_p_xkfsdm = course_type_code(course_type).unwrap_or_default();
+    let mut post_form = std::collections::HashMap::<&str, &str>::new();
+    post_form.insert("p_xn", semester_year);
+    post_form.insert("p_xq", semester_no);
+    post_form.insert("p_id", course_id);
+    post_form.insert("p_xkxs", points);
+    post_form.insert("p_pylx", "1");
+    post_form.insert("p_xkfsdm", code_p_xkfsdm);
+    post_form.insert("p_xktjz", "rwtjzyx");
+
+    send_json(client.post(SELECT_COURSE_URL).form(&post_form)).await
+}
+
+pub async fn submit_drop_course(
+    client: &reqwest::Client,
+    semester_year: &str,
+    semester_no: &str,
+    course_id: &str,
+) -> Result<serde_json::Value, FetchError> {
+    let mut post_form = std::collections::HashMap::<&str, &str>::new();
+    post_form.insert("p_xn", semester_year);
+    post_form.insert("p_xq", semester_no);
+    post_form.insert("p_id", course_id);
+    post_form.insert("p_pylx", "1");
+    post_form.insert("p_xkfsdm", "yixuan");
+
+    send_json(client.post(DROP_COURSE_URL).form(&post_form)).await
+}
+
+/// TIS answers the selection APIs with `jg` ("1" on success) and a
+/// human readable `message`.
+pub fn is_accepted(response: &serde_json::Value) -> bool {
+    match &response["jg"] {
+        serde_json::Value::String(s) => s == "1",
+        serde_json::Value::Number(n) => n.as_i64() == Some(1),
+        _ => false
+    }
+}
+
+pub fn response_message(response: &serde_json::Value) -> Option<String> {
+    response["message"].as_str()
+            .or_else(|| response["msg"].as_str())
+            .map(|s| s.to_owned())
+}
+
+/// Check a batch before anything is sent to TIS, so that an invalid batch
+/// never leaves the student with half of the selections made.
+pub fn validate_batch(
+    request: &BatchSelectionRequest
+) -> Result<u32, String> {
+    if request.items.is_empty() {
+        return Err("The batch does not contain any course".to_owned());
+    }
+    let mut seen = HashSet::<&str>::new();
+    for item in &request.items {
+        if !seen.insert(&item.course_id[..]) {
+            return Err(format!("Course {} appears more than once in the batch", item.course_id));
+        }
+        if course_type_code(&item.course_type).is_none() {
+            return Err(format!("Unknown course type {} for course {}", item.course_type, item.course_id));
+        }
+    }
+    let total_points = request.items
+                            .iter()
+                            .try_fold(0u32, |total, item| total.checked_add(item.points))
+                            .ok_or_else(|| "The total points of the batch are too large".to_owned())?;
+    if let Some(budget) = request.points_budget {
+        if total_points > budget {
+            return Err(format!("Total points {} exceed the budget {}", total_points, budget));
+        }
+    }
+    Ok(total_points)
+}
+
+/// Submit the items of a batch one after another in the order they are
+/// listed. In all-or-nothing mode the first failure stops the batch and the
+/// courses selected so far are dropped again.
+pub async fn batch_select(
+    client: &reqwest::Client,
+    semester_year: &str,
+    semester_no: &str,
+    request: &BatchSelectionRequest,
+) -> Result<BatchSelectionResult, Unauthorized<String>> {
+    let total_points = validate_batch(request).map_err(|e| Unauthorized(Some(e)))?;
+
+    let mut results = Vec::<SelectionResult>::new();
+    let mut failed = false;
+    for item in &request.items {
+        if failed && request.all_or_nothing {
+            results.push(SelectionResult {
+                course_id: item.course_id.clone(),
+                status: SelectionStatus::Skipped,
+                message: None,
+                response: None,
+            });
+            continue;
+        }
+        let result = match submit_select_course(
+            client,
+            semester_year,
+            semester_no,
+            &item.course_id,
+            &item.course_type,
+            &item.points.to_string()
+        ).await {
+            Ok(response) => SelectionResult {
+                course_id: item.course_id.clone(),
+                status: if is_accepted(&response) { SelectionStatus::Selected } else { SelectionStatus::Rejected },
+                message: response_message(&response),
+                response: Some(response),
+            },
+            Err(e) => SelectionResult {
+                course_id: item.course_id.clone(),
+                status: SelectionStatus::Failed,
+                message: Some(e.message()),
+                response: None,
+            }
+        };
+        if result.status != SelectionStatus::Selected {
+            failed = true;
+        }
+        results.push(result);
+    }
+
+    if failed && request.all_or_nothing {
+        for result in results.iter_mut().filter(|r| r.status == SelectionStatus::Selected) {
+            match submit_drop_course(client, semester_year, semester_no, &result.course_id).await {
+                Ok(response) if is_accepted(&response) => {
+                    result.status = SelectionStatus::RolledBack;
+                    result.message = response_message(&response);
+                    result.response = Some(response);
+                },
+                Ok(response) => {
+                    result.status = SelectionStatus::RollbackFailed;
+                    result.message = response_message(&response);
+                    result.response = Some(response);
+                },
+                Err(e) => {
+                    result.status = SelectionStatus::RollbackFailed;
+                    result.message = Some(e.message());
+                }
+            }
+        }
+    }
+
+    Ok(BatchSelectionResult {
+        total_points,
+        completed: !failed,
+        results,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::structures::*;
+
+    fn item(course_id: &str, course_type: &str, points: u32) -> BatchSelectionItem {
+        BatchSelectionItem {
+            course_id: course_id.to_owned(),
+            course_type: course_type.to_owned(),
+            points,
+        }
+    }
+
+    #[test]
+    fn test_validate_batch() {
+        let mut request = BatchSelectionRequest {
+            items: vec![item("a", "GR", 30), item("b", "TP", 50)],
+            points_budget: Some(100),
+            all_or_nothing: false,
+        };
+        assert_eq!(super::validate_batch(&request), Ok(80));
+
+        request.points_budget = Some(60);
+        assert!(super::validate_batch(&request).is_err());
+
+        request.points_budget = None;
+        request.items.push(item("a", "GE", 0));
+        assert!(super::validate_batch(&request).is_err());
+
+        request.items.pop();
+        request.items.push(item("c", "XX", 0));
+        assert!(super::validate_batch(&request).is_err());
+
+        // Points wrapping around would slip under the budget.
+        request.items = vec![item("a", "GR", u32::MAX), item("b", "TP", 2)];
+        request.points_budget = Some(100);
+        assert!(super::validate_batch(&request).is_err());
+    }
+
+    #[test]
+    fn test_is_accepted() {
+        assert!(super::is_accepted(&serde_json::json!({"jg": "1", "message": "ok"})));
+        assert!(super::is_accepted(&serde_json::json!({"jg": 1})));
+        assert!(!super::is_accepted(&serde_json::json!({"jg": "-1", "message": "full"})));
+        assert!(!super::is_accepted(&serde_json::json!({})));
+        assert_eq!(super::response_message(&serde_json::json!({"jg": "-1", "message": "full"})), Some("full".to_owned()));
+    }
+}
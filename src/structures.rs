@@ -1,4 +1,4 @@
-use rocket::serde::Serialize;
+use rocket::serde::{Deserialize, Serialize};
 
 #[derive(Serialize)]
 pub struct BasicInfo {
@@ -26,20 +26,28 @@ pub struct StudentGPA {
     pub rank: String
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone, Debug, Default)]
 pub struct CourseGrade {
     pub code: String,
     pub name: String,
     pub class_hour: String,
-    pub credit: u64,
+    pub credit: f64,
     pub semester: String,
     pub final_grade: String,
     pub final_level: String,
     pub department: String,
-    pub course_type: String
+    pub course_type: String,
+    /// Whether TIS flags it as a retake, or it is not the first attempt.
+    pub retake: bool,
+    /// The attempts at the course so far, and which one this is from 1.
+    pub attempt: u32,
+    pub attempts: u32,
+    /// Whether this attempt counts towards the credits and the GPA.
+    pub counted: bool,
+    pub excluded_reason: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Course {
     pub course_id: String,
     pub course_name: String,
@@ -47,16 +55,131 @@ pub struct Course {
     pub department: String,
 }
 
+/// A course of the TAO catalogue, with what its Chinese and English listings
+/// and detail pages tell about it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct CatalogCourse {
+    pub course_id: String,
+    pub course_name: String,
+    pub english_name: Option<String>,
+    pub credits: f32,
+    pub hours: Option<f32>,
+    pub department: String,
+    pub english_department: Option<String>,
+    pub description: Option<String>,
+    pub english_description: Option<String>,
+    pub prerequisites: Option<String>,
+    pub english_prerequisites: Option<String>,
+    /// Links to the detail pages on TAO.
+    pub detail_url: Option<String>,
+    pub english_detail_url: Option<String>,
+    /// RFC 3339 time the detail pages were last read, unset until they are.
+    pub details_fetched_at: Option<String>,
+}
+
+impl From<&CatalogCourse> for Course {
+    fn from(course: &CatalogCourse) -> Self {
+        Course {
+            course_id: course.course_id.clone(),
+            course_name: course.course_name.clone(),
+            credits: course.credits,
+            department: course.department.clone(),
+        }
+    }
+}
+
+/// What the detail page of a course on TAO tells, in its language.
+#[derive(Default, Debug, PartialEq)]
+pub struct CourseDetail {
+    pub english_name: Option<String>,
+    pub hours: Option<f32>,
+    pub description: Option<String>,
+    pub prerequisites: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionKind {
+    Lecture,
+    Lab,
+    Tutorial,
+    Unknown,
+}
+
+/// One teaching section of a class, such as its lecture or its lab.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TeachingSection {
+    pub kind: SectionKind,
+    /// The label TIS gives the section, if any.
+    pub label: Option<String>,
+    pub teachers: Vec<String>,
+    pub time_slots: Vec<TimeSlot>,
+}
+
+/// Teaching weeks of a slot, serialized as the sorted list of week numbers.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(into = "Vec<u32>")]
+pub struct WeekSet(u64);
+
+impl WeekSet {
+    /// Weeks after this one cannot be represented and are ignored.
+    pub const MAX_WEEK: u32 = 63;
+
+    pub fn insert(&mut self, week: u32) {
+        if (1..=Self::MAX_WEEK).contains(&week) {
+            self.0 |= 1 << week;
+        }
+    }
+
+    pub fn contains(&self, week: u32) -> bool {
+        (1..=Self::MAX_WEEK).contains(&week) && self.0 & (1 << week) != 0
+    }
+
+    pub fn union(&self, other: &WeekSet) -> WeekSet {
+        WeekSet(self.0 | other.0)
+    }
+
+    pub fn intersects(&self, other: &WeekSet) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn weeks(&self) -> Vec<u32> {
+        (1..=Self::MAX_WEEK).filter(|week| self.contains(*week)).collect()
+    }
+}
+
+impl From<WeekSet> for Vec<u32> {
+    fn from(weeks: WeekSet) -> Self {
+        weeks.weeks()
+    }
+}
+
+/// One weekly slot of a teaching section, such as
+/// `1-16周 星期一 第3-4节 一教101`. The parts which could not be read are
+/// left empty and the original text is always kept in `raw`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TimeSlot {
+    pub weeks: WeekSet,
+    /// 1 for Monday to 7 for Sunday.
+    pub weekday: Option<u32>,
+    pub start_period: Option<u32>,
+    pub end_period: Option<u32>,
+    pub building: Option<String>,
+    pub room: Option<String>,
+    pub raw: String,
+}
+
 #[derive(Serialize)]
 pub struct AdvancedCourse {
     pub basic_course: Course,
     pub course_type: String,
     pub course_class: String,
     pub id: String,
-    pub major_teacher: Vec<String>,
-    pub major_time_and_place: Vec<String>,
-    pub minor_teacher: Option<Vec<String>>,
-    pub minor_time_and_place: Option<Vec<String>>,
+    pub sections: Vec<TeachingSection>,
     pub undergraduated_available: u32,
     pub undergraduated_selected: u32,
     pub graduated_available: u32,
@@ -75,21 +198,695 @@ pub struct AvailableCourse {
     pub advanced_course: AdvancedCourse,
     pub outline_id: String,
     pub conflict_courses: Option<String>,
+    /// The classes of `conflict_courses`, one by one.
+    pub conflicts: Vec<ConflictReference>,
 }
 
-#[derive(Serialize)]
+/// Two slots which clash, of an available class and of a selected one.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ClashingSlots {
+    pub slot: TimeSlot,
+    pub selected_slot: TimeSlot,
+}
+
+/// A class an available class clashes with, as TIS names it.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ConflictReference {
+    pub course_id: Option<String>,
+    pub course_name: String,
+    pub course_class: Option<String>,
+    /// The `id` of the class in the selected courses, once linked to it.
+    pub selected_class_id: Option<String>,
+    /// The slots of both classes which clash, once linked.
+    pub clashing_slots: Vec<ClashingSlots>,
+    pub raw: String,
+}
+
+#[derive(Serialize, Clone, PartialEq)]
 pub struct CurrentSemester {
     pub semester_year: String,
     pub semester_no: String,
 }
 
+/// One course in a cell of the course table.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CourseTableEntry {
+    pub course_name: String,
+    pub course_class: Option<String>,
+    pub teachers: Vec<String>,
+    pub building: Option<String>,
+    pub room: Option<String>,
+    pub weeks: WeekSet,
+    /// The lines of `kbxx` this entry was read from.
+    pub raw: String,
+}
+
 #[derive(Serialize)]
 pub struct CourseTableItem {
     pub day: u32,
     pub time: u32,
+    pub entries: Vec<CourseTableEntry>,
+    /// The original `kbxx` text of the cell.
     pub course_info: String,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BatchSelectionItem {
+    pub course_id: String,
+    pub course_type: String,
+    pub points: u32,
+}
+
+#[derive(Deserialize)]
+pub struct BatchSelectionRequest {
+    pub items: Vec<BatchSelectionItem>,
+    pub points_budget: Option<u32>,
+    #[serde(default)]
+    pub all_or_nothing: bool,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionStatus {
+    Selected,
+    Rejected,
+    Failed,
+    Skipped,
+    RolledBack,
+    RollbackFailed,
+}
+
+#[derive(Serialize)]
+pub struct SelectionResult {
+    pub course_id: String,
+    pub status: SelectionStatus,
+    pub message: Option<String>,
+    pub response: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct BatchSelectionResult {
+    pub total_points: u32,
+    pub completed: bool,
+    pub results: Vec<SelectionResult>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Capacity {
+    pub available: u32,
+    pub selected: u32,
+}
+
+impl Capacity {
+    pub fn of(course: &AdvancedCourse) -> Self {
+        Capacity {
+            available: course.undergraduated_available,
+            selected: course.undergraduated_selected,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.selected >= self.available
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct WatchTarget {
+    pub class_id: String,
+    pub semester_year: String,
+    pub semester_no: String,
+    pub courses_type: String,
+    pub webhook: Option<String>,
+    pub course_name: Option<String>,
+    pub capacity: Option<Capacity>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SeatEventKind {
+    Opened,
+    Filled,
+    SessionExpired,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SeatEvent {
+    pub username: String,
+    pub kind: SeatEventKind,
+    pub class_id: Option<String>,
+    pub course_name: Option<String>,
+    pub capacity: Option<Capacity>,
+    pub time: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WaitlistOption {
+    pub class_id: String,
+    pub course_type: String,
+    pub points: u32,
+}
+
+#[derive(Deserialize)]
+pub struct WaitlistRequest {
+    pub semester_year: String,
+    pub semester_no: String,
+    pub options: Vec<WaitlistOption>,
+    #[serde(default)]
+    pub drop_fallback: bool,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitlistStatus {
+    Waiting,
+    Holding,
+    Satisfied,
+    Paused,
+    SessionExpired,
+    Cancelled,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Waitlist {
+    pub id: u64,
+    pub semester_year: String,
+    pub semester_no: String,
+    pub options: Vec<WaitlistOption>,
+    pub drop_fallback: bool,
+    pub status: WaitlistStatus,
+    pub held: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Registered,
+    Selected,
+    SelectRejected,
+    Dropped,
+    DropFailed,
+    Paused,
+    Resumed,
+    SessionExpired,
+    Satisfied,
+    Cancelled,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AuditEntry {
+    pub time: String,
+    pub waitlist_id: u64,
+    pub action: AuditAction,
+    pub class_id: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ScheduledSelectionRequest {
+    pub fire_at: String,
+    pub semester_year: String,
+    pub semester_no: String,
+    pub items: Vec<BatchSelectionItem>,
+    pub points_budget: Option<u32>,
+    pub max_attempts: Option<u32>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleStatus {
+    Queued,
+    WarmingUp,
+    Ready,
+    Firing,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScheduledAttempt {
+    pub course_id: String,
+    pub attempt: u32,
+    pub time: String,
+    pub status: SelectionStatus,
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScheduledSelection {
+    pub id: u64,
+    pub fire_at: String,
+    pub semester_year: String,
+    pub semester_no: String,
+    pub items: Vec<BatchSelectionItem>,
+    pub max_attempts: u32,
+    pub status: ScheduleStatus,
+    pub clock_offset_ms: Option<i64>,
+    pub message: Option<String>,
+    pub attempts: Vec<ScheduledAttempt>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EnrolmentSnapshot {
+    pub time: String,
+    pub semester_year: String,
+    pub semester_no: String,
+    pub class_id: String,
+    pub course_code: String,
+    pub course_name: String,
+    pub available: u32,
+    pub selected: u32,
+}
+
+#[derive(Serialize)]
+pub struct PointsRecommendation {
+    pub class_id: String,
+    pub course_name: Option<String>,
+    pub points: u32,
+    pub demand_ratio: Option<f64>,
+    pub projected_ratio: Option<f64>,
+    pub historical_ratio: Option<f64>,
+    pub snapshots: usize,
+    pub rationale: String,
+}
+
+#[derive(Serialize)]
+pub struct PointsAllocation {
+    pub budget: u32,
+    pub allocated: u32,
+    pub recommendations: Vec<PointsRecommendation>,
+}
+
+/// The week and the weekday whose timetable applies on a day.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct DaySchedule {
+    pub week: u32,
+    /// 1 for Monday to 7 for Sunday.
+    pub weekday: u32,
+}
+
+#[derive(Serialize)]
+pub struct CalendarDay {
+    pub date: String,
+    /// Teaching week the date is in, if it is in the semester.
+    pub week: Option<u32>,
+    /// None on holidays and outside the semester.
+    pub schedule: Option<DaySchedule>,
+    pub holiday: Option<String>,
+    /// The date whose classes are held instead, on a make-up day.
+    pub follows: Option<String>,
+}
+
+/// A class on a given date, over one or more consecutive periods.
+#[derive(Serialize)]
+pub struct ScheduledClass {
+    /// Set when the class is taken on a given date.
+    pub date: Option<String>,
+    pub course_name: String,
+    pub course_class: Option<String>,
+    pub teachers: Vec<String>,
+    pub building: Option<String>,
+    pub room: Option<String>,
+    pub start_period: u32,
+    pub end_period: u32,
+    /// `HH:MM` from the bell schedule, if it has the period.
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TodayClasses {
+    pub semester_year: String,
+    pub semester_no: String,
+    pub day: CalendarDay,
+    pub classes: Vec<ScheduledClass>,
+}
+
+#[derive(Serialize)]
+pub struct NextClass {
+    pub class: ScheduledClass,
+    /// Seconds until the class starts.
+    pub starts_in: i64,
+}
+
+#[derive(Serialize)]
+pub struct CalendarSubscription {
+    pub token: String,
+    /// Path of the calendar on this server, to be used with `webcal://`.
+    pub path: String,
+}
+
+/// A class chosen by the timetable planner.
+#[derive(Serialize)]
+pub struct PlannedClass {
+    pub course_id: String,
+    pub course_name: String,
+    pub class_id: String,
+    pub course_class: String,
+    /// The tag to select the class with, as in `/available_courses`.
+    pub courses_type: String,
+    pub time_slots: Vec<TimeSlot>,
+}
+
+#[derive(Serialize)]
+pub struct PlannedSchedule {
+    /// Up to 100, higher is better.
+    pub score: f64,
+    pub gap_periods: u32,
+    pub building_changes: u32,
+    pub class_days: u32,
+    pub classes: Vec<PlannedClass>,
+}
+
+#[derive(Serialize)]
+pub struct TimetablePlan {
+    pub schedules: Vec<PlannedSchedule>,
+    /// Wanted courses without any class meeting the constraints.
+    pub unplaced_courses: Vec<String>,
+    /// Partial combinations tried.
+    pub searched: usize,
+    /// False when the search limit was hit before trying every combination.
+    pub complete: bool,
+}
+
+#[derive(Serialize)]
+pub struct CatalogueMatch {
+    pub course: CatalogCourse,
+    /// Up to 100, higher is better.
+    pub score: f64,
+}
+
+#[derive(Serialize)]
+pub struct CatalogueSearch {
+    /// RFC 3339 time the course list was downloaded from TAO.
+    pub fetched_at: Option<String>,
+    /// Set when the list is older than the refresh interval, or the last
+    /// download failed.
+    pub stale: bool,
+    /// Number of matches before the limit.
+    pub total: usize,
+    pub courses: Vec<CatalogueMatch>,
+}
+
+#[derive(Serialize)]
+pub struct OutlineHit {
+    pub outline_id: String,
+    pub course_id: Option<String>,
+    pub course_name: Option<String>,
+    pub score: f64,
+    /// HTML escaped text around the first match, with the matches in `<mark>`.
+    pub snippet: String,
+}
+
+#[derive(Serialize)]
+pub struct OutlineSearch {
+    /// Number of outlines in the index.
+    pub indexed: usize,
+    /// Number of matches before the limit.
+    pub total: usize,
+    pub hits: Vec<OutlineHit>,
+}
+
+#[derive(Serialize)]
+pub struct OutlineIndexStatus {
+    /// Outlines queued by this request.
+    pub added: usize,
+    /// Outlines waiting to be fetched.
+    pub queued: usize,
+    pub indexed: usize,
+}
+
+#[derive(Serialize, Default)]
+pub struct CourseHours {
+    pub total: Option<f32>,
+    pub lecture: Option<f32>,
+    pub lab: Option<f32>,
+    pub practice: Option<f32>,
+}
+
+/// A part of the final grade, such as `期末考试 50%`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AssessmentItem {
+    pub name: String,
+    /// Percentage of the final grade.
+    pub weight: f32,
+}
+
+/// The outline of a course as TIS gives it, where the rich text fields are
+/// in the format asked for.
+#[derive(Serialize)]
+pub struct CourseOutline {
+    pub outline_id: String,
+    pub course_id: Option<String>,
+    pub course_name: Option<String>,
+    pub english_name: Option<String>,
+    pub credits: Option<f32>,
+    pub hours: CourseHours,
+    pub description: Option<String>,
+    pub english_description: Option<String>,
+    pub objectives: Option<String>,
+    pub assessment: Option<String>,
+    /// The parts of `assessment` with a percentage.
+    pub assessment_items: Vec<AssessmentItem>,
+    pub textbooks: Vec<String>,
+    pub references: Vec<String>,
+    pub prerequisites: Option<String>,
+    /// The other fields TIS filled in, in the format asked for.
+    pub other_fields: std::collections::BTreeMap<String, String>,
+}
+
+/// The courses needed before taking a course.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Requirement {
+    Course(String),
+    /// Every one of them.
+    All(Vec<Requirement>),
+    /// At least one of them.
+    Any(Vec<Requirement>),
+}
+
+#[derive(Serialize)]
+pub struct PrerequisiteInfo {
+    pub course_id: String,
+    pub course_name: String,
+    /// The prerequisites as written in the catalogue.
+    pub text: Option<String>,
+    /// The course codes of `text`, unset when it names none.
+    pub requirement: Option<Requirement>,
+    pub prerequisites: Vec<String>,
+    /// The prerequisites, their prerequisites and so on.
+    pub all_prerequisites: Vec<String>,
+    /// The courses which need this one.
+    pub unlocks: Vec<String>,
+    pub all_unlocks: Vec<String>,
+    /// This course and its prerequisites whose detail pages have not been
+    /// read yet, which may need more courses than listed.
+    pub unknown: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Eligibility {
+    pub course_id: String,
+    pub course_name: Option<String>,
+    /// Set unless a prerequisite is known to be missing.
+    pub eligible: bool,
+    pub requirement: Option<Requirement>,
+    /// The prerequisites which have not been passed.
+    pub missing: Vec<String>,
+    pub explanation: String,
+}
+
+#[derive(Serialize)]
+pub struct GroupAudit {
+    pub name: String,
+    pub category: String,
+    pub satisfied: bool,
+    pub min_credits: Option<f32>,
+    pub earned_credits: f32,
+    pub remaining_credits: f32,
+    pub min_courses: Option<usize>,
+    /// The passed courses counted towards the group.
+    pub courses: Vec<String>,
+    /// The required courses not passed, alternatives joined by `/`.
+    pub missing_required: Vec<String>,
+}
+
+/// How far the passed courses of a student are from the training plan.
+#[derive(Serialize)]
+pub struct DegreeAudit {
+    pub major: String,
+    /// The cohort year of the plan used, which may be before the student's.
+    pub plan_year: String,
+    pub satisfied: bool,
+    pub total_credits: Option<f32>,
+    pub earned_credits: f32,
+    pub remaining_credits: f32,
+    pub remaining_by_category: std::collections::BTreeMap<String, f32>,
+    pub groups: Vec<GroupAudit>,
+    /// The required courses not passed, of all the groups.
+    pub courses_needed: Vec<String>,
+    /// The passed courses no group takes.
+    pub unassigned: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct TrainingPlanKey {
+    pub major: String,
+    pub year: String,
+}
+
+#[derive(Serialize)]
+pub struct RoadmapCourse {
+    pub course_id: String,
+    pub course_name: Option<String>,
+    pub credits: f32,
+    pub pinned: bool,
+    /// The semester numbers it has been seen in, empty if never.
+    pub offered_in: Vec<String>,
+}
+
+/// Credits of a category of electives, to be taken in a semester.
+#[derive(Serialize)]
+pub struct ElectivePlaceholder {
+    pub category: String,
+    pub credits: f32,
+}
+
+#[derive(Serialize)]
+pub struct RoadmapTerm {
+    pub semester_year: String,
+    pub semester_no: String,
+    pub skipped: bool,
+    pub max_credits: f32,
+    pub credits: f32,
+    pub courses: Vec<RoadmapCourse>,
+    pub electives: Vec<ElectivePlaceholder>,
+}
+
+#[derive(Serialize)]
+pub struct UnplacedCourse {
+    pub course_id: String,
+    pub course_name: Option<String>,
+    pub reason: String,
+}
+
+/// The courses left to take, semester by semester.
+#[derive(Serialize)]
+pub struct StudyPlan {
+    pub terms: Vec<RoadmapTerm>,
+    pub unplaced: Vec<UnplacedCourse>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct CourseAttempts {
+    pub code: String,
+    pub name: String,
+    /// The `attempt` which counts.
+    pub counted_attempt: Option<u32>,
+    pub attempts: Vec<CourseGrade>,
+}
+
+/// The grades grouped by course, with the credits and the GPA of the
+/// counted attempts.
+#[derive(Serialize)]
+pub struct GradeHistory {
+    pub retake_policy: String,
+    pub courses: Vec<CourseAttempts>,
+    pub attempted_credits: f64,
+    pub earned_credits: f64,
+    /// The credits of the counted attempts with a grade point.
+    pub gpa_credits: f64,
+    pub gpa: Option<f64>,
+    /// The attempts left out of the credits and the GPA.
+    pub excluded: Vec<CourseGrade>,
+}
+
+/// A counted grade as converted for a transcript summary.
+#[derive(Serialize)]
+pub struct ConvertedCourse {
+    pub code: String,
+    pub name: String,
+    pub semester: String,
+    pub credit: f64,
+    pub course_type: String,
+    pub final_grade: String,
+    pub final_level: String,
+    pub percentage: f64,
+    /// `score` or `letter`, what the percentage comes from.
+    pub source: String,
+    /// Whether it counts in the major average.
+    pub major: bool,
+    /// The grade point on each scale, by the name of the scale.
+    pub points: std::collections::BTreeMap<String, f64>,
+}
+
+#[derive(Serialize)]
+pub struct ExcludedGrade {
+    pub code: String,
+    pub name: String,
+    pub semester: String,
+    pub final_grade: String,
+    pub final_level: String,
+    pub reason: String,
+}
+
+#[derive(Serialize)]
+pub struct ScaleSummary {
+    pub name: String,
+    pub description: String,
+    pub credits: f64,
+    pub gpa: Option<f64>,
+    pub major_gpa: Option<f64>,
+    /// The bands of the scale.
+    pub rules: Vec<String>,
+}
+
+/// The grades converted into other scales, with the courses used and left
+/// out and the rules applied, for applications.
+#[derive(Serialize)]
+pub struct TranscriptSummary {
+    pub credits: f64,
+    /// Weighted by credits.
+    pub percentage_average: Option<f64>,
+    pub arithmetic_average: Option<f64>,
+    pub major_credits: f64,
+    pub major_average: Option<f64>,
+    pub scales: Vec<ScaleSummary>,
+    pub rules: Vec<String>,
+    pub courses: Vec<ConvertedCourse>,
+    pub excluded: Vec<ExcludedGrade>,
+}
+
+#[derive(Serialize)]
+pub struct TranscriptSemester {
+    pub semester: String,
+    pub courses: Vec<CourseGrade>,
+    /// The credits of the counted attempts, and of those passed.
+    pub attempted_credits: f64,
+    pub earned_credits: f64,
+    /// The GPA of TIS, and the one computed from the counted attempts.
+    pub gpa: Option<f64>,
+    pub computed_gpa: Option<f64>,
+}
+
+/// The whole grade history of a student, semester by semester, for an
+/// unofficial transcript.
+#[derive(Serialize)]
+pub struct Transcript {
+    pub info: BasicInfo,
+    pub retake_policy: String,
+    pub semesters: Vec<TranscriptSemester>,
+    pub attempted_credits: f64,
+    pub earned_credits: f64,
+    pub gpa: f64,
+    pub computed_gpa: Option<f64>,
+    pub rank: String,
+    pub generated_at: String,
+}
+
 pub struct Account {
     pub hash_salt: Option<(
         [u8; super::encrypt::CREDENTIAL_LEN], 
@@ -0,0 +1,74 @@
+//! Builders of the structures the unit tests of several modules share.
+
+use super::parser::{parse_course_table_info, parse_time_slot};
+use super::structures::*;
+
+/// A cell of the course table, as TIS would send it.
+pub fn table_item(day: u32, time: u32, kbxx: &str) -> CourseTableItem {
+    CourseTableItem {
+        day,
+        time,
+        entries: parse_course_table_info(kbxx),
+        course_info: kbxx.to_owned(),
+    }
+}
+
+/// A grade as TIS lists it, counted until a retake policy says otherwise.
+pub fn course_grade(
+    code: &str,
+    credit: f64,
+    semester: &str,
+    final_grade: &str,
+    final_level: &str,
+    course_type: &str,
+) -> CourseGrade {
+    CourseGrade {
+        code: code.to_owned(),
+        name: code.to_owned(),
+        credit,
+        semester: semester.to_owned(),
+        final_grade: final_grade.to_owned(),
+        final_level: final_level.to_owned(),
+        course_type: course_type.to_owned(),
+        counted: true,
+        ..Default::default()
+    }
+}
+
+/// A course of the catalogue whose detail page has been read, so that its
+/// prerequisites are known.
+pub fn catalog_course(course_id: &str, prerequisites: Option<&str>) -> CatalogCourse {
+    CatalogCourse {
+        course_id: course_id.to_owned(),
+        course_name: course_id.to_owned(),
+        prerequisites: prerequisites.map(|text| text.to_owned()),
+        details_fetched_at: Some("2026-09-01T00:00:00+08:00".to_owned()),
+        ..Default::default()
+    }
+}
+
+/// A class with one lecture at the given slots, such as
+/// `1-16周 星期一 第3-4节 一教101`, and half of its 60 seats taken.
+pub fn advanced_course(course_id: &str, course_name: &str, course_class: &str, id: &str, slots: &[&str]) -> AdvancedCourse {
+    AdvancedCourse {
+        basic_course: Course {
+            course_id: course_id.to_owned(),
+            course_name: course_name.to_owned(),
+            credits: 3.0,
+            department: String::new(),
+        },
+        course_type: String::new(),
+        course_class: course_class.to_owned(),
+        id: id.to_owned(),
+        sections: vec![TeachingSection {
+            kind: SectionKind::Lecture,
+            label: None,
+            teachers: Vec::new(),
+            time_slots: slots.iter().map(|slot| parse_time_slot(slot)).collect(),
+        }],
+        undergraduated_available: 60,
+        undergraduated_selected: 30,
+        graduated_available: 0,
+        graduated_selected: 0,
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use futures::lock::Mutex;
+use rocket::serde::Deserialize;
+use rocket::tokio::time::{sleep, Instant};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ThrottleConfig {
+    /// The minimum gap between two requests the background jobs send to TIS.
+    pub min_interval_ms: u64,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        ThrottleConfig { min_interval_ms: 1000 }
+    }
+}
+
+/// Rate limiter shared by every background job that talks to TIS, so that
+/// the server as a whole stays polite no matter how many jobs are running.
+pub struct Throttle {
+    min_interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn new(config: &ThrottleConfig) -> Self {
+        Throttle {
+            min_interval: Duration::from_millis(config.min_interval_ms),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until the next request is allowed to go out.
+    pub async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        if *next > now {
+            sleep(*next - now).await;
+        }
+        *next = Instant::now() + self.min_interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::tokio;
+
+    #[tokio::test]
+    async fn test_throttle_wait() {
+        let throttle = super::Throttle::new(&super::ThrottleConfig { min_interval_ms: 50 });
+        let start = tokio::time::Instant::now();
+        throttle.wait().await;
+        throttle.wait().await;
+        throttle.wait().await;
+        assert!(start.elapsed() >= std::time::Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use rocket::serde::Deserialize;
+
+use super::prerequisites::is_passed;
+use super::structures::*;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TrainingConfig {
+    /// Directory of the training plan files, one JSON file per major and
+    /// cohort year.
+    pub directory: String,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        TrainingConfig { directory: "plans".to_owned() }
+    }
+}
+
+/// A part of a training plan, such as 通识必修课 or 专业选修课.
+#[derive(Deserialize, Clone)]
+pub struct RequirementGroup {
+    pub name: String,
+    /// What the remaining credits are counted under, the name if missing.
+    pub category: Option<String>,
+    pub min_credits: Option<f32>,
+    pub min_courses: Option<usize>,
+    /// Courses which all have to be passed, where `MA101A|MA101B` means
+    /// either of them.
+    #[serde(default)]
+    pub required: Vec<String>,
+    /// Courses counted towards the group when passed.
+    #[serde(default)]
+    pub electives: Vec<String>,
+    /// Starts of course codes counted towards the group, such as `GE`.
+    #[serde(default)]
+    pub elective_prefixes: Vec<String>,
+    /// Course types of the grades counted towards the group, such as 通识选修.
+    #[serde(default)]
+    pub course_types: Vec<String>,
+}
+
+impl RequirementGroup {
+    /// A group without any course list takes any course, such as the free
+    /// electives.
+    fn takes_any(&self) -> bool {
+        self.required.is_empty() && self.electives.is_empty()
+            && self.elective_prefixes.is_empty() && self.course_types.is_empty()
+    }
+
+    fn takes(&self, grade: &CourseGrade, code: &str) -> bool {
+        self.takes_any()
+            || self.electives.iter().any(|elective| elective.eq_ignore_ascii_case(code))
+            || self.elective_prefixes.iter().any(|prefix| code.starts_with(&prefix.to_uppercase()))
+            || self.course_types.iter().any(|course_type| grade.course_type.trim() == course_type)
+    }
+
+    fn category(&self) -> &str {
+        self.category.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// The training plan (培养方案) of a major for the students of a cohort year,
+/// as read from its file.
+#[derive(Deserialize, Clone)]
+pub struct TrainingPlan {
+    pub major: String,
+    pub year: String,
+    pub total_credits: Option<f32>,
+    pub groups: Vec<RequirementGroup>,
+}
+
+/// The year of a cohort, such as 2021 for `2021级`.
+fn cohort_year(year: &str) -> Option<u32> {
+    year.chars().filter(|c| c.is_ascii_digit()).collect::<String>().parse::<u32>().ok()
+}
+
+fn alternatives(entry: &str) -> Vec<String> {
+    entry.split('|').map(|code| code.trim().to_uppercase()).filter(|code| !code.is_empty()).collect()
+}
+
+struct Counted {
+    credits: f32,
+    courses: Vec<String>,
+}
+
+/// Check passed courses against a training plan. Each passed course counts
+/// towards one group only: first towards the group requiring it, then
+/// towards the first group in the plan which takes it and is not satisfied
+/// yet. The courses no group takes are left unassigned.
+pub fn audit(plan: &TrainingPlan, grades: &[CourseGrade]) -> DegreeAudit {
+    // Only the attempt counted under the retake policy, once.
+    let mut passed = Vec::<(String, &CourseGrade)>::new();
+    for grade in grades.iter().filter(|grade| grade.counted && is_passed(grade)) {
+        let code = grade.code.trim().to_uppercase();
+        if !passed.iter().any(|(other, _)| *other == code) {
+            passed.push((code, grade));
+        }
+    }
+    let mut assigned = vec![None::<usize>; passed.len()];
+    let mut counted: Vec<Counted> = plan.groups.iter().map(|_| Counted { credits: 0.0, courses: Vec::new() }).collect();
+    let mut missing_required: Vec<Vec<String>> = plan.groups.iter().map(|_| Vec::new()).collect();
+
+    for (g, group) in plan.groups.iter().enumerate() {
+        for entry in group.required.iter() {
+            let codes = alternatives(entry);
+            let found = passed.iter()
+                            .enumerate()
+                            .find(|(i, (code, _))| assigned[*i].is_none() && codes.contains(code))
+                            .map(|(i, _)| i);
+            match found {
+                Some(course) => {
+                    counted[g].credits += passed[course].1.credit as f32;
+                    counted[g].courses.push(passed[course].0.clone());
+                    assigned[course] = Some(g);
+                },
+                None => missing_required[g].push(codes.join("/"))
+            }
+        }
+    }
+    let satisfied = |group: &RequirementGroup, counted: &Counted| {
+        group.min_credits.is_none_or(|min| counted.credits >= min)
+            && group.min_courses.is_none_or(|min| counted.courses.len() >= min)
+    };
+    for (g, group) in plan.groups.iter().enumerate() {
+        for course in 0..passed.len() {
+            if satisfied(group, &counted[g]) && (group.min_credits.is_some() || group.min_courses.is_some()) {
+                break;
+            }
+            let (code, grade) = &passed[course];
+            if assigned[course].is_none() && group.takes(grade, code) {
+                counted[g].credits += grade.credit as f32;
+                counted[g].courses.push(code.clone());
+                assigned[course] = Some(g);
+            }
+        }
+    }
+
+    let mut remaining_by_category = BTreeMap::<String, f32>::new();
+    let mut courses_needed = Vec::<String>::new();
+    let groups: Vec<GroupAudit> = plan.groups
+                                    .iter()
+                                    .zip(counted)
+                                    .zip(missing_required)
+                                    .map(|((group, counted), missing_required)| {
+                                        let remaining_credits = group.min_credits.map_or(0.0, |min| (min - counted.credits).max(0.0));
+                                        *remaining_by_category.entry(group.category().to_owned()).or_default() += remaining_credits;
+                                        courses_needed.extend(missing_required.iter().cloned());
+                                        GroupAudit {
+                                            name: group.name.clone(),
+                                            category: group.category().to_owned(),
+                                            satisfied: satisfied(group, &counted) && missing_required.is_empty(),
+                                            min_credits: group.min_credits,
+                                            earned_credits: counted.credits,
+                                            remaining_credits,
+                                            min_courses: group.min_courses,
+                                            courses: counted.courses,
+                                            missing_required,
+                                        }
+                                    })
+                                    .collect();
+    let earned_credits: f32 = passed.iter().map(|(_, grade)| grade.credit as f32).sum();
+    let remaining_credits = plan.total_credits.map_or(0.0, |total| (total - earned_credits).max(0.0));
+    DegreeAudit {
+        major: plan.major.clone(),
+        plan_year: plan.year.clone(),
+        satisfied: remaining_credits == 0.0 && groups.iter().all(|group| group.satisfied),
+        total_credits: plan.total_credits,
+        earned_credits,
+        remaining_credits,
+        remaining_by_category,
+        courses_needed,
+        unassigned: passed.iter()
+                        .zip(assigned)
+                        .filter(|(_, group)| group.is_none())
+                        .map(|((code, _), _)| code.clone())
+                        .collect(),
+        groups,
+    }
+}
+
+/// The training plans of all the majors and cohorts, loaded once at launch.
+#[derive(Clone)]
+pub struct TrainingPlans {
+    plans: Arc<HashMap<String, Vec<TrainingPlan>>>,
+}
+
+impl TrainingPlans {
+    pub fn load(config: &TrainingConfig) -> Self {
+        let mut plans = HashMap::<String, Vec<TrainingPlan>>::new();
+        let entries = std::fs::read_dir(Path::new(&config.directory)).into_iter().flatten().flatten();
+        for entry in entries {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let plan = std::fs::read_to_string(&path)
+                            .map_err(|e| e.to_string())
+                            .and_then(|content| serde_json::from_str::<TrainingPlan>(&content).map_err(|e| e.to_string()));
+            match plan {
+                Ok(plan) => plans.entry(plan.major.trim().to_owned()).or_default().push(plan),
+                Err(_e) => {
+                    #[cfg(debug_assertions)]
+                    println!("Unable to load the training plan {}: {}", path.display(), _e);
+                }
+            }
+        }
+        for plans in plans.values_mut() {
+            plans.sort_by_key(|plan| cohort_year(&plan.year));
+        }
+        TrainingPlans { plans: Arc::new(plans) }
+    }
+
+    /// The plan of a major for a cohort, or the latest plan before it when
+    /// the cohort has none.
+    pub fn find(&self, major: &str, year: &str) -> Option<&TrainingPlan> {
+        let year = cohort_year(year);
+        self.plans
+            .get(major.trim())?
+            .iter()
+            .rev()
+            .find(|plan| match (cohort_year(&plan.year), year) {
+                (Some(plan_year), Some(year)) => plan_year <= year,
+                _ => false
+            })
+    }
+
+    /// The majors and cohort years with a plan.
+    pub fn list(&self) -> Vec<TrainingPlanKey> {
+        let mut keys: Vec<TrainingPlanKey> = self.plans
+                                                .values()
+                                                .flatten()
+                                                .map(|plan| TrainingPlanKey { major: plan.major.clone(), year: plan.year.clone() })
+                                                .collect();
+        keys.sort_by(|a, b| a.major.cmp(&b.major).then_with(|| a.year.cmp(&b.year)));
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::course_grade;
+    use super::{audit, TrainingConfig, TrainingPlan, TrainingPlans};
+
+    #[test]
+    fn test_audit() {
+        let plan: TrainingPlan = serde_json::from_str(r#"{
+            "major": "计算机科学与技术",
+            "year": "2021",
+            "total_credits": 20,
+            "groups": [
+                { "name": "数学基础", "category": "通识必修", "required": ["MA101A|MA101B", "MA103A"] },
+                { "name": "专业核心", "category": "专业必修", "required": ["CS203", "CS205"] },
+                { "name": "通识选修", "min_credits": 4, "elective_prefixes": ["GE"], "course_types": ["通识选修"] },
+                { "name": "自由选修", "min_credits": 2 }
+            ]
+        }"#).unwrap();
+        let grades = vec![
+            course_grade("MA101B", 4.0, "2021-2022-1", "85", "", "通识必修"),
+            course_grade("CS203", 3.0, "2021-2022-1", "55", "", "专业必修"),
+            course_grade("CS203", 3.0, "2021-2022-1", "72", "", "专业必修"),
+            course_grade("GE131", 2.0, "2021-2022-1", "90", "", "通识选修"),
+            course_grade("HUM001", 2.0, "2021-2022-1", "88", "", "通识选修"),
+            course_grade("SS007", 1.0, "2021-2022-1", "92", "", "通识选修"),
+            course_grade("PE101", 1.0, "2021-2022-1", "80", "", "体育"),
+        ];
+        let report = audit(&plan, &grades);
+        assert_eq!(report.earned_credits, 13.0);
+        assert_eq!(report.remaining_credits, 7.0);
+        assert!(!report.satisfied);
+        assert_eq!(report.groups[0].courses, vec!["MA101B"]);
+        assert_eq!(report.groups[0].missing_required, vec!["MA103A"]);
+        assert_eq!(report.groups[1].missing_required, vec!["CS205"]);
+        assert_eq!(report.groups[2].courses, vec!["GE131", "HUM001"]);
+        assert!(report.groups[2].satisfied);
+        assert_eq!(report.groups[3].courses, vec!["SS007", "PE101"]);
+        assert!(report.groups[3].satisfied);
+        assert_eq!(report.courses_needed, vec!["MA103A", "CS205"]);
+        assert_eq!(report.remaining_by_category.get("通识选修"), Some(&0.0));
+        assert!(report.unassigned.is_empty());
+    }
+    #[test]
+    fn test_load_sample_plan() {
+        let plans = TrainingPlans::load(&TrainingConfig::default());
+        let plan = plans.find("示例专业", "2023级").unwrap();
+        assert_eq!(plan.year, "2021");
+        assert_eq!(plan.groups.len(), 6);
+        assert!(plans.find("示例专业", "2020").is_none());
+    }
+}
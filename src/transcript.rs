@@ -0,0 +1,390 @@
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use rocket::serde::Deserialize;
+
+use super::grades::{grade_history, grade_point, mark_attempts, semester_key, RetakePolicy};
+use super::prerequisites::is_passed;
+use super::structures::*;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TranscriptConfig {
+    /// TrueType font embedded in the PDF, which has to cover Chinese, such
+    /// as Noto Sans SC. The whole font is embedded, a small one keeps the
+    /// files small.
+    pub font_file: String,
+}
+
+impl Default for TranscriptConfig {
+    fn default() -> Self {
+        TranscriptConfig { font_file: "fonts/NotoSansSC-Regular.ttf".to_owned() }
+    }
+}
+
+impl TranscriptConfig {
+    /// Why PDF transcripts cannot be made, if the server is built without
+    /// the `pdf` feature or the font is missing.
+    pub fn check_pdf(&self) -> Result<(), String> {
+        if !cfg!(feature = "pdf") {
+            return Err("PDF output is not enabled on this server, build it with the pdf feature".to_owned());
+        }
+        if !Path::new(&self.font_file).is_file() {
+            return Err(format!("Unable to find the font {} of the PDF transcripts, set transcript.font_file to a TrueType font covering Chinese", self.font_file));
+        }
+        Ok(())
+    }
+
+    /// PDF when it can be made, otherwise CSV.
+    pub fn default_format(&self) -> &'static str {
+        if self.check_pdf().is_ok() { "pdf" } else { "csv" }
+    }
+}
+
+/// The GPA of some attempts, from their grade points weighted by credits.
+fn computed_gpa(grades: &[&CourseGrade]) -> Option<f64> {
+    let graded: Vec<(f64, f64)> = grades.iter()
+                                    .filter(|grade| grade.counted)
+                                    .filter_map(|grade| Some((grade.credit, grade_point(grade)?)))
+                                    .collect();
+    let credits: f64 = graded.iter().map(|(credits, _)| credits).sum();
+    if credits > 0.0 {
+        Some(graded.iter().map(|(credits, point)| credits * point).sum::<f64>() / credits)
+    } else {
+        None
+    }
+}
+
+/// Group the grades by semester, in the order taken, with the GPA of TIS
+/// for each semester along with the one computed from the grades.
+pub fn build_transcript(info: BasicInfo, student_gpa: &StudentGPA, grades: &[CourseGrade], policy: RetakePolicy, generated_at: NaiveDateTime) -> Transcript {
+    let mut grades = grades.to_vec();
+    mark_attempts(&mut grades, policy);
+    let history = grade_history(&grades, policy);
+
+    let mut names: Vec<&str> = Vec::new();
+    for grade in grades.iter() {
+        if !names.contains(&grade.semester.as_str()) {
+            names.push(&grade.semester);
+        }
+    }
+    names.sort_by_key(|semester| semester_key(semester));
+
+    let semesters = names.iter()
+                        .map(|semester| {
+                            let mut courses: Vec<&CourseGrade> = grades.iter().filter(|grade| grade.semester == *semester).collect();
+                            courses.sort_by(|a, b| a.code.cmp(&b.code));
+                            let key = semester_key(semester);
+                            let official = student_gpa.all_gpa
+                                                .iter()
+                                                .find(|gpa| semester_key(&format!("{}-{}", gpa.semester_year, gpa.semester_number)) == key
+                                                            || gpa.semester_full_name == *semester)
+                                                .and_then(|gpa| gpa.gpa);
+                            let counted = courses.iter().filter(|grade| grade.counted);
+                            TranscriptSemester {
+                                semester: semester.to_string(),
+                                attempted_credits: counted.clone().map(|grade| grade.credit).sum(),
+                                earned_credits: counted.filter(|grade| is_passed(grade)).map(|grade| grade.credit).sum(),
+                                gpa: official,
+                                computed_gpa: computed_gpa(&courses),
+                                courses: courses.into_iter().cloned().collect(),
+                            }
+                        })
+                        .collect();
+
+    Transcript {
+        info,
+        retake_policy: history.retake_policy,
+        semesters,
+        attempted_credits: history.attempted_credits,
+        earned_credits: history.earned_credits,
+        gpa: student_gpa.average_gpa,
+        computed_gpa: history.gpa,
+        rank: student_gpa.rank.clone(),
+        generated_at: generated_at.format("%Y-%m-%d %H:%M").to_string(),
+    }
+}
+
+fn format_gpa(gpa: Option<f64>) -> String {
+    gpa.map(|gpa| format!("{:.2}", gpa)).unwrap_or_default()
+}
+
+/// One row per attempt, with a byte order mark so that spreadsheets read
+/// the Chinese as UTF-8.
+pub fn transcript_csv(transcript: &Transcript) -> Result<Vec<u8>, String> {
+    let mut writer = csv::Writer::from_writer(b"\xEF\xBB\xBF".to_vec());
+    writer.write_record([
+        "semester", "code", "name", "course_type", "credit", "final_grade", "final_level",
+        "grade_point", "retake", "attempt", "counted", "excluded_reason", "semester_gpa",
+    ]).map_err(|e| e.to_string())?;
+    for semester in transcript.semesters.iter() {
+        for grade in semester.courses.iter() {
+            writer.write_record([
+                semester.semester.clone(),
+                grade.code.clone(),
+                grade.name.clone(),
+                grade.course_type.clone(),
+                grade.credit.to_string(),
+                grade.final_grade.clone(),
+                grade.final_level.clone(),
+                format_gpa(grade_point(grade)),
+                grade.retake.to_string(),
+                format!("{}/{}", grade.attempt, grade.attempts),
+                grade.counted.to_string(),
+                grade.excluded_reason.clone().unwrap_or_default(),
+                format_gpa(semester.gpa.or(semester.computed_gpa)),
+            ]).map_err(|e| e.to_string())?;
+        }
+    }
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "pdf")]
+mod pdf {
+    use printpdf::{IndirectFontRef, Line, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference, Point};
+
+    use super::super::structures::*;
+    use super::{format_gpa, grade_point, TranscriptConfig};
+
+    const PAGE_WIDTH: f32 = 210.0;
+    const PAGE_HEIGHT: f32 = 297.0;
+    const MARGIN: f32 = 15.0;
+    const ROW_HEIGHT: f32 = 5.5;
+    const FONT_SIZE: f32 = 9.0;
+    /// Left edges of the columns, in millimetres.
+    const COLUMNS: [(f32, &str); 8] = [
+        (MARGIN, "Code 课程代码"), (40.0, "Course 课程名称"), (108.0, "Type 类别"), (134.0, "Credits 学分"),
+        (152.0, "Score 成绩"), (166.0, "Level 等级"), (180.0, "GP 绩点"), (PAGE_WIDTH - MARGIN, ""),
+    ];
+
+    /// Rough width of a character in millimetres, Chinese ones being a full
+    /// em wide.
+    fn char_width(c: char, font_size: f32) -> f32 {
+        let em = font_size * 25.4 / 72.0;
+        if c.is_ascii() { em * 0.55 } else { em }
+    }
+
+    fn fit(text: &str, width: f32, font_size: f32) -> String {
+        let mut used = 0.0;
+        let mut fitted = String::new();
+        for c in text.chars() {
+            used += char_width(c, font_size);
+            if used > width - char_width('…', font_size) {
+                fitted.push('…');
+                return fitted;
+            }
+            fitted.push(c);
+        }
+        fitted
+    }
+
+    struct Writer {
+        document: PdfDocumentReference,
+        font: IndirectFontRef,
+        layer: PdfLayerReference,
+        page: usize,
+        y: f32,
+    }
+
+    impl Writer {
+        fn text(&self, text: &str, font_size: f32, x: f32) {
+            self.layer.use_text(text, font_size, Mm(x), Mm(self.y), &self.font);
+        }
+
+        fn rule(&self, thickness: f32) {
+            self.layer.set_outline_thickness(thickness);
+            let y = self.y + ROW_HEIGHT - 4.0;
+            self.layer.add_line(Line {
+                points: vec![(Point::new(Mm(MARGIN), Mm(y)), false), (Point::new(Mm(PAGE_WIDTH - MARGIN), Mm(y)), false)],
+                is_closed: false,
+            });
+        }
+
+        fn footer(&self, generated_at: &str) {
+            let footer = format!("Unofficial transcript 非官方成绩单 · Generated {} · Page {}", generated_at, self.page);
+            self.layer.use_text(footer, 7.0, Mm(MARGIN), Mm(MARGIN - 5.0), &self.font);
+        }
+
+        /// Start a new page unless `height` fits on this one.
+        fn room(&mut self, height: f32, generated_at: &str) {
+            if self.y - height >= MARGIN {
+                return;
+            }
+            let (page, layer) = self.document.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Transcript");
+            self.layer = self.document.get_page(page).get_layer(layer);
+            self.page += 1;
+            self.y = PAGE_HEIGHT - MARGIN - 5.0;
+            self.footer(generated_at);
+        }
+
+        fn header_row(&mut self) {
+            for (x, title) in COLUMNS.iter() {
+                self.text(title, FONT_SIZE - 1.0, *x);
+            }
+            self.rule(0.5);
+            self.y -= ROW_HEIGHT;
+        }
+
+        fn grade_row(&mut self, grade: &CourseGrade) {
+            let note = if !grade.counted {
+                "Not counted 不计入"
+            } else if grade.retake {
+                "Retake 重修"
+            } else {
+                ""
+            };
+            let name = if note.is_empty() { grade.name.clone() } else { format!("{} ({})", grade.name, note) };
+            let cells = [
+                grade.code.clone(),
+                name,
+                grade.course_type.clone(),
+                grade.credit.to_string(),
+                grade.final_grade.clone(),
+                grade.final_level.clone(),
+                format_gpa(grade_point(grade)),
+            ];
+            for (i, cell) in cells.iter().enumerate() {
+                let width = COLUMNS[i + 1].0 - COLUMNS[i].0 - 1.5;
+                self.text(&fit(cell, width, FONT_SIZE), FONT_SIZE, COLUMNS[i].0);
+            }
+            self.y -= ROW_HEIGHT;
+        }
+    }
+
+    /// Lay the transcript out on A4 pages, in the font of `config`.
+    pub fn transcript_pdf(transcript: &Transcript, config: &TranscriptConfig) -> Result<Vec<u8>, String> {
+        let info = &transcript.info;
+        let title = format!("Unofficial Transcript of {} {}", info.name, info.sid);
+        let (document, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Transcript");
+        let font_file = std::fs::File::open(&config.font_file)
+                            .map_err(|e| format!("Unable to open the font {}: {}", config.font_file, e))?;
+        let font = document.add_external_font(font_file)
+                        .map_err(|e| format!("Unable to load the font {}: {}", config.font_file, e))?;
+        let layer = document.get_page(page).get_layer(layer);
+        let mut writer = Writer { document, font, layer, page: 1, y: PAGE_HEIGHT - MARGIN - 5.0 };
+        let generated_at = transcript.generated_at.as_str();
+        writer.footer(generated_at);
+
+        writer.text("Unofficial Transcript 非官方成绩单", 16.0, MARGIN);
+        writer.y -= 10.0;
+        let lines = [
+            format!("Name 姓名: {}    Student ID 学号: {}", info.name, info.sid),
+            format!("Department 院系: {}    Major 专业: {}    Cohort 年级: {}", info.department, info.major, info.year),
+            format!(
+                "GPA: {:.2} (computed 计算 {})    Rank 排名: {}    Credits 学分: {} earned 获得 / {} attempted 修读",
+                transcript.gpa, format_gpa(transcript.computed_gpa), transcript.rank, transcript.earned_credits, transcript.attempted_credits
+            ),
+            format!("Retakes 重修: the {} attempt of each course counts 每门课程计入一次修读", transcript.retake_policy),
+        ];
+        for line in lines.iter() {
+            writer.text(line, FONT_SIZE + 1.0, MARGIN);
+            writer.y -= ROW_HEIGHT + 0.5;
+        }
+
+        for semester in transcript.semesters.iter() {
+            // A semester starts on a new page unless its heading and first rows fit.
+            writer.room(ROW_HEIGHT * 5.0 + 4.0, generated_at);
+            writer.y -= 4.0;
+            writer.text(&semester.semester, FONT_SIZE + 2.0, MARGIN);
+            writer.y -= ROW_HEIGHT + 1.0;
+            writer.header_row();
+            for grade in semester.courses.iter() {
+                if writer.y - ROW_HEIGHT < MARGIN {
+                    writer.room(ROW_HEIGHT * 2.0, generated_at);
+                    writer.header_row();
+                }
+                writer.grade_row(grade);
+            }
+            writer.room(ROW_HEIGHT, generated_at);
+            writer.rule(0.3);
+            let summary = format!(
+                "Credits 学分: {} earned 获得 / {} attempted 修读    GPA: {} (computed 计算 {})",
+                semester.earned_credits, semester.attempted_credits, format_gpa(semester.gpa), format_gpa(semester.computed_gpa)
+            );
+            writer.text(&summary, FONT_SIZE, COLUMNS[1].0);
+            writer.y -= ROW_HEIGHT;
+        }
+
+        writer.room(ROW_HEIGHT * 2.0, generated_at);
+        writer.y -= 4.0;
+        writer.rule(0.5);
+        let totals = format!(
+            "Total 合计    Credits 学分: {} earned 获得 / {} attempted 修读    GPA: {:.2} (computed 计算 {})",
+            transcript.earned_credits, transcript.attempted_credits, transcript.gpa, format_gpa(transcript.computed_gpa)
+        );
+        writer.text(&totals, FONT_SIZE + 1.0, MARGIN);
+
+        writer.document.save_to_bytes().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "pdf")]
+pub use pdf::transcript_pdf;
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::super::grades::RetakePolicy;
+    use super::super::structures::*;
+    use super::super::test_util::course_grade;
+    use super::{build_transcript, transcript_csv, TranscriptConfig};
+
+    #[test]
+    fn test_build_transcript() {
+        let info = BasicInfo {
+            id: "1".to_owned(),
+            sid: "12010000".to_owned(),
+            name: "张三".to_owned(),
+            email: String::new(),
+            year: "2020".to_owned(),
+            department: "计算机科学与工程系".to_owned(),
+            major: "计算机科学与技术".to_owned(),
+        };
+        let student_gpa = StudentGPA {
+            all_gpa: vec![SemesterGPA {
+                semester_full_name: "2020-2021学年第1学期".to_owned(),
+                semester_year: "2020-2021".to_owned(),
+                semester_number: "1".to_owned(),
+                gpa: Some(3.5),
+            }],
+            average_gpa: 3.6,
+            rank: "10/100".to_owned(),
+        };
+        let grades = vec![
+            course_grade("CS102", 3.0, "2020-2021学年第2学期", "92", "A-", "专业必修"),
+            course_grade("MA101B", 4.0, "2020-2021学年第1学期", "55", "F", "专业必修"),
+            course_grade("CS101", 3.0, "2020-2021学年第1学期", "85", "B", "专业必修"),
+            course_grade("MA101B", 4.0, "2020-2021学年第2学期", "80", "B-", "专业必修"),
+        ];
+        let generated_at = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(9, 30, 0).unwrap();
+        let transcript = build_transcript(info, &student_gpa, &grades, RetakePolicy::Latest, generated_at);
+
+        assert_eq!(transcript.semesters.len(), 2);
+        let first = &transcript.semesters[0];
+        assert_eq!(first.semester, "2020-2021学年第1学期");
+        assert_eq!(first.courses.iter().map(|grade| grade.code.as_str()).collect::<Vec<_>>(), vec!["CS101", "MA101B"]);
+        assert_eq!((first.attempted_credits, first.earned_credits), (3.0, 3.0));
+        assert_eq!(first.gpa, Some(3.5));
+        assert!((first.computed_gpa.unwrap() - 3.55).abs() < 1e-9);
+        let second = &transcript.semesters[1];
+        assert_eq!(second.gpa, None);
+        assert!((second.computed_gpa.unwrap() - (3.85 * 3.0 + 3.32 * 4.0) / 7.0).abs() < 1e-9);
+        assert_eq!((transcript.attempted_credits, transcript.earned_credits), (10.0, 10.0));
+        assert_eq!(transcript.generated_at, "2023-01-01 09:30");
+
+        let csv = String::from_utf8(transcript_csv(&transcript).unwrap()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("\u{feff}semester,code,name"));
+        assert_eq!(lines[2], "2020-2021学年第1学期,MA101B,MA101B,专业必修,4,55,F,0.00,false,1/2,false,\"The latest attempt, of 2020-2021学年第2学期, counts instead\",3.50");
+    }
+    #[test]
+    fn test_default_format() {
+        let missing = TranscriptConfig { font_file: "fonts/missing.ttf".to_owned() };
+        assert!(missing.check_pdf().is_err());
+        assert_eq!(missing.default_format(), "csv");
+        let present = TranscriptConfig { font_file: "Cargo.toml".to_owned() };
+        assert_eq!(present.default_format(), if cfg!(feature = "pdf") { "pdf" } else { "csv" });
+    }
+}
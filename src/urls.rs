@@ -1,10 +1,12 @@
 pub const USER_AGENT: &'static str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:89.0) Gecko/20100101 Firefox/89.0";
 pub const LOGIN_URL: &'static str = "https://cas.sustech.edu.cn/cas/login";
+pub const TIS_URL: &'static str = "https://tis.sustech.edu.cn/";
 pub const TIS_CAS_URL: &'static str = "https://cas.sustech.edu.cn/cas/login?service=https://tis.sustech.edu.cn/cas";
 pub const BASIC_INFO_URL: &'static str = "https://tis.sustech.edu.cn/UserManager/queryxsxx";
 pub const SEMESTER_GPA_URL: &'static str = "https://tis.sustech.edu.cn/cjgl/xscjgl/xsgrcjcx/queryXnAndXqXfj";
 pub const COURSE_GRADES_URL: &'static str = "https://tis.sustech.edu.cn/cjgl/grcjcx/grcjcx";
 pub const COURSES_URL: &'static str = "https://course-tao.sustech.edu.cn/kcxxweb/KcxxwebChinesePC";
+pub const ENGLISH_COURSES_URL: &'static str = "https://course-tao.sustech.edu.cn/kcxxweb/KcxxwebEnglishPC";
 pub const SELECTED_COURSES_URL: &'static str = "https://tis.sustech.edu.cn/Xsxk/queryYxkc";
 pub const AVAILABLE_COURSES_URL: &'static str = "https://tis.sustech.edu.cn/Xsxk/queryKxrw";
 pub const SELECT_COURSE_URL: &'static str = "https://tis.sustech.edu.cn/Xsxk/addGouwuche"; // WTF???? 购物车？？？
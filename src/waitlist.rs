@@ -0,0 +1,864 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::lock::Mutex;
+use rocket::serde::Deserialize;
+use rocket::tokio;
+
+use super::courses::{fetch_available_courses, fetch_current_semester, fetch_selected_courses, FetchError};
+use super::selection::{course_type_code, is_accepted, response_message, submit_drop_course, submit_select_course};
+use super::structures::*;
+use super::throttle::Throttle;
+
+const MIN_POLL_INTERVAL: u64 = 15;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WaitlistConfig {
+    /// Seconds between two rounds over all the waitlists.
+    pub interval: u64,
+}
+
+impl Default for WaitlistConfig {
+    fn default() -> Self {
+        WaitlistConfig { interval: 30 }
+    }
+}
+
+/// What TIS currently says about one option of a waitlist.
+pub struct Observation {
+    pub capacity: Capacity,
+    /// The `ctkcxx` of the class, if it clashes with a selected class.
+    pub conflict: Option<String>,
+    pub conflicts: Vec<ConflictReference>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    Wait,
+    Satisfied,
+    /// Select the option, then drop the held one if there is any.
+    Select { option: usize, drop: Option<usize> },
+    /// The option only clashes with the held fallback, so the fallback has
+    /// to be dropped before the option can be selected.
+    Swap { option: usize, fallback: usize },
+}
+
+/// Whether a class TIS says clashes is the given course, by its code, or by
+/// its whole name when TIS leaves the code out.
+fn is_course(reference: &ConflictReference, course: &Course) -> bool {
+    match &reference.course_id {
+        Some(course_id) => course_id.eq_ignore_ascii_case(course.course_id.trim()),
+        None => reference.course_name == course.course_name.trim()
+    }
+}
+
+/// Decide what to do for a waitlist, given the option currently held (its
+/// index and course) and what TIS says about the options.
+pub fn next_step(
+    list: &Waitlist,
+    observed: &HashMap<String, Observation>,
+    held: Option<usize>,
+    held_course: Option<&Course>,
+) -> Step {
+    if held == Some(0) {
+        return Step::Satisfied;
+    }
+    let limit = held.unwrap_or(list.options.len());
+    for (i, option) in list.options.iter().enumerate().take(limit) {
+        let observation = match observed.get(&option.class_id) {
+            Some(observation) => observation,
+            None => continue
+        };
+        if observation.capacity.is_full() {
+            continue;
+        }
+        match (&observation.conflict, held, held_course) {
+            (None, _, _) => {
+                return Step::Select { option: i, drop: if list.drop_fallback { held } else { None } };
+            },
+            (Some(_), Some(fallback), Some(course)) if list.drop_fallback
+                && !observation.conflicts.is_empty()
+                && observation.conflicts.iter().all(|reference| is_course(reference, course)) => {
+                return Step::Swap { option: i, fallback };
+            },
+            _ => continue
+        }
+    }
+    Step::Wait
+}
+
+fn is_active(list: &Waitlist) -> bool {
+    matches!(list.status, WaitlistStatus::Waiting | WaitlistStatus::Holding | WaitlistStatus::Paused)
+}
+
+/// The requests the waitlists send to TIS, so that a round can be run
+/// against a fake TIS in the tests.
+pub trait Upstream: Send + Sync {
+    fn current_semester<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+    ) -> BoxFuture<'a, Result<CurrentSemester, FetchError>>;
+
+    fn selected_courses<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        list: &'a Waitlist,
+    ) -> BoxFuture<'a, Result<Vec<SelectedCourse>, FetchError>>;
+
+    fn available_courses<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        list: &'a Waitlist,
+        course_type: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<AvailableCourse>, FetchError>>;
+
+    fn select_course<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        list: &'a Waitlist,
+        option: &'a WaitlistOption,
+    ) -> BoxFuture<'a, Result<serde_json::Value, FetchError>>;
+
+    fn drop_course<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        list: &'a Waitlist,
+        option: &'a WaitlistOption,
+    ) -> BoxFuture<'a, Result<serde_json::Value, FetchError>>;
+}
+
+/// The real TIS.
+pub struct Tis;
+
+impl Upstream for Tis {
+    fn current_semester<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+    ) -> BoxFuture<'a, Result<CurrentSemester, FetchError>> {
+        Box::pin(fetch_current_semester(client))
+    }
+
+    fn selected_courses<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        list: &'a Waitlist,
+    ) -> BoxFuture<'a, Result<Vec<SelectedCourse>, FetchError>> {
+        Box::pin(fetch_selected_courses(client, &list.semester_year, &list.semester_no))
+    }
+
+    fn available_courses<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        list: &'a Waitlist,
+        course_type: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<AvailableCourse>, FetchError>> {
+        Box::pin(fetch_available_courses(client, &list.semester_year, &list.semester_no, course_type))
+    }
+
+    fn select_course<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        list: &'a Waitlist,
+        option: &'a WaitlistOption,
+    ) -> BoxFuture<'a, Result<serde_json::Value, FetchError>> {
+        Box::pin(async move {
+            submit_select_course(
+                client,
+                &list.semester_year,
+                &list.semester_no,
+                &option.class_id,
+                &option.course_type,
+                &option.points.to_string()
+            ).await
+        })
+    }
+
+    fn drop_course<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        list: &'a Waitlist,
+        option: &'a WaitlistOption,
+    ) -> BoxFuture<'a, Result<serde_json::Value, FetchError>> {
+        Box::pin(submit_drop_course(client, &list.semester_year, &list.semester_no, &option.class_id))
+    }
+}
+
+struct UserWaitlists {
+    client: reqwest::Client,
+    lists: Vec<Waitlist>,
+    audit: Vec<AuditEntry>,
+}
+
+struct WaitlistsInner {
+    interval: Duration,
+    throttle: Arc<Throttle>,
+    upstream: Arc<dyn Upstream>,
+    users: Mutex<HashMap<String, UserWaitlists>>,
+    next_id: AtomicU64,
+    running: AtomicBool,
+}
+
+/// Per-user ordered wish lists for the current selection round. Like the
+/// seat watcher it only keeps the logged in client of the user, and every
+/// action it takes on behalf of the student goes to the audit trail.
+#[derive(Clone)]
+pub struct Waitlists {
+    inner: Arc<WaitlistsInner>,
+}
+
+impl Waitlists {
+    pub fn new(config: &WaitlistConfig, throttle: Arc<Throttle>) -> Self {
+        Waitlists::with_upstream(config, throttle, Arc::new(Tis))
+    }
+
+    pub fn with_upstream(config: &WaitlistConfig, throttle: Arc<Throttle>, upstream: Arc<dyn Upstream>) -> Self {
+        Waitlists {
+            inner: Arc::new(WaitlistsInner {
+                interval: Duration::from_secs(config.interval.max(MIN_POLL_INTERVAL)),
+                throttle,
+                upstream,
+                users: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(1),
+                running: AtomicBool::new(false),
+            })
+        }
+    }
+
+    pub fn start(&self) {
+        if self.inner.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let waitlists = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(waitlists.inner.interval).await;
+                waitlists.tick().await;
+            }
+        });
+    }
+
+    pub async fn add(
+        &self,
+        username: &str,
+        client: reqwest::Client,
+        current: &CurrentSemester,
+        request: &WaitlistRequest,
+    ) -> Result<Waitlist, String> {
+        if request.options.is_empty() {
+            return Err("The waitlist does not contain any option".to_owned());
+        }
+        if let Some(option) = request.options.iter().find(|o| course_type_code(&o.course_type).is_none()) {
+            return Err(format!("Unknown course type {} for class {}", option.course_type, option.class_id));
+        }
+        if request.semester_year != current.semester_year || request.semester_no != current.semester_no {
+            return Err(format!("The selection period of {}-{} is not open, the current one is {}-{}",
+                                request.semester_year, request.semester_no,
+                                current.semester_year, current.semester_no));
+        }
+
+        let list = Waitlist {
+            id: self.inner.next_id.fetch_add(1, Ordering::SeqCst),
+            semester_year: request.semester_year.clone(),
+            semester_no: request.semester_no.clone(),
+            options: request.options.clone(),
+            drop_fallback: request.drop_fallback,
+            status: WaitlistStatus::Waiting,
+            held: None,
+            reason: None,
+        };
+        self.refresh(username, client).await;
+        let mut users = self.inner.users.lock().await;
+        let user = users.get_mut(username).unwrap();
+        user.lists.push(list.clone());
+        user.audit.push(audit_entry(list.id, AuditAction::Registered, None, None));
+        Ok(list)
+    }
+
+    /// Replace the client of a user after a new login, resuming the
+    /// waitlists stopped by an expired session.
+    pub async fn refresh(
+        &self,
+        username: &str,
+        client: reqwest::Client,
+    ) {
+        let mut users = self.inner.users.lock().await;
+        let user = users.entry(username.to_owned())
+                        .or_insert_with(|| UserWaitlists { client: client.clone(), lists: Vec::new(), audit: Vec::new() });
+        user.client = client;
+        for list in user.lists.iter_mut().filter(|l| l.status == WaitlistStatus::SessionExpired) {
+            list.status = WaitlistStatus::Waiting;
+            list.reason = None;
+            user.audit.push(audit_entry(list.id, AuditAction::Resumed, None, None));
+        }
+    }
+
+    pub async fn lists(
+        &self,
+        username: &str,
+    ) -> Vec<Waitlist> {
+        let users = self.inner.users.lock().await;
+        users.get(username)
+                .map(|user| user.lists.clone())
+                .unwrap_or_default()
+    }
+
+    pub async fn audit(
+        &self,
+        username: &str,
+    ) -> Vec<AuditEntry> {
+        let users = self.inner.users.lock().await;
+        users.get(username)
+                .map(|user| user.audit.clone())
+                .unwrap_or_default()
+    }
+
+    pub async fn cancel(
+        &self,
+        username: &str,
+        waitlist_id: u64,
+    ) -> Option<Waitlist> {
+        let mut users = self.inner.users.lock().await;
+        let user = users.get_mut(username)?;
+        let list = user.lists.iter_mut().find(|l| l.id == waitlist_id)?;
+        if is_active(list) {
+            list.status = WaitlistStatus::Cancelled;
+            user.audit.push(audit_entry(waitlist_id, AuditAction::Cancelled, None, None));
+        }
+        Some(list.clone())
+    }
+
+    async fn tick(&self) {
+        let users: Vec<(String, reqwest::Client)> = {
+            let users = self.inner.users.lock().await;
+            users.iter()
+                    .filter(|(_, user)| user.lists.iter().any(is_active))
+                    .map(|(username, user)| (username.clone(), user.client.clone()))
+                    .collect()
+        };
+        for (username, client) in users {
+            match self.run_user(&username, &client).await {
+                Ok(()) => {},
+                Err(FetchError::SessionExpired) => {
+                    #[cfg(debug_assertions)]
+                    println!("Waitlist: the session of {} expired", username);
+                    let message = Some(FetchError::SessionExpired.message());
+                    let mut users = self.inner.users.lock().await;
+                    if let Some(user) = users.get_mut(&username) {
+                        for list in user.lists.iter_mut().filter(|l| is_active(l)) {
+                            list.status = WaitlistStatus::SessionExpired;
+                            list.reason = message.clone();
+                            user.audit.push(audit_entry(list.id, AuditAction::SessionExpired, None, message.clone()));
+                        }
+                    }
+                },
+                Err(_e) => {
+                    // A timeout or an unexpected response, the lists are
+                    // checked again next round.
+                    #[cfg(debug_assertions)]
+                    println!("Waitlist: checking the waitlists of {} failed: {:?}", username, _e);
+                }
+            }
+        }
+    }
+
+    /// Whether a waitlist is still active. A round works on a copy of the
+    /// lists, so this is checked again before every request to TIS in case
+    /// the student cancelled the list meanwhile.
+    async fn still_active(
+        &self,
+        username: &str,
+        waitlist_id: u64,
+    ) -> bool {
+        let users = self.inner.users.lock().await;
+        users.get(username)
+                .and_then(|user| user.lists.iter().find(|l| l.id == waitlist_id))
+                .map(is_active)
+                .unwrap_or(false)
+    }
+
+    async fn run_user(
+        &self,
+        username: &str,
+        client: &reqwest::Client,
+    ) -> Result<(), FetchError> {
+        self.inner.throttle.wait().await;
+        let current = self.inner.upstream.current_semester(client).await?;
+
+        let lists: Vec<Waitlist> = {
+            let users = self.inner.users.lock().await;
+            users.get(username)
+                    .map(|user| user.lists.iter().filter(|l| is_active(l)).cloned().collect())
+                    .unwrap_or_default()
+        };
+        for list in lists {
+            if list.semester_year != current.semester_year || list.semester_no != current.semester_no {
+                if list.status != WaitlistStatus::Paused {
+                    let reason = format!("The selection period of {}-{} is closed", list.semester_year, list.semester_no);
+                    self.update(username, list.id, AuditAction::Paused, None, Some(reason.clone()), |l| {
+                        if is_active(l) {
+                            l.status = WaitlistStatus::Paused;
+                            l.reason = Some(reason);
+                        }
+                    }).await;
+                }
+                continue;
+            }
+            if list.status == WaitlistStatus::Paused {
+                self.update(username, list.id, AuditAction::Resumed, None, None, |l| {
+                    if is_active(l) {
+                        l.status = WaitlistStatus::Waiting;
+                        l.reason = None;
+                    }
+                }).await;
+            }
+            self.run_list(username, client, &list).await?;
+        }
+        Ok(())
+    }
+
+    async fn run_list(
+        &self,
+        username: &str,
+        client: &reqwest::Client,
+        list: &Waitlist,
+    ) -> Result<(), FetchError> {
+        let upstream = &self.inner.upstream;
+        if !self.still_active(username, list.id).await {
+            return Ok(());
+        }
+        self.inner.throttle.wait().await;
+        let selected = upstream.selected_courses(client, list).await?;
+        let held = list.options
+                        .iter()
+                        .position(|o| selected.iter().any(|s| s.advanced_course.id == o.class_id));
+        let held_course = held.and_then(|i| {
+            selected.iter()
+                    .find(|s| s.advanced_course.id == list.options[i].class_id)
+                    .map(|s| s.advanced_course.basic_course.clone())
+        });
+
+        let mut observed = HashMap::<String, Observation>::new();
+        let mut course_types = Vec::<&str>::new();
+        for option in &list.options {
+            if !course_types.contains(&&option.course_type[..]) {
+                course_types.push(&option.course_type);
+            }
+        }
+        for course_type in course_types {
+            if !self.still_active(username, list.id).await {
+                return Ok(());
+            }
+            self.inner.throttle.wait().await;
+            let courses = upstream.available_courses(client, list, course_type).await?;
+            for course in courses {
+                if list.options.iter().any(|o| o.class_id == course.advanced_course.id) {
+                    observed.insert(course.advanced_course.id.clone(), Observation {
+                        capacity: Capacity::of(&course.advanced_course),
+                        conflict: course.conflict_courses.filter(|c| !c.trim().is_empty()),
+                        conflicts: course.conflicts,
+                    });
+                }
+            }
+        }
+
+        if !self.still_active(username, list.id).await {
+            return Ok(());
+        }
+        match next_step(list, &observed, held, held_course.as_ref()) {
+            Step::Wait => {
+                let held_id = held.map(|i| list.options[i].class_id.clone());
+                let mut users = self.inner.users.lock().await;
+                if let Some(l) = users.get_mut(username).and_then(|u| u.lists.iter_mut().find(|l| l.id == list.id)) {
+                    if is_active(l) {
+                        l.status = if held_id.is_some() { WaitlistStatus::Holding } else { WaitlistStatus::Waiting };
+                        l.held = held_id;
+                    }
+                }
+            },
+            Step::Satisfied => {
+                let held_id = list.options[0].class_id.clone();
+                self.update(username, list.id, AuditAction::Satisfied, Some(&held_id), None, |l| {
+                    if is_active(l) {
+                        l.status = WaitlistStatus::Satisfied;
+                        l.held = Some(held_id.clone());
+                    }
+                }).await;
+            },
+            Step::Select { option, drop } => {
+                if self.select_option(username, client, list, option).await? {
+                    if let Some(drop) = drop {
+                        if self.still_active(username, list.id).await {
+                            self.drop_option(username, client, list, drop).await?;
+                        }
+                    }
+                }
+            },
+            Step::Swap { option, fallback } => {
+                if self.drop_option(username, client, list, fallback).await? {
+                    let swapped = self.still_active(username, list.id).await
+                                    && self.select_option(username, client, list, option).await?;
+                    if !swapped {
+                        // Take the fallback back even if the list was cancelled
+                        // meanwhile, the student held it before the swap.
+                        self.select_option(username, client, list, fallback).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Select one option of a waitlist, returning whether TIS accepted it.
+    async fn select_option(
+        &self,
+        username: &str,
+        client: &reqwest::Client,
+        list: &Waitlist,
+        option: usize,
+    ) -> Result<bool, FetchError> {
+        let target = &list.options[option];
+        self.inner.throttle.wait().await;
+        let (accepted, message) = match self.inner.upstream.select_course(client, list, target).await {
+            Ok(response) => (is_accepted(&response), response_message(&response)),
+            Err(FetchError::SessionExpired) => return Err(FetchError::SessionExpired),
+            Err(e) => (false, Some(e.message()))
+        };
+        if accepted {
+            self.update(username, list.id, AuditAction::Selected, Some(&target.class_id), message, |l| {
+                l.held = Some(target.class_id.clone());
+                if is_active(l) {
+                    l.status = if option == 0 { WaitlistStatus::Satisfied } else { WaitlistStatus::Holding };
+                }
+            }).await;
+        } else {
+            self.update(username, list.id, AuditAction::SelectRejected, Some(&target.class_id), message, |_| {}).await;
+        }
+        Ok(accepted)
+    }
+
+    /// Drop one option of a waitlist, returning whether TIS accepted it.
+    async fn drop_option(
+        &self,
+        username: &str,
+        client: &reqwest::Client,
+        list: &Waitlist,
+        option: usize,
+    ) -> Result<bool, FetchError> {
+        let target = &list.options[option];
+        self.inner.throttle.wait().await;
+        let (accepted, message) = match self.inner.upstream.drop_course(client, list, target).await {
+            Ok(response) => (is_accepted(&response), response_message(&response)),
+            Err(FetchError::SessionExpired) => return Err(FetchError::SessionExpired),
+            Err(e) => (false, Some(e.message()))
+        };
+        if accepted {
+            self.update(username, list.id, AuditAction::Dropped, Some(&target.class_id), message, |l| {
+                if l.held.as_deref() == Some(&target.class_id[..]) {
+                    l.held = None;
+                    if is_active(l) {
+                        l.status = WaitlistStatus::Waiting;
+                    }
+                }
+            }).await;
+        } else {
+            self.update(username, list.id, AuditAction::DropFailed, Some(&target.class_id), message, |_| {}).await;
+        }
+        Ok(accepted)
+    }
+
+    /// Apply `f` to a stored waitlist and record the action in the audit trail.
+    async fn update<F: FnOnce(&mut Waitlist)>(
+        &self,
+        username: &str,
+        waitlist_id: u64,
+        action: AuditAction,
+        class_id: Option<&str>,
+        message: Option<String>,
+        f: F,
+    ) {
+        let mut users = self.inner.users.lock().await;
+        if let Some(user) = users.get_mut(username) {
+            if let Some(list) = user.lists.iter_mut().find(|l| l.id == waitlist_id) {
+                f(list);
+            }
+            user.audit.push(audit_entry(waitlist_id, action, class_id, message));
+        }
+    }
+}
+
+fn audit_entry(
+    waitlist_id: u64,
+    action: AuditAction,
+    class_id: Option<&str>,
+    message: Option<String>,
+) -> AuditEntry {
+    AuditEntry {
+        time: chrono::Local::now().to_rfc3339(),
+        waitlist_id,
+        action,
+        class_id: class_id.map(|c| c.to_owned()),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use futures::future::BoxFuture;
+    use rocket::tokio;
+
+    use super::super::courses::FetchError;
+    use super::super::parser::parse_conflict_courses;
+    use super::super::structures::*;
+    use super::super::test_util::advanced_course;
+    use super::super::throttle::{Throttle, ThrottleConfig};
+    use super::{next_step, Observation, Step, Upstream, WaitlistConfig, Waitlists};
+
+    fn waitlist(drop_fallback: bool) -> Waitlist {
+        let option = |class_id: &str| WaitlistOption {
+            class_id: class_id.to_owned(),
+            course_type: "TP".to_owned(),
+            points: 10,
+        };
+        Waitlist {
+            id: 1,
+            semester_year: "2020-2021".to_owned(),
+            semester_no: "2".to_owned(),
+            options: vec![option("A"), option("B"), option("C")],
+            drop_fallback,
+            status: WaitlistStatus::Waiting,
+            held: None,
+            reason: None,
+        }
+    }
+
+    fn observe(available: u32, selected: u32, conflict: Option<&str>) -> Observation {
+        Observation {
+            capacity: Capacity { available, selected },
+            conflict: conflict.map(|c| c.to_owned()),
+            conflicts: conflict.map(parse_conflict_courses).unwrap_or_default(),
+        }
+    }
+
+    fn course(course_id: &str, course_name: &str) -> Course {
+        Course {
+            course_id: course_id.to_owned(),
+            course_name: course_name.to_owned(),
+            credits: 3.0,
+            department: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_next_step_picks_first_open_option() {
+        let list = waitlist(false);
+        let database = course("CS307", "数据库");
+        let mut observed = HashMap::new();
+        observed.insert("A".to_owned(), observe(30, 30, None));
+        observed.insert("B".to_owned(), observe(30, 10, Some("软件工程")));
+        observed.insert("C".to_owned(), observe(30, 10, None));
+        assert_eq!(next_step(&list, &observed, None, None), Step::Select { option: 2, drop: None });
+        assert_eq!(next_step(&list, &observed, Some(2), Some(&database)), Step::Wait);
+    }
+
+    #[test]
+    fn test_next_step_upgrades_held_option() {
+        let list = waitlist(true);
+        let database = course("CS307", "数据库");
+        let mut observed = HashMap::new();
+        observed.insert("A".to_owned(), observe(30, 29, None));
+        observed.insert("C".to_owned(), observe(30, 30, None));
+        assert_eq!(next_step(&list, &observed, Some(2), Some(&database)), Step::Select { option: 0, drop: Some(2) });
+        assert_eq!(next_step(&list, &observed, Some(0), Some(&database)), Step::Satisfied);
+
+        observed.insert("A".to_owned(), observe(30, 29, Some("数据库[1班]")));
+        assert_eq!(next_step(&list, &observed, Some(2), Some(&database)), Step::Swap { option: 0, fallback: 2 });
+        assert_eq!(next_step(&waitlist(false), &observed, Some(2), Some(&database)), Step::Wait);
+
+        observed.insert("A".to_owned(), observe(30, 29, Some("与[CS307]数据库系统[1班]冲突")));
+        assert_eq!(next_step(&list, &observed, Some(2), Some(&database)), Step::Swap { option: 0, fallback: 2 });
+        // Another course whose name contains the held one, or a second clash.
+        observed.insert("A".to_owned(), observe(30, 29, Some("与[CS308]数据库原理[1班]冲突")));
+        assert_eq!(next_step(&list, &observed, Some(2), Some(&database)), Step::Wait);
+        observed.insert("A".to_owned(), observe(30, 29, Some("数据库原理[1班]")));
+        assert_eq!(next_step(&list, &observed, Some(2), Some(&database)), Step::Wait);
+        observed.insert("A".to_owned(), observe(30, 29, Some("与[CS307]数据库[1班]、[MA101]高等数学[2班]冲突")));
+        assert_eq!(next_step(&list, &observed, Some(2), Some(&database)), Step::Wait);
+    }
+
+    /// A TIS with a single course type, which records every request.
+    struct FakeTis {
+        error: Mutex<Option<FetchError>>,
+        /// The class id, course code and course name of the selected classes.
+        selected: Mutex<Vec<(String, String, String)>>,
+        /// The class id, course code, course name, selected seats and clashes
+        /// of the available classes.
+        available: Vec<(String, String, String, u32, String)>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl FakeTis {
+        fn new(selected: &[(&str, &str, &str)], available: &[(&str, &str, &str, u32, &str)]) -> Self {
+            FakeTis {
+                error: Mutex::new(None),
+                selected: Mutex::new(selected.iter().map(|(a, b, c)| (a.to_string(), b.to_string(), c.to_string())).collect()),
+                available: available.iter()
+                                .map(|(a, b, c, d, e)| (a.to_string(), b.to_string(), c.to_string(), *d, e.to_string()))
+                                .collect(),
+                calls: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn call(&self, call: String) -> Result<(), FetchError> {
+            self.calls.lock().unwrap().push(call);
+            match self.error.lock().unwrap().clone() {
+                Some(error) => Err(error),
+                None => Ok(())
+            }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl Upstream for FakeTis {
+        fn current_semester<'a>(
+            &'a self,
+            _client: &'a reqwest::Client,
+        ) -> BoxFuture<'a, Result<CurrentSemester, FetchError>> {
+            Box::pin(async move {
+                self.call("semester".to_owned())?;
+                Ok(CurrentSemester { semester_year: "2020-2021".to_owned(), semester_no: "2".to_owned() })
+            })
+        }
+
+        fn selected_courses<'a>(
+            &'a self,
+            _client: &'a reqwest::Client,
+            _list: &'a Waitlist,
+        ) -> BoxFuture<'a, Result<Vec<SelectedCourse>, FetchError>> {
+            Box::pin(async move {
+                self.call("selected".to_owned())?;
+                Ok(self.selected.lock().unwrap().iter().map(|(class_id, course_id, course_name)| SelectedCourse {
+                    advanced_course: advanced_course(course_id, course_name, "1班", class_id, &[]),
+                    available: true,
+                    points: None,
+                }).collect())
+            })
+        }
+
+        fn available_courses<'a>(
+            &'a self,
+            _client: &'a reqwest::Client,
+            _list: &'a Waitlist,
+            _course_type: &'a str,
+        ) -> BoxFuture<'a, Result<Vec<AvailableCourse>, FetchError>> {
+            Box::pin(async move {
+                self.call("available".to_owned())?;
+                Ok(self.available.iter().map(|(class_id, course_id, course_name, selected, ctkcxx)| AvailableCourse {
+                    advanced_course: AdvancedCourse {
+                        undergraduated_available: 30,
+                        undergraduated_selected: *selected,
+                        ..advanced_course(course_id, course_name, "1班", class_id, &[])
+                    },
+                    outline_id: String::new(),
+                    conflict_courses: Some(ctkcxx.clone()),
+                    conflicts: parse_conflict_courses(ctkcxx),
+                }).collect())
+            })
+        }
+
+        fn select_course<'a>(
+            &'a self,
+            _client: &'a reqwest::Client,
+            _list: &'a Waitlist,
+            option: &'a WaitlistOption,
+        ) -> BoxFuture<'a, Result<serde_json::Value, FetchError>> {
+            Box::pin(async move {
+                self.call(format!("select {}", option.class_id))?;
+                let (_, course_id, course_name, _, _) = self.available.iter().find(|a| a.0 == option.class_id).unwrap();
+                self.selected.lock().unwrap().push((option.class_id.clone(), course_id.clone(), course_name.clone()));
+                Ok(serde_json::json!({ "jg": "1", "message": "选课成功" }))
+            })
+        }
+
+        fn drop_course<'a>(
+            &'a self,
+            _client: &'a reqwest::Client,
+            _list: &'a Waitlist,
+            option: &'a WaitlistOption,
+        ) -> BoxFuture<'a, Result<serde_json::Value, FetchError>> {
+            Box::pin(async move {
+                self.call(format!("drop {}", option.class_id))?;
+                self.selected.lock().unwrap().retain(|s| s.0 != option.class_id);
+                Ok(serde_json::json!({ "jg": "1", "message": "退课成功" }))
+            })
+        }
+    }
+
+    async fn waitlists(tis: &Arc<FakeTis>) -> (Waitlists, Waitlist) {
+        let throttle = Arc::new(Throttle::new(&ThrottleConfig { min_interval_ms: 0 }));
+        let waitlists = Waitlists::with_upstream(&WaitlistConfig::default(), throttle, tis.clone());
+        let current = CurrentSemester { semester_year: "2020-2021".to_owned(), semester_no: "2".to_owned() };
+        let list = waitlist(true);
+        let request = WaitlistRequest {
+            semester_year: list.semester_year.clone(),
+            semester_no: list.semester_no.clone(),
+            options: list.options.clone(),
+            drop_fallback: true,
+        };
+        let list = waitlists.add("student", reqwest::Client::new(), &current, &request).await.unwrap();
+        (waitlists, list)
+    }
+
+    #[tokio::test]
+    async fn test_tick_expires_only_on_session_loss() {
+        let tis = Arc::new(FakeTis::new(&[], &[]));
+        let (waitlists, _) = waitlists(&tis).await;
+
+        *tis.error.lock().unwrap() = Some(FetchError::Network("timed out".to_owned()));
+        waitlists.tick().await;
+        assert_eq!(waitlists.lists("student").await[0].status, WaitlistStatus::Waiting);
+
+        *tis.error.lock().unwrap() = Some(FetchError::SessionExpired);
+        waitlists.tick().await;
+        assert_eq!(waitlists.lists("student").await[0].status, WaitlistStatus::SessionExpired);
+        assert_eq!(waitlists.audit("student").await.last().unwrap().action, AuditAction::SessionExpired);
+    }
+
+    #[tokio::test]
+    async fn test_tick_swaps_by_course_code() {
+        let tis = Arc::new(FakeTis::new(
+            &[("C", "CS307", "数据库")],
+            &[
+                ("A", "CS308", "数据库原理", 10, "与[CS308]数据库原理[1班]冲突"),
+                ("B", "CS309", "软件工程", 10, "与[CS307]数据库[1班]冲突"),
+                ("C", "CS307", "数据库", 30, ""),
+            ],
+        ));
+        let (waitlists, _) = waitlists(&tis).await;
+        waitlists.tick().await;
+        assert_eq!(tis.calls(), vec!["semester", "selected", "available", "drop C", "select B"]);
+        let list = &waitlists.lists("student").await[0];
+        assert_eq!(list.status, WaitlistStatus::Holding);
+        assert_eq!(list.held.as_deref(), Some("B"));
+    }
+
+    #[tokio::test]
+    async fn test_run_list_stops_once_cancelled() {
+        let tis = Arc::new(FakeTis::new(&[], &[("A", "CS101", "计算机导论", 10, "")]));
+        let (waitlists, list) = waitlists(&tis).await;
+        waitlists.cancel("student", list.id).await;
+        // A round which copied the list before it was cancelled.
+        waitlists.run_list("student", &reqwest::Client::new(), &list).await.unwrap();
+        assert!(tis.calls().is_empty());
+        assert_eq!(waitlists.lists("student").await[0].status, WaitlistStatus::Cancelled);
+    }
+}
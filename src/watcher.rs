@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::lock::Mutex;
+use rocket::serde::Deserialize;
+use rocket::tokio::{self, sync::broadcast};
+
+use super::bidding::Snapshots;
+use super::courses::{fetch_available_courses, FetchError};
+use super::structures::*;
+use super::throttle::Throttle;
+
+/// Polling faster than this is never allowed, whatever the config says.
+const MIN_POLL_INTERVAL: u64 = 15;
+/// A user whose requests keep failing is polled at most this rarely.
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WatcherConfig {
+    /// Seconds between two polling rounds.
+    pub interval: u64,
+    /// Hosts webhooks may be posted to. When empty any host is allowed as
+    /// long as it resolves to public addresses only.
+    pub webhook_hosts: Vec<String>,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        WatcherConfig { interval: 60, webhook_hosts: Vec::new() }
+    }
+}
+
+struct WatchedUser {
+    client: reqwest::Client,
+    targets: Vec<WatchTarget>,
+    /// Failed polls in a row other than an expired session, and when the
+    /// user is polled again.
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+/// Whether an address can be reached from the internet, so that webhooks
+/// cannot be used to reach the server itself or its private network.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+                || ip.is_broadcast() || ip.is_documentation() || ip.is_multicast()
+                // Shared address space, and the reserved 240.0.0.0/4.
+                || (a == 100 && (64..128).contains(&b)) || a >= 240 || a == 0)
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+                    // Unique local and link local addresses.
+                    || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80)
+            }
+        }
+    }
+}
+
+/// Check that a webhook is an http(s) URL of an allowed host, and resolve
+/// it to the public address it is posted to.
+pub async fn check_webhook(webhook: &str, allowed_hosts: &[String]) -> Result<(reqwest::Url, SocketAddr), String> {
+    let url = reqwest::Url::parse(webhook).map_err(|e| format!("Invalid webhook {}: {}", webhook, e))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("The webhook {} is not an http or https URL", webhook));
+    }
+    let host = url.host_str().ok_or_else(|| format!("The webhook {} has no host", webhook))?.to_owned();
+    if !allowed_hosts.is_empty() && !allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(&host)) {
+        return Err(format!("Webhooks to {} are not allowed", host));
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_matches(|c| c == '[' || c == ']'), port))
+                                        .await
+                                        .map_err(|e| format!("Unable to resolve the webhook host {}: {}", host, e))?
+                                        .collect();
+    if addresses.is_empty() || addresses.iter().any(|address| !is_public(address.ip())) {
+        return Err(format!("The webhook host {} is not a public address", host));
+    }
+    Ok((url, addresses[0]))
+}
+
+struct WatcherInner {
+    interval: Duration,
+    throttle: Arc<Throttle>,
+    snapshots: Snapshots,
+    users: Mutex<HashMap<String, WatchedUser>>,
+    events: broadcast::Sender<SeatEvent>,
+    webhook_hosts: Vec<String>,
+    running: AtomicBool,
+}
+
+/// Background subsystem polling the capacity of the classes users registered
+/// and notifying them when a class changes between full and open.
+///
+/// The watcher only keeps the logged in client of a user, never the
+/// password. When the TIS session expires the user is notified and has to
+/// register the classes again.
+#[derive(Clone)]
+pub struct Watcher {
+    inner: Arc<WatcherInner>,
+}
+
+/// The event a class moving from `previous` to `current` should fire.
+pub fn transition(
+    previous: Option<Capacity>,
+    current: Capacity
+) -> Option<SeatEventKind> {
+    match previous {
+        Some(previous) if previous.is_full() && !current.is_full() => Some(SeatEventKind::Opened),
+        Some(previous) if !previous.is_full() && current.is_full() => Some(SeatEventKind::Filled),
+        _ => None
+    }
+}
+
+fn now() -> String {
+    chrono::Local::now().to_rfc3339()
+}
+
+impl Watcher {
+    pub fn new(config: &WatcherConfig, throttle: Arc<Throttle>, snapshots: Snapshots) -> Self {
+        let (events, _) = broadcast::channel(64);
+        Watcher {
+            inner: Arc::new(WatcherInner {
+                interval: Duration::from_secs(config.interval.max(MIN_POLL_INTERVAL)),
+                throttle,
+                snapshots,
+                users: Mutex::new(HashMap::new()),
+                events,
+                webhook_hosts: config.webhook_hosts.clone(),
+                running: AtomicBool::new(false),
+            })
+        }
+    }
+
+    /// Spawn the polling loop, at most once.
+    pub fn start(&self) {
+        if self.inner.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let watcher = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(watcher.inner.interval).await;
+                // A round which panics must not stop the watcher for everyone.
+                let round = watcher.clone();
+                if let Err(_e) = tokio::spawn(async move { round.poll().await }).await {
+                    #[cfg(debug_assertions)]
+                    println!("Seat watcher: a polling round failed: {}", _e);
+                }
+            }
+        });
+    }
+
+    /// Check a webhook before it is watched with.
+    pub async fn check_webhook(&self, webhook: &str) -> Result<(), String> {
+        check_webhook(webhook, &self.inner.webhook_hosts).await.map(|_| ())
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SeatEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Register a class for a user, replacing the client of the user with
+    /// the freshly logged in one.
+    pub async fn watch(
+        &self,
+        username: &str,
+        client: reqwest::Client,
+        target: WatchTarget
+    ) -> Vec<WatchTarget> {
+        let mut users = self.inner.users.lock().await;
+        let user = users.entry(username.to_owned())
+                        .or_insert_with(|| WatchedUser { client: client.clone(), targets: Vec::new(), failures: 0, retry_at: None });
+        user.client = client;
+        user.failures = 0;
+        user.retry_at = None;
+        user.targets.retain(|t| t.class_id != target.class_id);
+        user.targets.push(target);
+        user.targets.clone()
+    }
+
+    pub async fn unwatch(
+        &self,
+        username: &str,
+        class_id: &str
+    ) -> Vec<WatchTarget> {
+        let mut users = self.inner.users.lock().await;
+        let mut targets = Vec::new();
+        if let Some(user) = users.get_mut(username) {
+            user.targets.retain(|t| t.class_id != class_id);
+            targets = user.targets.clone();
+        }
+        if targets.is_empty() {
+            users.remove(username);
+        }
+        targets
+    }
+
+    pub async fn watched(
+        &self,
+        username: &str
+    ) -> Vec<WatchTarget> {
+        let users = self.inner.users.lock().await;
+        users.get(username)
+                .map(|user| user.targets.clone())
+                .unwrap_or_default()
+    }
+
+    /// One polling round. Every (user, semester, courses type) group costs a
+    /// single request to TIS, whatever the number of classes in it.
+    async fn poll(&self) {
+        let mut groups = Vec::<(String, reqwest::Client, String, String, String)>::new();
+        {
+            let users = self.inner.users.lock().await;
+            let now = Instant::now();
+            for (username, user) in users.iter().filter(|(_, user)| user.retry_at.is_none_or(|at| at <= now)) {
+                for target in &user.targets {
+                    let exists = groups.iter().any(|(u, _, y, n, t)| {
+                        u == username && *y == target.semester_year && *n == target.semester_no && *t == target.courses_type
+                    });
+                    if !exists {
+                        groups.push((
+                            username.clone(),
+                            user.client.clone(),
+                            target.semester_year.clone(),
+                            target.semester_no.clone(),
+                            target.courses_type.clone()
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (username, client, semester_year, semester_no, courses_type) in groups {
+            if !self.inner.users.lock().await.contains_key(&username) {
+                continue;
+            }
+            self.inner.throttle.wait().await;
+            match fetch_available_courses(&client, &semester_year, &semester_no, &courses_type).await {
+                Ok(courses) => {
+                    if let Some(user) = self.inner.users.lock().await.get_mut(&username) {
+                        user.failures = 0;
+                        user.retry_at = None;
+                    }
+                    self.inner.snapshots.record(&semester_year, &semester_no, &courses).await;
+                    let events = self.update(&username, &semester_year, &semester_no, &courses_type, &courses).await;
+                    for (event, webhook) in events {
+                        self.notify(event, webhook).await;
+                    }
+                },
+                Err(FetchError::Network(_e)) | Err(FetchError::Parse(_e)) => {
+                    // Try again later, waiting twice as long after each failure.
+                    if let Some(user) = self.inner.users.lock().await.get_mut(&username) {
+                        user.failures = user.failures.saturating_add(1);
+                        let backoff = self.inner.interval.saturating_mul(1 << user.failures.min(8)).min(MAX_BACKOFF);
+                        user.retry_at = Some(Instant::now() + backoff);
+                        #[cfg(debug_assertions)]
+                        println!("Seat watcher: polling for {} failed, retrying in {:?}: {}", username, backoff, _e);
+                    }
+                },
+                Err(FetchError::SessionExpired) => {
+                    #[cfg(debug_assertions)]
+                    println!("Seat watcher: the session of {} expired", username);
+                    let user = self.inner.users.lock().await.remove(&username);
+                    let mut webhooks = Vec::<String>::new();
+                    for target in user.map(|u| u.targets).unwrap_or_default() {
+                        if let Some(webhook) = target.webhook {
+                            if !webhooks.contains(&webhook) {
+                                webhooks.push(webhook);
+                            }
+                        }
+                    }
+                    let event = SeatEvent {
+                        username: username.clone(),
+                        kind: SeatEventKind::SessionExpired,
+                        class_id: None,
+                        course_name: None,
+                        capacity: None,
+                        time: now(),
+                    };
+                    let _ = self.inner.events.send(event.clone());
+                    for webhook in webhooks {
+                        self.post_webhook(&webhook, &event).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn update(
+        &self,
+        username: &str,
+        semester_year: &str,
+        semester_no: &str,
+        courses_type: &str,
+        courses: &[AvailableCourse]
+    ) -> Vec<(SeatEvent, Option<String>)> {
+        let mut events = Vec::new();
+        let mut users = self.inner.users.lock().await;
+        let user = match users.get_mut(username) {
+            Some(user) => user,
+            None => return events
+        };
+        let targets = user.targets
+                        .iter_mut()
+                        .filter(|t| t.semester_year == semester_year && t.semester_no == semester_no && t.courses_type == courses_type);
+        for target in targets {
+            let course = match courses.iter().find(|c| c.advanced_course.id == target.class_id) {
+                Some(course) => &course.advanced_course,
+                None => continue
+            };
+            let capacity = Capacity::of(course);
+            target.course_name = Some(format!("{}[{}]", course.basic_course.course_name, course.course_class));
+            if let Some(kind) = transition(target.capacity, capacity) {
+                events.push((SeatEvent {
+                    username: username.to_owned(),
+                    kind,
+                    class_id: Some(target.class_id.clone()),
+                    course_name: target.course_name.clone(),
+                    capacity: Some(capacity),
+                    time: now(),
+                }, target.webhook.clone()));
+            }
+            target.capacity = Some(capacity);
+        }
+        events
+    }
+
+    async fn notify(
+        &self,
+        event: SeatEvent,
+        webhook: Option<String>
+    ) {
+        // Nobody listening on the stream is not an error.
+        let _ = self.inner.events.send(event.clone());
+        if let Some(webhook) = webhook {
+            self.post_webhook(&webhook, &event).await;
+        }
+    }
+
+    async fn post_webhook(
+        &self,
+        webhook: &str,
+        event: &SeatEvent
+    ) {
+        // Checked again when posting, as the host may resolve elsewhere by
+        // now, and pinned to the address checked. Redirects are not
+        // followed, they could lead anywhere.
+        let result = match check_webhook(webhook, &self.inner.webhook_hosts).await {
+            Ok((url, address)) => {
+                let client = reqwest::Client::builder()
+                                .redirect(reqwest::redirect::Policy::none())
+                                .resolve(url.host_str().unwrap_or_default(), address)
+                                .timeout(Duration::from_secs(10))
+                                .build()
+                                .map_err(|e| e.to_string());
+                match client {
+                    Ok(client) => client.post(url).json(event).send().await.map(|_| ()).map_err(|e| e.to_string()),
+                    Err(e) => Err(e)
+                }
+            },
+            Err(e) => Err(e)
+        };
+        if let Err(_e) = result {
+            #[cfg(debug_assertions)]
+            println!("Seat watcher: unable to call the webhook {}: {}", webhook, _e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use rocket::tokio;
+
+    use super::super::structures::{Capacity, SeatEventKind};
+
+    #[test]
+    fn test_transition() {
+        let full = Capacity { available: 30, selected: 30 };
+        let open = Capacity { available: 30, selected: 29 };
+        assert_eq!(super::transition(None, full), None);
+        assert_eq!(super::transition(Some(full), open), Some(SeatEventKind::Opened));
+        assert_eq!(super::transition(Some(open), full), Some(SeatEventKind::Filled));
+        assert_eq!(super::transition(Some(open), open), None);
+        assert_eq!(super::transition(Some(full), Capacity { available: 35, selected: 30 }), Some(SeatEventKind::Opened));
+    }
+
+    #[test]
+    fn test_is_public() {
+        let public = |ip: &str| super::is_public(ip.parse::<IpAddr>().unwrap());
+        assert!(public("1.1.1.1"));
+        assert!(public("2606:4700:4700::1111"));
+        for ip in ["127.0.0.1", "10.0.0.1", "172.16.5.4", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!public(ip), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_webhook() {
+        assert!(super::check_webhook("ftp://example.com/hook", &[]).await.is_err());
+        assert!(super::check_webhook("http://127.0.0.1:8000/hook", &[]).await.is_err());
+        assert!(super::check_webhook("http://[::1]/hook", &[]).await.is_err());
+        assert!(super::check_webhook("https://example.com/hook", &["hooks.example.org".to_owned()]).await.is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::lock::Mutex;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::response::status::Unauthorized;
+use rocket::serde::Deserialize;
+
+use super::calendar::Calendars;
+use super::ical::{timetable_calendar, TimetableConfig};
+use super::login::refresh_tis_session;
+use super::structures::*;
+use super::throttle::Throttle;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WebcalConfig {
+    /// Seconds a calendar is served from the cache before TIS is asked again.
+    pub refresh_interval: u64,
+}
+
+impl Default for WebcalConfig {
+    fn default() -> Self {
+        WebcalConfig { refresh_interval: 900 }
+    }
+}
+
+struct CachedCalendar {
+    etag: String,
+    ics: String,
+    fetched_at: Instant,
+}
+
+struct Subscription {
+    username: String,
+    client: reqwest::Client,
+    /// The current semester at each refresh if not set.
+    semester: Option<CurrentSemester>,
+    cached: Option<Arc<CachedCalendar>>,
+}
+
+struct SubscriptionsInner {
+    refresh_interval: Duration,
+    throttle: Arc<Throttle>,
+    subscriptions: Mutex<HashMap<String, Subscription>>,
+}
+
+/// Timetables served on secret URLs, so that calendar apps can subscribe to
+/// them and follow the changes of the selected courses.
+///
+/// Like the seat watcher, a subscription only keeps the logged in client of
+/// its user. When the TIS session expires it is opened again with the CAS
+/// session, and once that one expires too the user has to subscribe again.
+#[derive(Clone)]
+pub struct Subscriptions {
+    inner: Arc<SubscriptionsInner>,
+}
+
+/// The `If-None-Match` header of a request, if any.
+pub struct IfNoneMatch(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(request.headers().get_one("If-None-Match").map(|v| v.to_owned())))
+    }
+}
+
+impl IfNoneMatch {
+    pub fn matches(&self, etag: &str) -> bool {
+        match &self.0 {
+            Some(header) => header.split(',')
+                                .map(|tag| tag.trim().trim_start_matches("W/"))
+                                .any(|tag| tag == "*" || tag == etag),
+            None => false
+        }
+    }
+}
+
+/// A calendar with its ETag, or only the ETag when the client already has it.
+pub struct WebCalendar {
+    etag: String,
+    ics: Option<String>,
+}
+
+impl<'r> Responder<'r, 'static> for WebCalendar {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.raw_header("ETag", self.etag)
+                .raw_header("Cache-Control", "no-cache");
+        match self.ics {
+            Some(ics) => response.header(ContentType::Calendar).sized_body(ics.len(), Cursor::new(ics)),
+            None => response.status(Status::NotModified)
+        };
+        response.ok()
+    }
+}
+
+/// A strong ETag of the calendar. The DTSTAMP lines change at each
+/// rendering, so they are left out.
+fn etag(ics: &str) -> String {
+    let stable: String = ics.split("\r\n")
+                            .filter(|line| !line.starts_with("DTSTAMP:"))
+                            .collect();
+    let hash = digest::digest(&digest::SHA256, stable.as_bytes());
+    format!("\"{}\"", data_encoding::HEXLOWER.encode(&hash.as_ref()[..16]))
+}
+
+fn new_token() -> Option<String> {
+    let mut token = [0u8; 24];
+    SystemRandom::new().fill(&mut token).ok()?;
+    Some(data_encoding::HEXLOWER.encode(&token))
+}
+
+impl Subscriptions {
+    pub fn new(config: &WebcalConfig, throttle: Arc<Throttle>) -> Self {
+        Subscriptions {
+            inner: Arc::new(SubscriptionsInner {
+                refresh_interval: Duration::from_secs(config.refresh_interval),
+                throttle,
+                subscriptions: Mutex::new(HashMap::new()),
+            })
+        }
+    }
+
+    /// Create the secret token of a user, revoking the previous one.
+    pub async fn subscribe(
+        &self,
+        username: &str,
+        client: reqwest::Client,
+        semester: Option<CurrentSemester>,
+    ) -> Option<String> {
+        let token = new_token()?;
+        let mut subscriptions = self.inner.subscriptions.lock().await;
+        subscriptions.retain(|_, s| s.username != username);
+        subscriptions.insert(token.clone(), Subscription {
+            username: username.to_owned(),
+            client,
+            semester,
+            cached: None,
+        });
+        Some(token)
+    }
+
+    pub async fn revoke(
+        &self,
+        username: &str,
+    ) -> bool {
+        let mut subscriptions = self.inner.subscriptions.lock().await;
+        let count = subscriptions.len();
+        subscriptions.retain(|_, s| s.username != username);
+        subscriptions.len() != count
+    }
+
+    /// The calendar of a token, from the cache while it is fresh.
+    pub async fn calendar(
+        &self,
+        token: &str,
+        if_none_match: &IfNoneMatch,
+        timetable: &TimetableConfig,
+        calendars: &Calendars,
+    ) -> Result<WebCalendar, Unauthorized<String>> {
+        let unknown = || Unauthorized(Some("Unknown or revoked calendar subscription".to_owned()));
+        let (client, semester, cached) = match self.inner.subscriptions.lock().await.get(token) {
+            Some(s) => (s.client.clone(), s.semester.clone(), s.cached.clone()),
+            None => return Err(unknown())
+        };
+
+        let cached = match cached {
+            Some(cached) if cached.fetched_at.elapsed() < self.inner.refresh_interval => cached,
+            _ => match self.fetch(&client, semester, timetable, calendars).await {
+                Ok(ics) => {
+                    let etag = etag(&ics);
+                    let fresh = match cached {
+                        // Keep the old DTSTAMP when nothing changed.
+                        Some(cached) if cached.etag == etag => CachedCalendar { etag, ics: cached.ics.clone(), fetched_at: Instant::now() },
+                        _ => CachedCalendar { etag, ics, fetched_at: Instant::now() },
+                    };
+                    let fresh = Arc::new(fresh);
+                    let mut subscriptions = self.inner.subscriptions.lock().await;
+                    subscriptions.get_mut(token).ok_or_else(unknown)?.cached = Some(fresh.clone());
+                    fresh
+                },
+                // A stale calendar is better than none for a calendar app.
+                Err(_) if cached.is_some() => cached.unwrap(),
+                Err(e) => return Err(e)
+            }
+        };
+
+        Ok(WebCalendar {
+            etag: cached.etag.clone(),
+            ics: if if_none_match.matches(&cached.etag) { None } else { Some(cached.ics.clone()) },
+        })
+    }
+
+    async fn fetch(
+        &self,
+        client: &reqwest::Client,
+        semester: Option<CurrentSemester>,
+        timetable: &TimetableConfig,
+        calendars: &Calendars,
+    ) -> Result<String, Unauthorized<String>> {
+        self.inner.throttle.wait().await;
+        if let Ok(ics) = timetable_calendar(client, semester.clone(), None, timetable, calendars).await {
+            return Ok(ics);
+        }
+        // The TIS session has most likely expired.
+        self.inner.throttle.wait().await;
+        if !refresh_tis_session(client).await? {
+            return Err(Unauthorized(Some("The CAS session has expired, subscribe again".to_owned())));
+        }
+        timetable_calendar(client, semester, None, timetable, calendars).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{etag, IfNoneMatch};
+
+    #[test]
+    fn test_etag_ignores_dtstamp() {
+        let a = etag("BEGIN:VEVENT\r\nDTSTAMP:20210901T000000Z\r\nSUMMARY:A\r\n");
+        let b = etag("BEGIN:VEVENT\r\nDTSTAMP:20210902T000000Z\r\nSUMMARY:A\r\n");
+        let c = etag("BEGIN:VEVENT\r\nDTSTAMP:20210902T000000Z\r\nSUMMARY:B\r\n");
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(IfNoneMatch(Some(format!("W/{}, \"other\"", a))).matches(&a));
+        assert!(!IfNoneMatch(Some("\"other\"".to_owned())).matches(&a));
+        assert!(!IfNoneMatch(None).matches(&a));
+    }
+}